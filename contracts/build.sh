@@ -5,10 +5,22 @@ pushd token
 ./build.sh
 popd
 
+pushd taxable-token
+./build.sh
+popd
+
+pushd namespace
+./build.sh
+popd
+
 pushd factory
 ./build.sh
 popd
 
+pushd test-caller
+./build.sh
+popd
+
 
 
 
@@ -0,0 +1,212 @@
+//! Calls to the factory over RPC with near-api, and the NEAR amounts the commands take and print.
+use base64::Engine;
+use near_api::{AccountId, Contract, NearGas, NearToken, NetworkConfig, RPCEndpoint, Signer};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Decimals of NEAR amounts, which are in yoctoNEAR on chain.
+const NEAR_DECIMALS: u8 = 24;
+
+/// Returns the config of a known network, or of the network at `rpc_url`, which also overrides
+/// the RPC of a known one.
+pub fn network_config(network: &str, rpc_url: Option<&str>) -> Result<NetworkConfig> {
+    let config = match network {
+        "mainnet" => NetworkConfig::mainnet(),
+        "testnet" => NetworkConfig::testnet(),
+        _ if rpc_url.is_none() => {
+            return Err(format!("Network {network} needs an --rpc-url").into())
+        }
+        _ => NetworkConfig::testnet(),
+    };
+    Ok(match rpc_url {
+        Some(rpc_url) => NetworkConfig {
+            network_name: network.to_string(),
+            rpc_endpoints: vec![RPCEndpoint::new(rpc_url.parse()?)],
+            ..config
+        },
+        None => config,
+    })
+}
+
+/// Path of the near-cli credentials of the account, `~/.near-credentials/<network>/<account>.json`.
+pub fn default_credentials_path(network: &str, account_id: &AccountId) -> Result<PathBuf> {
+    let home = std::env::var_os("HOME").ok_or("HOME isn't set, pass --credentials")?;
+    Ok(PathBuf::from(home)
+        .join(".near-credentials")
+        .join(network)
+        .join(format!("{account_id}.json")))
+}
+
+/// Loads the key of a near-cli credentials file.
+pub fn signer_from_credentials(path: &Path) -> Result<Arc<Signer>> {
+    Signer::from_access_keyfile(path.to_path_buf())
+        .map_err(|err| format!("Can't load the key of {}: {}", path.display(), err).into())
+}
+
+/// The factory contract, called by the signer if one is configured.
+pub struct Factory {
+    pub account_id: AccountId,
+    network: NetworkConfig,
+    signer: Option<(AccountId, Arc<Signer>)>,
+}
+
+impl Factory {
+    pub fn new(
+        account_id: AccountId,
+        network: NetworkConfig,
+        signer: Option<(AccountId, Arc<Signer>)>,
+    ) -> Self {
+        Self {
+            account_id,
+            network,
+            signer,
+        }
+    }
+
+    pub fn signer_id(&self) -> Result<&AccountId> {
+        self.signer
+            .as_ref()
+            .map(|(signer_id, _)| signer_id)
+            .ok_or_else(|| "The command needs a --signer-id".into())
+    }
+
+    pub async fn view<T>(&self, method: &str, args: impl Serialize) -> Result<T>
+    where
+        T: DeserializeOwned + Send + Sync,
+    {
+        let data = Contract(self.account_id.clone())
+            .call_function(method, args)
+            .read_only::<T>()
+            .fetch_from(&self.network)
+            .await?;
+        Ok(data.data)
+    }
+
+    /// Calls the method as the signer and returns its JSON result once the transaction and its
+    /// receipts are final.
+    pub async fn call<T>(
+        &self,
+        method: &str,
+        args: impl Serialize,
+        deposit: u128,
+        tgas: u64,
+    ) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let (signer_id, signer) = self
+            .signer
+            .as_ref()
+            .ok_or("The command needs a --signer-id")?;
+        let result = Contract(self.account_id.clone())
+            .call_function(method, args)
+            .transaction()
+            .deposit(NearToken::from_yoctonear(deposit))
+            .gas(NearGas::from_tgas(tgas))
+            .with_signer(signer_id.clone(), signer.clone())
+            .send_to(&self.network)
+            .await?;
+        Ok(result.into_result()?.json()?)
+    }
+
+    /// Returns the code deployed to the account, e.g. of a token.
+    pub async fn code_of(&self, account_id: &AccountId) -> Result<Vec<u8>> {
+        let code = Contract(account_id.clone())
+            .wasm()
+            .fetch_from(&self.network)
+            .await?;
+        Ok(base64::engine::general_purpose::STANDARD.decode(code.data.code_base64)?)
+    }
+}
+
+/// Parses an amount of NEAR such as `2.5` into yoctoNEAR.
+pub fn parse_near(amount: &str) -> std::result::Result<u128, String> {
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    let invalid = || format!("Invalid NEAR amount {amount}");
+    if (whole.is_empty() && fraction.is_empty())
+        || !whole
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|c| c.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+    if fraction.len() > usize::from(NEAR_DECIMALS) {
+        return Err(format!(
+            "NEAR amounts can't have more than {NEAR_DECIMALS} decimals"
+        ));
+    }
+    format!(
+        "{}{:0<width$}",
+        whole,
+        fraction,
+        width = usize::from(NEAR_DECIMALS)
+    )
+    .parse()
+    .map_err(|_| invalid())
+}
+
+pub fn format_near(amount: u128) -> String {
+    format!("{} NEAR", format_amount(amount, NEAR_DECIMALS))
+}
+
+/// Formats a raw amount as a decimal string without trailing zeros in the fraction, like the
+/// factory's `get_human_supply`.
+pub fn format_amount(amount: u128, decimals: u8) -> String {
+    let digits = amount.to_string();
+    let decimals = usize::from(decimals);
+    if decimals == 0 {
+        return digits;
+    }
+    let digits = format!("{:0>width$}", digits, width = decimals + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - decimals);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        whole.to_string()
+    } else {
+        format!("{whole}.{fraction}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONE_NEAR: u128 = 10u128.pow(24);
+
+    #[test]
+    fn test_parse_near() {
+        assert_eq!(parse_near("2"), Ok(2 * ONE_NEAR));
+        assert_eq!(parse_near("2.5"), Ok(5 * ONE_NEAR / 2));
+        assert_eq!(parse_near(".1"), Ok(ONE_NEAR / 10));
+        assert_eq!(parse_near("0.000000000000000000000001"), Ok(1));
+        assert!(parse_near("0.0000000000000000000000001").is_err());
+        for amount in &["", ".", "-1", "+1", "1.2.3", "1 NEAR", "1e24"] {
+            assert!(parse_near(amount).is_err(), "{}", amount);
+        }
+        assert!(parse_near(&u128::MAX.to_string()).is_err());
+    }
+
+    #[test]
+    fn test_format_near() {
+        assert_eq!(format_near(0), "0 NEAR");
+        assert_eq!(format_near(5 * ONE_NEAR / 2), "2.5 NEAR");
+        assert_eq!(format_near(1), "0.000000000000000000000001 NEAR");
+        for amount in &[1, ONE_NEAR - 1, 123 * ONE_NEAR + 456] {
+            let formatted = format_near(*amount);
+            assert_eq!(parse_near(formatted.trim_end_matches(" NEAR")), Ok(*amount));
+        }
+    }
+
+    #[test]
+    fn test_format_amount() {
+        assert_eq!(format_amount(1_500_000, 6), "1.5");
+        assert_eq!(format_amount(1_000_000, 6), "1");
+        assert_eq!(format_amount(5, 3), "0.005");
+        assert_eq!(format_amount(42, 0), "42");
+    }
+}
@@ -0,0 +1,684 @@
+//! Command line client of the token factory for operators and power users. It runs the common
+//! flows against the factory on any network: quoting the deposit of a token, paying it into the
+//! storage balance, creating the token, listing the registry and verifying a token's code.
+mod client;
+mod views;
+
+use crate::client::{format_amount, format_near, Factory, Result};
+use crate::views::*;
+use clap::{Args, Parser, Subcommand};
+use factory_types::{
+    errors, SymbolValidation, MAX_BPS, MAX_LOCALIZATIONS, MAX_PAGE_SIZE, MAX_TAGS, MAX_TAG_LENGTH,
+    MAX_TRANSFER_FEE_BPS, MAX_WHITELIST_ACCOUNTS,
+};
+use near_api::AccountId;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+
+/// Gas of the calls that only update the factory's state.
+const CALL_TGAS: u64 = 30;
+/// Gas of `create_token`, which attaches most of it to the deployment promises.
+const CREATE_TOKEN_TGAS: u64 = 300;
+
+#[derive(Parser)]
+#[command(name = "factory-cli", version, about)]
+struct Cli {
+    /// `mainnet`, `testnet`, or the name of another network given with `--rpc-url`.
+    #[arg(long, env = "NEAR_NETWORK", default_value = "testnet")]
+    network: String,
+    /// RPC of the network, overriding the default one of `mainnet` and `testnet`.
+    #[arg(long, env = "NEAR_RPC_URL")]
+    rpc_url: Option<String>,
+    /// Account of the factory contract.
+    #[arg(long, env = "FACTORY_ACCOUNT_ID")]
+    factory: AccountId,
+    /// Account signing the calls. Views don't need one.
+    #[arg(long, env = "NEAR_SIGNER_ID")]
+    signer_id: Option<AccountId>,
+    /// near-cli credentials file of the signer, `~/.near-credentials/<network>/<signer>.json`
+    /// by default.
+    #[arg(long)]
+    credentials: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Prints the deposit creating the token takes, or locks its price with `--lock`.
+    Quote {
+        #[command(flatten)]
+        token: TokenOptions,
+        /// Account the deposit is quoted for, the signer by default.
+        #[arg(long)]
+        account_id: Option<AccountId>,
+        /// Stores a quote with `get_quote`, which `create --quote-id` charges before it expires.
+        #[arg(long)]
+        lock: bool,
+    },
+    /// Registers the signer with the factory or tops up its storage balance.
+    Deposit(DepositOptions),
+    /// Creates the token, paid from the signer's storage balance.
+    Create {
+        #[command(flatten)]
+        token: TokenOptions,
+        /// Public key added as a full access key to the token account.
+        #[arg(long)]
+        public_key: Option<String>,
+        /// Account receiving the referral share of the creation fee.
+        #[arg(long)]
+        referrer_id: Option<AccountId>,
+        /// Quote of `quote --lock` to charge instead of the current price.
+        #[arg(long)]
+        quote_id: Option<u64>,
+        /// Priority fee in NEAR, only charged when the creation is queued.
+        #[arg(long, value_parser = client::parse_near)]
+        priority_fee: Option<u128>,
+    },
+    /// Lists the registered tokens, or the tokens of an owner.
+    List {
+        #[arg(long, default_value_t = 0)]
+        from_index: u64,
+        #[arg(long, default_value_t = MAX_PAGE_SIZE)]
+        limit: u64,
+        /// Keeps reading pages until the end of the list.
+        #[arg(long)]
+        all: bool,
+        #[arg(long)]
+        owner_id: Option<AccountId>,
+        /// Also lists the tokens frozen by the compliance role.
+        #[arg(long)]
+        include_frozen: bool,
+    },
+    /// Checks that the code of the token account is the code the factory deploys for it.
+    Verify { token_id: String },
+}
+
+#[derive(Args)]
+struct TokenOptions {
+    /// JSON file with the token args, as passed to `create_token`.
+    #[arg(long)]
+    args: PathBuf,
+    /// JSON object merged into the init args of templates that take more parameters.
+    #[arg(long)]
+    extra_init_json: Option<String>,
+    /// Project the token is launched under, as `token.project.factory`.
+    #[arg(long)]
+    project: Option<String>,
+}
+
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct DepositOptions {
+    /// Amount of NEAR to deposit.
+    #[arg(long, value_parser = client::parse_near)]
+    amount: Option<u128>,
+    /// JSON file with the args of a token, to deposit what the storage balance lacks for it.
+    #[arg(long)]
+    args: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() {
+    if let Err(err) = run(Cli::parse()).await {
+        eprintln!("Error: {err}");
+        std::process::exit(1);
+    }
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let network = client::network_config(&cli.network, cli.rpc_url.as_deref())?;
+    let signer = match cli.signer_id {
+        Some(signer_id) => {
+            let path = match cli.credentials {
+                Some(path) => path,
+                None => client::default_credentials_path(&cli.network, &signer_id)?,
+            };
+            Some((signer_id, client::signer_from_credentials(&path)?))
+        }
+        None => None,
+    };
+    let factory = Factory::new(cli.factory, network, signer);
+    match cli.command {
+        Command::Quote {
+            token,
+            account_id,
+            lock,
+        } => quote(&factory, &token, account_id, lock).await,
+        Command::Deposit(options) => deposit(&factory, &options).await,
+        Command::Create {
+            token,
+            public_key,
+            referrer_id,
+            quote_id,
+            priority_fee,
+        } => {
+            create(
+                &factory,
+                &token,
+                public_key,
+                referrer_id,
+                quote_id,
+                priority_fee,
+            )
+            .await
+        }
+        Command::List {
+            from_index,
+            limit,
+            all,
+            owner_id,
+            include_frozen,
+        } => {
+            let limit = limit.clamp(1, MAX_PAGE_SIZE);
+            match owner_id {
+                Some(owner_id) => list_by_owner(&factory, &owner_id, from_index, limit, all).await,
+                None => list(&factory, from_index, limit, all, include_frozen).await,
+            }
+        }
+        Command::Verify { token_id } => verify(&factory, &token_id).await,
+    }
+}
+
+fn read_token_args(token: &TokenOptions) -> Result<Value> {
+    let path = &token.args;
+    let args: Value = serde_json::from_slice(
+        &std::fs::read(path).map_err(|err| format!("Can't read {}: {}", path.display(), err))?,
+    )
+    .map_err(|err| format!("{} isn't valid JSON: {}", path.display(), err))?;
+    check_token_args(&args)?;
+    Ok(args)
+}
+
+/// Checks the token args against the limits of `factory-types`, with the messages the factory
+/// panics with, so a mistake doesn't cost a failed transaction.
+fn check_token_args(args: &Value) -> Result<()> {
+    if !args["metadata"]["symbol"].is_string() {
+        return Err("The token args have no metadata.symbol".into());
+    }
+    let tags = args["tags"].as_array().map_or(&[][..], Vec::as_slice);
+    if tags.len() > MAX_TAGS {
+        return Err(format!("Can't have more than {MAX_TAGS} tags").into());
+    }
+    for (index, tag) in tags.iter().enumerate() {
+        let tag = tag.as_str().ok_or("Tags have to be strings")?;
+        if tag.is_empty()
+            || tag.len() > MAX_TAG_LENGTH
+            || !tag
+                .chars()
+                .all(|c| matches!(c, '0'..='9' | 'a'..='z' | '-'))
+        {
+            return Err(format!(
+                "Tag {tag} is invalid, it has to be up to {MAX_TAG_LENGTH} lowercase alphanumeric \
+                 characters or -"
+            )
+            .into());
+        }
+        if tags[..index]
+            .iter()
+            .any(|other| other.as_str() == Some(tag))
+        {
+            return Err(format!("Duplicate tag {tag}").into());
+        }
+    }
+    let localizations = args["localizations"].as_object().map_or(0, |map| map.len());
+    if localizations > MAX_LOCALIZATIONS {
+        return Err(format!("Can't have more than {MAX_LOCALIZATIONS} localizations").into());
+    }
+    let whitelist = args["whitelist"]["accounts"].as_array().map_or(0, Vec::len);
+    if whitelist > MAX_WHITELIST_ACCOUNTS {
+        return Err(
+            format!("Whitelist can't have more than {MAX_WHITELIST_ACCOUNTS} accounts").into(),
+        );
+    }
+    match (
+        args["treasury_split_bps"].as_u64(),
+        args["treasury_id"].is_string(),
+    ) {
+        (Some(bps), true) if bps > u64::from(MAX_BPS) => {
+            return Err(errors::TREASURY_SPLIT_TOO_LARGE.into())
+        }
+        (Some(_), false) | (None, true) => return Err(errors::TREASURY_SPLIT_INCOMPLETE.into()),
+        _ => (),
+    }
+    match (
+        args["transfer_fee_bps"].as_u64(),
+        args["fee_sink_id"].is_string(),
+    ) {
+        (Some(bps), true) if bps > u64::from(MAX_TRANSFER_FEE_BPS) => {
+            return Err(
+                format!("Transfer fee can't exceed {MAX_TRANSFER_FEE_BPS} basis points").into(),
+            )
+        }
+        (Some(_), false) | (None, true) => return Err(errors::TRANSFER_FEE_INCOMPLETE.into()),
+        _ => (),
+    }
+    Ok(())
+}
+
+/// Returns the account the token would get, or why the symbol can't be used.
+async fn check_symbol(factory: &Factory, symbol: &str) -> Result<String> {
+    let validation: SymbolValidation = factory
+        .view("validate_symbol", json!({ "symbol": symbol }))
+        .await?;
+    let reason = match validation {
+        SymbolValidation::Valid {
+            token_account_id, ..
+        } => return Ok(token_account_id),
+        SymbolValidation::Empty => errors::INVALID_SYMBOL.to_string(),
+        SymbolValidation::InvalidCharacter { index, character } => format!(
+            "{}: {:?} at index {} isn't allowed",
+            errors::INVALID_SYMBOL,
+            character,
+            index
+        ),
+        SymbolValidation::TooLong { max_length } => {
+            format!("Symbol can't be longer than {max_length} characters")
+        }
+        SymbolValidation::Reserved => errors::SYMBOL_RESERVED.to_string(),
+        SymbolValidation::Retired => errors::SYMBOL_RETIRED.to_string(),
+        SymbolValidation::Taken => errors::TOKEN_ID_TAKEN.to_string(),
+    };
+    Err(reason.into())
+}
+
+async fn required_deposit(
+    factory: &Factory,
+    token: &TokenOptions,
+    args: &Value,
+    account_id: &AccountId,
+) -> Result<DepositBreakdown> {
+    factory
+        .view(
+            "get_required_deposit",
+            json!({
+                "args": args,
+                "account_id": account_id,
+                "extra_init_json": token.extra_init_json,
+                "project": token.project,
+            }),
+        )
+        .await
+}
+
+/// Available storage balance of the account, which is 0 before it's registered.
+async fn available_balance(factory: &Factory, account_id: &AccountId) -> Result<u128> {
+    let balance: Option<StorageBalance> = factory
+        .view("storage_balance_of", json!({ "account_id": account_id }))
+        .await?;
+    Ok(balance.map_or(0, |balance| balance.available.0))
+}
+
+async fn quote(
+    factory: &Factory,
+    token: &TokenOptions,
+    account_id: Option<AccountId>,
+    lock: bool,
+) -> Result<()> {
+    let args = read_token_args(token)?;
+    if lock {
+        let quote: Quote = factory
+            .call(
+                "get_quote",
+                json!({ "args": args, "extra_init_json": token.extra_init_json }),
+                0,
+                CALL_TGAS,
+            )
+            .await?;
+        println!("Quote {}", quote.quote_id.0);
+        println!(
+            "  required balance: {}",
+            format_near(quote.required_balance.0)
+        );
+        println!("  fee: {}", format_near(quote.fee.0));
+        println!("  valid until block {}", quote.valid_until_block_height.0);
+        return Ok(());
+    }
+    let account_id = match account_id {
+        Some(account_id) => account_id,
+        None => factory.signer_id()?.clone(),
+    };
+    let breakdown = required_deposit(factory, token, &args, &account_id).await?;
+    for (part, amount) in breakdown.parts().iter().filter(|(_, amount)| *amount > 0) {
+        println!("{}: {}", part, format_near(*amount));
+    }
+    println!("total: {}", format_near(breakdown.total.0));
+    let available = available_balance(factory, &account_id).await?;
+    println!(
+        "storage balance of {}: {}",
+        account_id,
+        format_near(available)
+    );
+    if available < breakdown.total.0 {
+        println!("missing: {}", format_near(breakdown.total.0 - available));
+    }
+    Ok(())
+}
+
+async fn deposit(factory: &Factory, options: &DepositOptions) -> Result<()> {
+    let signer_id = factory.signer_id()?.clone();
+    let amount = match (options.amount, &options.args) {
+        (Some(amount), _) => amount,
+        (None, Some(args)) => {
+            let token = TokenOptions {
+                args: args.clone(),
+                extra_init_json: None,
+                project: None,
+            };
+            let args = read_token_args(&token)?;
+            let required = required_deposit(factory, &token, &args, &signer_id).await?;
+            let available = available_balance(factory, &signer_id).await?;
+            if available >= required.total.0 {
+                println!(
+                    "The storage balance of {} already covers {}",
+                    signer_id,
+                    format_near(required.total.0)
+                );
+                return Ok(());
+            }
+            required.total.0 - available
+        }
+        (None, None) => unreachable!("clap requires one of the options"),
+    };
+    let balance: StorageBalance = factory
+        .call("storage_deposit", json!({}), amount, CALL_TGAS)
+        .await?;
+    println!(
+        "Deposited {}, the storage balance of {} is {}",
+        format_near(amount),
+        signer_id,
+        format_near(balance.available.0)
+    );
+    Ok(())
+}
+
+async fn create(
+    factory: &Factory,
+    token: &TokenOptions,
+    public_key: Option<String>,
+    referrer_id: Option<AccountId>,
+    quote_id: Option<u64>,
+    priority_fee: Option<u128>,
+) -> Result<()> {
+    let args = read_token_args(token)?;
+    let signer_id = factory.signer_id()?.clone();
+    // Under a project the token gets a sub-account of the namespace, and the symbol can be
+    // taken by the tokens of other projects.
+    if token.project.is_none() {
+        let symbol = args["metadata"]["symbol"].as_str().unwrap_or_default();
+        let token_account_id = check_symbol(factory, symbol).await?;
+        println!("Token account: {token_account_id}");
+    }
+    if quote_id.is_none() {
+        let required = required_deposit(factory, token, &args, &signer_id).await?;
+        let available = available_balance(factory, &signer_id).await?;
+        if available < required.total.0 {
+            return Err(format!(
+                "The token needs {}, but the storage balance of {} is {}. Deposit the difference \
+                 with `deposit --args`.",
+                format_near(required.total.0),
+                signer_id,
+                format_near(available)
+            )
+            .into());
+        }
+    }
+    let deployed: bool = factory
+        .call(
+            "create_token",
+            json!({
+                "args": args,
+                "public_key": public_key,
+                "referrer_id": referrer_id,
+                "extra_init_json": token.extra_init_json,
+                "quote_id": quote_id.map(|quote_id| quote_id.to_string()),
+                "project": token.project,
+                "priority_fee": priority_fee.map(|fee| fee.to_string()),
+            }),
+            0,
+            CREATE_TOKEN_TGAS,
+        )
+        .await?;
+    if deployed {
+        println!("The token is deployed");
+    } else {
+        println!(
+            "The token isn't deployed yet. It is waiting for approval or in the creation queue, \
+             see `get_proposal` and `get_creation_queue`, or its deployment failed and the \
+             deposit was returned to the storage balance."
+        );
+    }
+    Ok(())
+}
+
+/// Lists pages of `get_tokens_flat`. The view skips frozen tokens within the page range, so the
+/// next page starts `limit` indexes further, whatever the page holds.
+async fn list(
+    factory: &Factory,
+    mut from_index: u64,
+    limit: u64,
+    all: bool,
+    include_frozen: bool,
+) -> Result<()> {
+    let total: u64 = factory.view("get_number_of_tokens", json!({})).await?;
+    while from_index < total {
+        let page: TokensFlat = factory
+            .view(
+                "get_tokens_flat",
+                json!({
+                    "from_index": from_index,
+                    "limit": limit,
+                    "include_frozen": include_frozen,
+                }),
+            )
+            .await?;
+        for index in 0..page.token_id.len() {
+            println!(
+                "{}\t{}\towner {}\tsupply {}",
+                page.token_id[index],
+                page.account[index],
+                page.owner[index],
+                format_amount(page.supply[index].0, page.decimals[index])
+            );
+        }
+        from_index += limit;
+        if !all {
+            break;
+        }
+    }
+    println!("{total} tokens registered");
+    Ok(())
+}
+
+/// Lists pages of `get_tokens_by_owner`, which end early at `MAX_PAGE_BYTES`, so the next page
+/// starts after the last returned record.
+async fn list_by_owner(
+    factory: &Factory,
+    owner_id: &AccountId,
+    mut from_index: u64,
+    limit: u64,
+    all: bool,
+) -> Result<()> {
+    loop {
+        let page: Vec<VersionedTokenRecord> = factory
+            .view(
+                "get_tokens_by_owner",
+                json!({ "owner_id": owner_id, "from_index": from_index, "limit": limit }),
+            )
+            .await?;
+        for VersionedTokenRecord { record } in &page {
+            println!(
+                "{}\t{}\tsupply {}{}",
+                record.metadata.symbol,
+                record.metadata.name,
+                format_amount(record.total_supply.0, record.metadata.decimals),
+                if record.frozen { "\tfrozen" } else { "" }
+            );
+        }
+        if page.is_empty() || !all {
+            return Ok(());
+        }
+        from_index += page.len() as u64;
+    }
+}
+
+async fn verify(factory: &Factory, token_id: &str) -> Result<()> {
+    let token: Option<VersionedTokenRecord> = factory
+        .view("get_token", json!({ "token_id": token_id }))
+        .await?;
+    let record = token
+        .ok_or_else(|| format!("{} isn't registered with {}", token_id, factory.account_id))?
+        .record;
+    let security: Option<TokenSecurityInfo> = factory
+        .view("get_token_security_info", json!({ "token_id": token_id }))
+        .await?;
+    let security = security.ok_or("The token has no security info")?;
+    let expected: TokenCodeInfo = match &record.template {
+        Some(template) => template_code(factory, template).await?,
+        None => factory.view("get_token_code_info", json!({})).await?,
+    };
+    let code = factory.code_of(&security.token_account_id.parse()?).await?;
+    let code_hash = base64::Engine::encode(
+        &base64::engine::general_purpose::STANDARD,
+        Sha256::digest(&code),
+    );
+    println!("Token account: {}", security.token_account_id);
+    println!("Code: {} bytes, sha256 {}", code.len(), code_hash);
+    println!(
+        "Full access key: {}",
+        match &security.full_access_key {
+            Some(key) => format!("{key}, the code can be replaced"),
+            None => "none".to_string(),
+        }
+    );
+    println!("Fee on transfer: {}", security.fee_on_transfer);
+    if record.frozen {
+        println!("Frozen by the compliance role");
+    }
+    if code_hash != expected.code_hash {
+        return Err(format!(
+            "The code doesn't match the {} code of the factory ({} bytes, sha256 {}). It was \
+             replaced, or deployed before the factory's code was updated.",
+            record.template.as_deref().unwrap_or("default"),
+            expected.code_size,
+            expected.code_hash
+        )
+        .into());
+    }
+    println!(
+        "The code matches the {} code of the factory",
+        record.template.as_deref().unwrap_or("default")
+    );
+    Ok(())
+}
+
+/// Looks the template up in the pages of `get_templates`.
+async fn template_code(factory: &Factory, name: &str) -> Result<TokenCodeInfo> {
+    let mut from_index = 0;
+    loop {
+        let page: Vec<(String, Template)> = factory
+            .view(
+                "get_templates",
+                json!({ "from_index": from_index, "limit": MAX_PAGE_SIZE }),
+            )
+            .await?;
+        if page.is_empty() {
+            return Err(format!("Template {name} isn't registered").into());
+        }
+        from_index += page.len() as u64;
+        if let Some((_, template)) = page.into_iter().find(|(template, _)| template == name) {
+            return Ok(template.code);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token_args() -> Value {
+        json!({
+            "owner_id": "alice.testnet",
+            "total_supply": "1000000",
+            "metadata": {
+                "spec": "ft-1.0.0",
+                "name": "Alice Token",
+                "symbol": "ALICE",
+                "decimals": 18,
+            },
+        })
+    }
+
+    fn check_error(args: &Value) -> String {
+        check_token_args(args).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_check_token_args() {
+        let mut args = token_args();
+        assert!(check_token_args(&args).is_ok());
+        args["tags"] = json!(["defi", "meme"]);
+        args["treasury_id"] = json!("treasury.testnet");
+        args["treasury_split_bps"] = json!(MAX_BPS);
+        args["fee_sink_id"] = json!("sink.testnet");
+        args["transfer_fee_bps"] = json!(MAX_TRANSFER_FEE_BPS);
+        args["whitelist"] = json!({ "accounts": vec!["bob.testnet"; MAX_WHITELIST_ACCOUNTS] });
+        assert!(check_token_args(&args).is_ok());
+        args["whitelist"] = json!({ "merkle_root": "AAAA" });
+        assert!(check_token_args(&args).is_ok());
+    }
+
+    #[test]
+    fn test_check_token_args_tags() {
+        let mut args = token_args();
+        args["tags"] = json!(vec!["defi"; MAX_TAGS + 1]);
+        assert_eq!(check_error(&args), "Can't have more than 5 tags");
+        for tag in ["", "DeFi", "de fi", &"t".repeat(MAX_TAG_LENGTH + 1)] {
+            args["tags"] = json!([tag]);
+            assert!(check_error(&args).starts_with("Tag "), "{tag}");
+        }
+        args["tags"] = json!(["defi", "meme", "defi"]);
+        assert_eq!(check_error(&args), "Duplicate tag defi");
+        args["tags"] = json!([1]);
+        assert_eq!(check_error(&args), "Tags have to be strings");
+    }
+
+    #[test]
+    fn test_check_token_args_limits() {
+        let mut args = token_args();
+        args["whitelist"] = json!({ "accounts": vec!["bob.testnet"; MAX_WHITELIST_ACCOUNTS + 1] });
+        assert_eq!(
+            check_error(&args),
+            "Whitelist can't have more than 500 accounts"
+        );
+
+        let mut args = token_args();
+        let localizations: serde_json::Map<String, Value> = (0..=MAX_LOCALIZATIONS)
+            .map(|index| (format!("l{index}"), json!({ "name": "Token" })))
+            .collect();
+        args["localizations"] = Value::Object(localizations);
+        assert_eq!(check_error(&args), "Can't have more than 10 localizations");
+
+        let mut args = token_args();
+        args["treasury_id"] = json!("treasury.testnet");
+        args["treasury_split_bps"] = json!(MAX_BPS + 1);
+        assert_eq!(check_error(&args), errors::TREASURY_SPLIT_TOO_LARGE);
+        args["treasury_id"] = Value::Null;
+        assert_eq!(check_error(&args), errors::TREASURY_SPLIT_INCOMPLETE);
+
+        let mut args = token_args();
+        args["fee_sink_id"] = json!("sink.testnet");
+        args["transfer_fee_bps"] = json!(MAX_TRANSFER_FEE_BPS + 1);
+        assert_eq!(
+            check_error(&args),
+            "Transfer fee can't exceed 1000 basis points"
+        );
+        args["transfer_fee_bps"] = Value::Null;
+        assert_eq!(check_error(&args), errors::TRANSFER_FEE_INCOMPLETE);
+
+        let mut args = token_args();
+        args["metadata"] = json!({ "name": "Alice Token" });
+        assert_eq!(check_error(&args), "The token args have no metadata.symbol");
+    }
+}
@@ -0,0 +1,113 @@
+//! Shapes of the factory views the commands read. They only declare the fields the CLI uses, so
+//! fields the factory adds later are ignored.
+use serde::{Deserialize, Deserializer};
+
+/// Amount of a `U128` JSON string, such as a balance in yoctoNEAR.
+#[derive(Deserialize, Clone, Copy, Default)]
+pub struct U128(#[serde(deserialize_with = "from_string")] pub u128);
+
+/// Number of a `U64` JSON string, such as a block height.
+#[derive(Deserialize, Clone, Copy, Default)]
+pub struct U64(#[serde(deserialize_with = "from_string")] pub u64);
+
+fn from_string<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
+#[derive(Deserialize)]
+pub struct StorageBalance {
+    pub available: U128,
+}
+
+#[derive(Deserialize)]
+pub struct DepositBreakdown {
+    pub account_registration: U128,
+    pub code_storage: U128,
+    pub args_storage: U128,
+    pub registry_storage: U128,
+    pub namespace_storage: U128,
+    pub launch_certificate: U128,
+    pub owner_registration: U128,
+    pub fee: U128,
+    pub total: U128,
+}
+
+impl DepositBreakdown {
+    /// The parts of the total with their names, in the order of the view.
+    pub fn parts(&self) -> [(&'static str, u128); 8] {
+        [
+            ("account registration", self.account_registration.0),
+            ("code storage", self.code_storage.0),
+            ("args storage", self.args_storage.0),
+            ("registry storage", self.registry_storage.0),
+            ("namespace storage", self.namespace_storage.0),
+            ("launch certificate", self.launch_certificate.0),
+            ("owner registration", self.owner_registration.0),
+            ("fee", self.fee.0),
+        ]
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Quote {
+    pub quote_id: U64,
+    pub required_balance: U128,
+    pub fee: U128,
+    pub valid_until_block_height: U64,
+}
+
+#[derive(Deserialize)]
+pub struct TokenCodeInfo {
+    /// Base64 of the SHA-256 hash of the code.
+    pub code_hash: String,
+    pub code_size: u64,
+}
+
+#[derive(Deserialize)]
+pub struct Template {
+    pub code: TokenCodeInfo,
+}
+
+#[derive(Deserialize)]
+pub struct TokenSecurityInfo {
+    pub token_account_id: String,
+    pub full_access_key: Option<String>,
+    pub fee_on_transfer: bool,
+}
+
+#[derive(Deserialize)]
+pub struct TokensFlat {
+    pub token_id: Vec<String>,
+    pub account: Vec<String>,
+    pub owner: Vec<String>,
+    pub supply: Vec<U128>,
+    pub decimals: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+pub struct VersionedTokenRecord {
+    pub record: TokenRecord,
+}
+
+#[derive(Deserialize)]
+pub struct TokenRecord {
+    pub total_supply: U128,
+    pub metadata: TokenMetadata,
+    #[serde(default)]
+    pub template: Option<String>,
+    pub frozen: bool,
+}
+
+#[derive(Deserialize)]
+pub struct TokenMetadata {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+}
@@ -0,0 +1,161 @@
+//! Types shared by the token factory, the token contracts it deploys and its clients, so the
+//! init args the factory passes to a token's `new` and the records, events and configs in its
+//! views can't drift apart between the crates.
+//!
+//! The limits, the panic messages and the enums of the views below only need serde and borsh.
+//! The types built on near-sdk, such as the token args and records, the config and the events,
+//! are in the `near` feature, which is on by default. Clients that don't link near-sdk depend
+//! on the crate with `default-features = false`.
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "near")]
+mod near;
+#[cfg(feature = "near")]
+pub use crate::near::*;
+
+pub const MAX_BPS: u16 = 10_000;
+pub const MAX_TRANSFER_FEE_BPS: u16 = 1_000;
+pub const MAX_WHITELIST_ACCOUNTS: usize = 500;
+pub const MAX_TAGS: usize = 5;
+pub const MAX_TAG_LENGTH: usize = 32;
+pub const MAX_LOCALIZATIONS: usize = 10;
+pub const MAX_LANGUAGE_TAG_LENGTH: usize = 8;
+pub const MAX_LOCALIZED_NAME_LENGTH: usize = 64;
+pub const MAX_LOCALIZED_DESCRIPTION_LENGTH: usize = 1000;
+/// Upper bound on the number of records returned by a single list view, so a page of records
+/// with the largest allowed metadata still fits into the view call gas limit.
+pub const MAX_PAGE_SIZE: u64 = 100;
+pub const EVENT_STANDARD: &str = "token_factory";
+pub const EVENT_VERSION: &str = "1.0.0";
+/// Names of all the fields of the token args. Extra init args can't use any of them, set or not,
+/// so an unset field such as the transfer fee can't reach the token past its validation.
+pub const TOKEN_ARGS_FIELDS: &[&str] = &[
+    "owner_id",
+    "total_supply",
+    "metadata",
+    "whitelist",
+    "icon_upload_id",
+    "treasury_id",
+    "treasury_split_bps",
+    "transfer_fee_bps",
+    "fee_sink_id",
+    "tags",
+    "template",
+    "delete_key_after_init",
+    "campaign",
+    "icon_blob",
+    "localizations",
+];
+/// Fields of the token args only kept in the factory's registry, which aren't passed to `new`.
+/// The factory resolves the icon upload into `metadata.icon` and records the whitelist for the
+/// sale, so neither reaches the token.
+pub const REGISTRY_ONLY_FIELDS: &[&str] = &[
+    "tags",
+    "template",
+    "delete_key_after_init",
+    "campaign",
+    "icon_blob",
+    "localizations",
+    "icon_upload_id",
+    "whitelist",
+];
+/// Init args the factory adds to the ones of the token args. A token created by a namespace
+/// can't tell the factory from the predecessor of `new`, so the factory passes its account.
+pub const FACTORY_INIT_FIELDS: &[&str] = &["factory_id"];
+
+/// Panic messages shared by the factory and the tokens, which clients match on.
+pub mod errors {
+    pub const INVALID_SYMBOL: &str = "Invalid Symbol";
+    pub const SYMBOL_RESERVED: &str = "Symbol is reserved";
+    pub const SYMBOL_RETIRED: &str = "Symbol is retired";
+    pub const TOKEN_ID_TAKEN: &str = "Token ID is already taken";
+    pub const TREASURY_SPLIT_TOO_LARGE: &str = "Treasury split can't exceed 100%";
+    pub const TREASURY_SPLIT_INCOMPLETE: &str =
+        "Treasury split needs both treasury_id and treasury_split_bps";
+    pub const TRANSFER_FEE_INCOMPLETE: &str =
+        "Transfer fee needs both transfer_fee_bps and fee_sink_id";
+}
+
+pub type TokenId = String;
+
+/// Holder counts a token can report to the factory with `on_holder_milestone`.
+pub const HOLDER_MILESTONES: &[u64] = &[10, 100, 1_000, 10_000, 100_000, 1_000_000];
+
+/// Result of validating a symbol, with the exact reason when it can't be used.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum SymbolValidation {
+    Valid {
+        token_id: TokenId,
+        token_account_id: String,
+    },
+    Empty,
+    InvalidCharacter {
+        index: u32,
+        character: char,
+    },
+    TooLong {
+        max_length: u8,
+    },
+    Reserved,
+    /// Permanently retired with `retire_symbols`.
+    Retired,
+    Taken,
+}
+
+impl SymbolValidation {
+    pub fn unwrap_token_id(self) -> TokenId {
+        match self {
+            SymbolValidation::Valid { token_id, .. } => token_id,
+            SymbolValidation::Empty | SymbolValidation::InvalidCharacter { .. } => {
+                panic!("{}", errors::INVALID_SYMBOL)
+            }
+            SymbolValidation::TooLong { max_length } => {
+                panic!("Symbol can't be longer than {} characters", max_length)
+            }
+            SymbolValidation::Reserved => panic!("{}", errors::SYMBOL_RESERVED),
+            SymbolValidation::Retired => panic!("{}", errors::SYMBOL_RETIRED),
+            SymbolValidation::Taken => panic!("{}", errors::TOKEN_ID_TAKEN),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, PartialEq)]
+pub enum Role {
+    Reviewer,
+    Compliance,
+    /// Attests the code and the access keys of the factory account for `get_trust_report`.
+    Attester,
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, PartialEq)]
+pub enum TokenIdMode {
+    /// The token account is derived from the lowercase symbol, which has to be unique.
+    Symbol,
+    /// The token account is `t{N}` for a monotonic counter `N`, and the symbol is only metadata.
+    Counter,
+}
+
+/// How strictly symbols and names are checked for lookalikes of other tokens.
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, PartialEq)]
+pub enum ConfusableCheck {
+    Off,
+    /// Refuses symbols and names with characters that look like ASCII letters or digits,
+    /// such as the Cyrillic `а`.
+    Characters,
+    /// Also refuses symbols whose skeleton matches a registered symbol, e.g. `usdt` and `u5dt`.
+    Skeleton,
+}
+
+/// What happens to tokens whose symbol or name is similar to the names of popular tokens the
+/// owner added with `add_similarity_references`. The similarity is the Levenshtein distance of
+/// the normalized strings relative to the length of the longer one.
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, PartialEq)]
+pub enum SimilarityCheck {
+    Off,
+    /// Creates the token but records the reference it is similar to, for anti-phishing UIs.
+    Flag,
+    /// Refuses the token.
+    Block,
+}
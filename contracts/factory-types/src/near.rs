@@ -0,0 +1,620 @@
+//! Types built on near-sdk: the token args and registry records, the init args of the token,
+//! the config, the events and the hooks of the factory.
+use crate::*;
+use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{Base58PublicKey, Base64VecU8, ValidAccountId, U128, U64};
+use near_sdk::serde::ser::SerializeStruct;
+use near_sdk::serde::{Deserialize, Serialize, Serializer};
+use near_sdk::serde_json;
+use near_sdk::{env, ext_contract, AccountId, Balance, Gas};
+use std::collections::BTreeMap;
+
+pub const DEFAULT_INIT_GAS: Gas = 50_000_000_000_000;
+pub const DEFAULT_CALLBACK_GAS: Gas = 10_000_000_000_000;
+
+/// Factory methods token templates can call on the factory that created them.
+#[ext_contract(ext_factory)]
+pub trait FactoryHooks {
+    /// Reports that the number of holders of the calling token reached one of the
+    /// `HOLDER_MILESTONES`. The factory takes the count as reported, so UIs should present it
+    /// as the token's own claim.
+    fn on_holder_milestone(&mut self, holders: U64);
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenArgs {
+    pub owner_id: ValidAccountId,
+    pub total_supply: U128,
+    pub metadata: FungibleTokenMetadata,
+    /// Allowlist for gated sales. It is only kept in the registry, where sales read it from the
+    /// token views, and isn't passed to the template's `new`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub whitelist: Option<SaleWhitelist>,
+    /// Id of a finalized icon upload of the creator, which replaces `metadata.icon` when the
+    /// token is created.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon_upload_id: Option<String>,
+    /// Account the token mints `treasury_split_bps` basis points of the total supply to at
+    /// init, instead of the owner. Needs a template that supports the split.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub treasury_id: Option<ValidAccountId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub treasury_split_bps: Option<u16>,
+    /// Fee on transfer in basis points, paid to `fee_sink_id` by every transfer of the token.
+    /// Needs a fee-on-transfer template such as the taxable token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transfer_fee_bps: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee_sink_id: Option<ValidAccountId>,
+    /// Categories the token is listed under in `get_tokens_by_tag`. They are only kept in the
+    /// registry and not passed to the token.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Registered template whose code is deployed instead of the default token code. Not
+    /// passed to the token either.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// Deletes the `public_key` again right after the token is initialized, in the same batch,
+    /// so the token ends up keyless. Only kept in the registry.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub delete_key_after_init: bool,
+    /// Campaign the token is launched in, which is only kept in the registry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub campaign: Option<String>,
+    /// Keeps the icon upload in the factory's icon blobs, served by `get_icon`, instead of
+    /// `metadata.icon`. `metadata.reference_hash` is set to the SHA-256 hash of the icon, so the
+    /// metadata can't have a reference of its own. Only kept in the registry.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub icon_blob: bool,
+    /// Localized names and descriptions by language tag such as `de` or `pt-br`, for launch
+    /// pages. Only kept in the registry, and charged per byte like the rest of the record.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub localizations: BTreeMap<String, Localization>,
+}
+
+impl TokenArgs {
+    /// Args for the default template, without a whitelist, icon upload, treasury split, transfer
+    /// fee or tags.
+    pub fn new(
+        owner_id: ValidAccountId,
+        total_supply: U128,
+        metadata: FungibleTokenMetadata,
+    ) -> Self {
+        Self {
+            owner_id,
+            total_supply,
+            metadata,
+            whitelist: None,
+            icon_upload_id: None,
+            treasury_id: None,
+            treasury_split_bps: None,
+            transfer_fee_bps: None,
+            fee_sink_id: None,
+            tags: vec![],
+            template: None,
+            delete_key_after_init: false,
+            campaign: None,
+            icon_blob: false,
+            localizations: BTreeMap::new(),
+        }
+    }
+
+    pub fn assert_valid_tags(&self) {
+        assert!(
+            self.tags.len() <= MAX_TAGS,
+            "Can't have more than {} tags",
+            MAX_TAGS
+        );
+        for (index, tag) in self.tags.iter().enumerate() {
+            assert!(
+                !tag.is_empty()
+                    && tag.len() <= MAX_TAG_LENGTH
+                    && tag
+                        .chars()
+                        .all(|c| matches!(c, '0'..='9' | 'a'..='z' | '-')),
+                "Tag {} is invalid, it has to be up to {} lowercase alphanumeric characters or -",
+                tag,
+                MAX_TAG_LENGTH
+            );
+            assert!(!self.tags[..index].contains(tag), "Duplicate tag {}", tag);
+        }
+    }
+
+    pub fn assert_valid_treasury_split(&self) {
+        match (&self.treasury_id, self.treasury_split_bps) {
+            (Some(_), Some(bps)) => assert!(bps <= MAX_BPS, "{}", errors::TREASURY_SPLIT_TOO_LARGE),
+            (None, None) => (),
+            _ => panic!("{}", errors::TREASURY_SPLIT_INCOMPLETE),
+        }
+    }
+
+    pub fn assert_valid_transfer_fee(&self) {
+        match (self.transfer_fee_bps, &self.fee_sink_id) {
+            (Some(bps), Some(_)) => assert!(
+                bps <= MAX_TRANSFER_FEE_BPS,
+                "Transfer fee can't exceed {} basis points",
+                MAX_TRANSFER_FEE_BPS
+            ),
+            (None, None) => (),
+            _ => panic!("{}", errors::TRANSFER_FEE_INCOMPLETE),
+        }
+    }
+
+    pub fn has_transfer_fee(&self) -> bool {
+        self.transfer_fee_bps.unwrap_or(0) > 0
+    }
+
+    pub fn assert_valid_localizations(&self) {
+        assert_valid_localizations(&self.localizations);
+    }
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Localization {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+pub fn assert_valid_localizations(localizations: &BTreeMap<String, Localization>) {
+    assert!(
+        localizations.len() <= MAX_LOCALIZATIONS,
+        "Can't have more than {} localizations",
+        MAX_LOCALIZATIONS
+    );
+    for (language, localization) in localizations {
+        assert!(
+            !language.is_empty()
+                && language.len() <= MAX_LANGUAGE_TAG_LENGTH
+                && language
+                    .chars()
+                    .all(|c| matches!(c, '0'..='9' | 'a'..='z' | '-')),
+            "Language tag {} is invalid, use up to {} lowercase alphanumeric characters or -",
+            language,
+            MAX_LANGUAGE_TAG_LENGTH
+        );
+        assert!(
+            !localization.name.is_empty() && localization.name.len() <= MAX_LOCALIZED_NAME_LENGTH,
+            "Localized name has to be between 1 and {} bytes",
+            MAX_LOCALIZED_NAME_LENGTH
+        );
+        if let Some(description) = &localization.description {
+            assert!(
+                description.len() <= MAX_LOCALIZED_DESCRIPTION_LENGTH,
+                "Localized description can't be longer than {} bytes",
+                MAX_LOCALIZED_DESCRIPTION_LENGTH
+            );
+        }
+    }
+}
+
+/// Arguments of the default token's `new` method. The token builds them from its parameters,
+/// so a field added here fails to compile until the token takes it.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenInitArgs {
+    pub owner_id: ValidAccountId,
+    pub total_supply: U128,
+    pub metadata: FungibleTokenMetadata,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub treasury_id: Option<ValidAccountId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub treasury_split_bps: Option<u16>,
+}
+
+impl TokenInitArgs {
+    /// Part of the total supply minted to the treasury instead of the owner.
+    pub fn treasury_supply(&self) -> Balance {
+        let total_supply: Balance = self.total_supply.into();
+        match (&self.treasury_id, self.treasury_split_bps) {
+            (Some(_), Some(bps)) => {
+                assert!(bps <= MAX_BPS, "{}", errors::TREASURY_SPLIT_TOO_LARGE);
+                // Split up so supplies close to the maximum don't overflow.
+                total_supply / Balance::from(MAX_BPS) * Balance::from(bps)
+                    + total_supply % Balance::from(MAX_BPS) * Balance::from(bps)
+                        / Balance::from(MAX_BPS)
+            }
+            (None, None) => 0,
+            _ => env::panic(errors::TREASURY_SPLIT_INCOMPLETE.as_bytes()),
+        }
+    }
+}
+
+/// Every field of the init args has to be taken from the token args the factory registers.
+impl From<&TokenArgs> for TokenInitArgs {
+    fn from(args: &TokenArgs) -> Self {
+        Self {
+            owner_id: args.owner_id.clone(),
+            total_supply: args.total_supply,
+            metadata: args.metadata.clone(),
+            treasury_id: args.treasury_id.clone(),
+            treasury_split_bps: args.treasury_split_bps,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum SaleWhitelist {
+    /// Root of a Merkle tree over the allowed accounts, checked against proofs by the sale.
+    MerkleRoot(Base64VecU8),
+    Accounts(Vec<ValidAccountId>),
+}
+
+impl SaleWhitelist {
+    pub fn assert_valid(&self) {
+        match self {
+            SaleWhitelist::MerkleRoot(root) => {
+                assert_eq!(root.0.len(), 32, "Whitelist Merkle root must be 32 bytes")
+            }
+            SaleWhitelist::Accounts(accounts) => assert!(
+                accounts.len() <= MAX_WHITELIST_ACCOUNTS,
+                "Whitelist can't have more than {} accounts",
+                MAX_WHITELIST_ACCOUNTS
+            ),
+        }
+    }
+}
+
+/// Registry entry of a token. The token args are flattened into the JSON output, so the views
+/// keep returning the args at the top level.
+#[derive(Serialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenRecord {
+    #[serde(flatten)]
+    pub args: TokenArgs,
+    pub creator_id: AccountId,
+    /// Set by the compliance role to hide the token from the default list views.
+    pub frozen: bool,
+    /// Resources consumed by the creation, filled once the deployment succeeded.
+    pub creation_stats: Option<CreationStats>,
+    /// Block timestamp of the last `ping_tokens` check the token answered.
+    pub last_seen_ok: Option<U64>,
+    /// Set when the token failed to answer the last `ping_tokens` check.
+    pub unresponsive: bool,
+    /// Full access key added to the token account at its creation, if any.
+    pub full_access_key: Option<Base58PublicKey>,
+    /// Account of a token created by a previous factory account, which is no longer a
+    /// sub-account of this factory. Set by `rebind_registry`.
+    pub detached_account_id: Option<AccountId>,
+    /// Set for tokens that charge a fee on every transfer, so UIs can warn about them.
+    pub fee_on_transfer: bool,
+    /// Block timestamp the token was added to the registry at.
+    pub created_at: Option<U64>,
+}
+
+/// Token record returned by the views, serialized as `{"version": 1, "record": {...}}`. A
+/// schema change of the record adds a variant with the next version, so integrators can tell
+/// the shapes apart instead of fields silently appearing or disappearing.
+pub enum VersionedTokenRecord {
+    V1(TokenRecord),
+}
+
+impl From<TokenRecord> for VersionedTokenRecord {
+    fn from(record: TokenRecord) -> Self {
+        VersionedTokenRecord::V1(record)
+    }
+}
+
+impl Serialize for VersionedTokenRecord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (version, record) = match self {
+            VersionedTokenRecord::V1(record) => (1u8, record),
+        };
+        let mut state = serializer.serialize_struct("VersionedTokenRecord", 2)?;
+        state.serialize_field("version", &version)?;
+        state.serialize_field("record", record)?;
+        state.end()
+    }
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CreationStats {
+    /// Bytes the registry entry takes in the factory storage.
+    pub registry_bytes: u64,
+    pub code_bytes: u64,
+    /// Gas attached to the deployment promises.
+    pub gas_attached: Gas,
+    /// Gas burned by the factory across the creation call and its callbacks.
+    pub factory_gas_used: Gas,
+}
+
+impl CreationStats {
+    pub fn add(&mut self, other: &CreationStats) {
+        self.registry_bytes += other.registry_bytes;
+        self.code_bytes += other.code_bytes;
+        self.gas_attached += other.gas_attached;
+        self.factory_gas_used += other.factory_gas_used;
+    }
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Config {
+    /// When set, `create_token` only records a proposal that a reviewer has to approve.
+    pub approval_required: bool,
+    /// Fee charged on top of the storage cost for every created token.
+    pub creation_fee: U128,
+    /// Share of the creation fee paid out to the referrer, in basis points.
+    pub referral_fee_bps: u16,
+    /// Minimum amount accepted by a single `storage_deposit` call.
+    pub min_storage_deposit: U128,
+    /// Maximum storage balance an account can hold, so the factory doesn't custody funds.
+    pub max_storage_balance: Option<U128>,
+    /// Seconds within which `reclaim_recent_deposit` reverses a storage deposit that nothing
+    /// was charged from yet. Deposits aren't tracked when unset.
+    pub deposit_grace_period_sec: Option<u64>,
+    /// Largest deposit a creation call accepts, so a mistyped amount fails instead of ending
+    /// up in the storage balance. Larger amounts can still go through `storage_deposit`.
+    pub max_creation_deposit: Option<U128>,
+    /// Refuses any deposit attached to a creation call, so every deposit goes through
+    /// `storage_deposit` and shows up separately from the creations it pays for.
+    pub separate_deposits: bool,
+    /// Creates the token account and deploys its code in separate receipts, for token code that
+    /// is too large to fit into a single receipt together with the other actions.
+    pub split_deployment: bool,
+    /// Number of blocks a quote returned by `get_quote` stays valid for.
+    pub quote_validity_blocks: u64,
+    pub token_id_mode: TokenIdMode,
+    /// Refuses token names that normalize to the name of a registered token.
+    pub unique_names: bool,
+    /// Number of registered tokens above which new creations are refused, until the owner
+    /// archives frozen or unresponsive tokens with `archive_tokens` or raises the cap.
+    pub registry_soft_cap: Option<u64>,
+    /// Only creates tokens through `commit_symbol` and `reveal_and_create`, so bots watching
+    /// pending transactions can't take a symbol before its creator.
+    pub symbol_commit_required: bool,
+    /// Blocks a symbol commitment has to wait before it can be revealed, and after which it
+    /// expires.
+    pub min_commit_age_blocks: u64,
+    pub max_commit_age_blocks: u64,
+    /// Seconds between a creator scheduling a new metadata reference with
+    /// `refresh_token_reference` and it being applied to the token.
+    pub reference_refresh_delay_sec: u64,
+    pub confusable_check: ConfusableCheck,
+    /// Range of `metadata.decimals` accepted for new tokens, as wallets break on extreme values.
+    pub min_decimals: u8,
+    pub max_decimals: u8,
+    /// Queues direct creations to be deployed by `process_queue` in the order of their
+    /// priority fee, for hyped launches.
+    pub congested: bool,
+    /// Gas attached to the `new` call of the token. Gas it doesn't use is refunded by the
+    /// protocol to the signer of the transaction, so the creator gets it back without the
+    /// factory forwarding anything. Deposit refunds of a failed deployment come back to the
+    /// factory instead, and are credited to the payer's storage balance by the callback.
+    pub init_gas: Gas,
+    /// Gas attached to each of the factory callbacks.
+    pub callback_gas: Gas,
+    /// NFT contract minting a launch certificate to the creator of every deployed token.
+    pub launch_certificate: Option<LaunchCertificateConfig>,
+    /// Part of the creation fee set in USD, converted to NEAR with the cached oracle price.
+    pub usd_fee: Option<UsdFeeConfig>,
+    /// Set by the owner with `deprecate`, so tooling can move on to the successor factory.
+    pub deprecation: Option<Deprecation>,
+    /// Logs `DIAGNOSTIC_JSON` entries with the validation results and the balances at the
+    /// decision points of a creation, for debugging failed launches from the explorer logs.
+    pub verbose_logging: bool,
+    /// Probes the token account before creating it, and refunds the escrow without spending any
+    /// of it if the account exists already, e.g. because it was created with a key of the
+    /// factory account. Doesn't apply to project tokens and preprovisioned accounts.
+    pub probe_token_accounts: bool,
+    /// SocialDB contract the creators link their near.social profiles from with
+    /// `link_social_profile`, e.g. `social.near`. Linking is disabled while it isn't set.
+    pub social_db_id: Option<ValidAccountId>,
+    /// Account the token accounts are created under instead of the factory account, e.g.
+    /// `testnet` for top level token ids. It runs the namespace contract initialized with the
+    /// factory through `new_with_factory`, so the registry and the namespace can be different
+    /// accounts. The namespace has to return the deposit of failed creations to the factory, as
+    /// the current namespace contract does. Can only change while the registry is empty.
+    pub token_namespace_id: Option<ValidAccountId>,
+    pub similarity_check: SimilarityCheck,
+    /// Similarity in basis points from which a symbol or name counts as similar to one of the
+    /// owner's similarity references, see `SimilarityCheck`.
+    pub min_similarity_bps: u16,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            approval_required: false,
+            creation_fee: 0.into(),
+            referral_fee_bps: 0,
+            min_storage_deposit: 0.into(),
+            max_storage_balance: None,
+            deposit_grace_period_sec: None,
+            max_creation_deposit: None,
+            separate_deposits: false,
+            split_deployment: false,
+            quote_validity_blocks: 600,
+            token_id_mode: TokenIdMode::Symbol,
+            unique_names: false,
+            registry_soft_cap: None,
+            symbol_commit_required: false,
+            min_commit_age_blocks: 5,
+            max_commit_age_blocks: 86_400,
+            reference_refresh_delay_sec: 86_400,
+            confusable_check: ConfusableCheck::Characters,
+            min_decimals: 0,
+            max_decimals: 24,
+            congested: false,
+            init_gas: DEFAULT_INIT_GAS,
+            callback_gas: DEFAULT_CALLBACK_GAS,
+            launch_certificate: None,
+            usd_fee: None,
+            deprecation: None,
+            verbose_logging: false,
+            probe_token_accounts: false,
+            social_db_id: None,
+            token_namespace_id: None,
+            similarity_check: SimilarityCheck::Off,
+            min_similarity_bps: 8_000,
+        }
+    }
+}
+
+impl Config {
+    pub fn assert_valid_decimals(&self, decimals: u8) {
+        assert!(
+            decimals >= self.min_decimals && decimals <= self.max_decimals,
+            "Decimals have to be between {} and {}, got {}",
+            self.min_decimals,
+            self.max_decimals,
+            decimals
+        );
+    }
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LaunchCertificateConfig {
+    /// Contract implementing `nft_mint(token_id, receiver_id, token_metadata)`.
+    pub contract_id: ValidAccountId,
+    /// Deposit attached to `nft_mint`, charged to the creator together with the storage.
+    pub mint_deposit: U128,
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct UsdFeeConfig {
+    /// Contract implementing `get_price_data(asset_ids)` like `priceoracle.near`.
+    pub oracle_id: ValidAccountId,
+    /// Oracle asset priced like NEAR, e.g. `wrap.near`.
+    pub asset_id: String,
+    /// Fee charged on top of `creation_fee`, in USD cents.
+    pub fee_usd_cents: u64,
+    /// Age after which the cached price has to be refreshed with `refresh_usd_price`.
+    pub max_price_age_sec: u64,
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Deprecation {
+    /// Factory that replaces this one, if any.
+    pub successor_id: Option<ValidAccountId>,
+    /// Block height from which new creations are refused. Pending deployments, proposals and
+    /// queued creations are still processed.
+    pub sunset_block_height: Option<U64>,
+}
+
+impl Deprecation {
+    pub fn is_sunset(&self, block_height: u64) -> bool {
+        self.sunset_block_height
+            .map_or(false, |sunset_block_height| {
+                block_height >= sunset_block_height.0
+            })
+    }
+}
+
+/// Events are logged following the NEP-297 format, so indexers can pick up launches while the
+/// deployment receipts are still in flight.
+#[derive(Serialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum FactoryEvent {
+    TokenCreationStarted {
+        token_id: TokenId,
+        token_account_id: AccountId,
+        creator_id: AccountId,
+    },
+    TokenCreationFinished {
+        token_id: TokenId,
+        token_account_id: AccountId,
+        creator_id: AccountId,
+        success: bool,
+    },
+    TokenFrozen {
+        token_id: TokenId,
+        reason: String,
+    },
+    TokenUnfrozen {
+        token_id: TokenId,
+    },
+    OwnerChanged {
+        old_owner_id: AccountId,
+        new_owner_id: AccountId,
+    },
+    ConfigUpdated {
+        config: Box<Config>,
+    },
+    RoleGranted {
+        account_id: AccountId,
+        role: Role,
+    },
+    RoleRevoked {
+        account_id: AccountId,
+        role: Role,
+    },
+    AccountBanned {
+        account_id: AccountId,
+    },
+    AccountUnbanned {
+        account_id: AccountId,
+    },
+    TokenArchived {
+        token_id: TokenId,
+        token_account_id: AccountId,
+        creator_id: AccountId,
+    },
+    TokenReferenceRefreshScheduled {
+        token_id: TokenId,
+        reference: Option<String>,
+        ready_at: U64,
+    },
+    TokenReferenceRefreshed {
+        token_id: TokenId,
+        reference: Option<String>,
+        success: bool,
+    },
+    HolderMilestoneReached {
+        token_id: TokenId,
+        holders: U64,
+    },
+    StorageBalanceChanged {
+        account_id: AccountId,
+        old_balance: U128,
+        new_balance: U128,
+        reason: String,
+    },
+    SocialProfileLinked {
+        account_id: AccountId,
+        name: Option<String>,
+    },
+    TokenManagerAdded {
+        token_id: TokenId,
+        account_id: AccountId,
+    },
+    TokenManagerRemoved {
+        token_id: TokenId,
+        account_id: AccountId,
+    },
+    TokenFlaggedSimilar {
+        token_id: TokenId,
+        similar_to: String,
+        similarity_bps: u16,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: &'a FactoryEvent,
+}
+
+impl FactoryEvent {
+    pub fn emit(&self) {
+        let log = EventLog {
+            standard: EVENT_STANDARD,
+            version: EVENT_VERSION,
+            event: self,
+        };
+        env::log(format!("EVENT_JSON:{}", serde_json::to_string(&log).unwrap()).as_bytes());
+    }
+}
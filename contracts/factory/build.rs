@@ -0,0 +1,958 @@
+//! Builds the token and the namespace crates to wasm into `OUT_DIR`, where the factory embeds
+//! them from, so the embedded code can't fall behind their sources.
+//!
+//! Then checks that the token code exports the methods the factory calls on it. near-sdk 3.1
+//! doesn't embed an ABI into the code, so the parameters of the token's `new` are read from its
+//! source instead. They have to match the fields of `TokenInitArgs` of the `factory-types`
+//! crate, and every one of them has to be a field of `TokenArgs` that isn't in
+//! `REGISTRY_ONLY_FIELDS`, so the factory passes it on, apart from the `FACTORY_INIT_FIELDS`
+//! the factory adds itself.
+//!
+//! It also generates the ABI of the factory's methods in the near-abi format from the sources,
+//! which the factory returns from `__contract_abi` like contracts built with near-sdk 4, so
+//! near-cli-rs and the ABI codegen tools work with it.
+//!
+//! The build also fails if the token code grows past `MAX_TOKEN_WASM_SIZE` bytes, which
+//! defaults to the `MAX_TOKEN_CODE_SIZE` the factory accepts, so a dependency bump bloating the
+//! token is caught before it can't be deployed within the receipt limits anymore.
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const TOKEN_CRATE_DIR: &str = "../token";
+const TOKEN_WASM_NAME: &str = "fungible_token.wasm";
+const TOKEN_SOURCE_PATH: &str = "../token/src/lib.rs";
+/// Sources of `factory-types`, with the consts in `lib.rs` and the near-sdk types in `near.rs`.
+const TYPES_SOURCE_PATHS: &[&str] = &[
+    "../factory-types/src/lib.rs",
+    "../factory-types/src/near.rs",
+];
+const NAMESPACE_CRATE_DIR: &str = "../namespace";
+const NAMESPACE_WASM_NAME: &str = "token_namespace.wasm";
+/// Sources the token code is built from, besides its own crate.
+const SHARED_CRATE_DIRS: &[&str] = &["../factory-types"];
+const WASM_TARGET: &str = "wasm32-unknown-unknown";
+const MAX_TOKEN_WASM_SIZE_VAR: &str = "MAX_TOKEN_WASM_SIZE";
+/// Same as `MAX_TOKEN_CODE_SIZE` of the factory.
+const DEFAULT_MAX_TOKEN_WASM_SIZE: usize = 3 * 1024 * 1024;
+const FACTORY_SOURCE_DIR: &str = "src";
+/// Version of the near-abi format the ABI is generated in.
+const ABI_SCHEMA_VERSION: &str = "0.4.0";
+const ABI_JSON_NAME: &str = "token_factory_abi.json";
+const ABI_NAME: &str = "token_factory_abi.zst";
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const ZSTD_MAX_BLOCK_SIZE: usize = 128 * 1024;
+/// Types of near-contract-standards in the params and results of the factory's methods, in the
+/// form of their source, so their definitions are generated like the ones of the crate.
+const EXTERNAL_TYPES_SOURCE: &str = "
+#[derive(Serialize, Deserialize)]
+pub struct FungibleTokenMetadata {
+    pub spec: String,
+    pub name: String,
+    pub symbol: String,
+    pub icon: Option<String>,
+    pub reference: Option<String>,
+    pub reference_hash: Option<Base64VecU8>,
+    pub decimals: u8,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StorageBalance {
+    pub total: U128,
+    pub available: U128,
+}
+";
+const REQUIRED_EXPORTS: &[&str] = &["new", "ft_metadata", "update_reference"];
+const EXPORT_SECTION_ID: u8 = 7;
+const FUNCTION_EXPORT_KIND: u8 = 0;
+
+fn read_leb128(code: &[u8], offset: &mut usize) -> u32 {
+    let mut result = 0u32;
+    let mut shift = 0;
+    loop {
+        let byte = code[*offset];
+        *offset += 1;
+        result |= u32::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return result;
+        }
+        shift += 7;
+    }
+}
+
+fn function_exports(code: &[u8]) -> Vec<String> {
+    // Skips the magic and the version.
+    let mut offset = 8;
+    while offset < code.len() {
+        let section_id = code[offset];
+        offset += 1;
+        let section_size = read_leb128(code, &mut offset) as usize;
+        let section_end = offset + section_size;
+        if section_id == EXPORT_SECTION_ID {
+            let mut exports = vec![];
+            let count = read_leb128(code, &mut offset);
+            for _ in 0..count {
+                let name_length = read_leb128(code, &mut offset) as usize;
+                let name = String::from_utf8_lossy(&code[offset..offset + name_length]).to_string();
+                offset += name_length;
+                let kind = code[offset];
+                offset += 1;
+                read_leb128(code, &mut offset);
+                if kind == FUNCTION_EXPORT_KIND {
+                    exports.push(name);
+                }
+            }
+            return exports;
+        }
+        offset = section_end;
+    }
+    vec![]
+}
+
+/// Names of the parameters of the `new` method in the source. The parameter types of the token
+/// contain no commas or parentheses, so splitting the parameter list is enough.
+fn new_params(source: &str) -> Vec<String> {
+    let signature = "pub fn new(";
+    let start = source
+        .find(signature)
+        .expect("Token source has no new method")
+        + signature.len();
+    let end = start + source[start..].find(')').unwrap();
+    source[start..end]
+        .split(',')
+        .filter_map(|param| param.split(':').next())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Names of the public fields of the struct `name` in the source.
+fn struct_fields(source: &str, name: &str) -> Vec<String> {
+    let header = format!("pub struct {} {{", name);
+    let start = source
+        .find(&header)
+        .unwrap_or_else(|| panic!("Types source has no {}", name))
+        + header.len();
+    let end = start + source[start..].find("\n}").unwrap();
+    source[start..end]
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("pub "))
+        .filter_map(|field| field.split(':').next())
+        .map(|field| field.trim().to_string())
+        .collect()
+}
+
+/// Entries of the `name` list of fields in the source.
+fn field_list(source: &str, name: &str) -> Vec<String> {
+    let declaration = format!("pub const {}: &[&str] = &[", name);
+    let start = source
+        .find(&declaration)
+        .unwrap_or_else(|| panic!("Types source has no {}", name))
+        + declaration.len();
+    let end = start + source[start..].find("];").unwrap();
+    source[start..end]
+        .split(',')
+        .map(|field| field.trim().trim_matches('"').to_string())
+        .filter(|field| !field.is_empty())
+        .collect()
+}
+
+fn check_new_params() {
+    let token_source = fs::read_to_string(TOKEN_SOURCE_PATH).unwrap();
+    let types_source = TYPES_SOURCE_PATHS
+        .iter()
+        .map(|path| fs::read_to_string(path).unwrap())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let factory_init_fields = field_list(&types_source, "FACTORY_INIT_FIELDS");
+    let mut params = new_params(&token_source);
+    params.retain(|param| !factory_init_fields.contains(param));
+    let mut init_args_fields = struct_fields(&types_source, "TokenInitArgs");
+    let token_args_fields = struct_fields(&types_source, "TokenArgs");
+    let registry_only_fields = field_list(&types_source, "REGISTRY_ONLY_FIELDS");
+    for param in &params {
+        assert!(
+            token_args_fields.contains(param) && !registry_only_fields.contains(param),
+            "Token's new takes {}, which the factory doesn't pass from TokenArgs",
+            param
+        );
+    }
+    params.sort();
+    init_args_fields.sort();
+    assert_eq!(
+        params, init_args_fields,
+        "Token's new takes other parameters than the fields of TokenInitArgs"
+    );
+}
+
+/// Builds the contract crate at `crate_dir` the way its `build.sh` does, into a target dir of
+/// its own under `OUT_DIR` so it doesn't wait on the lock of the factory's build, and copies
+/// the code to `OUT_DIR/<wasm_name>`.
+fn build_contract(crate_dir: &str, wasm_name: &str) -> Vec<u8> {
+    for path in &["src", "Cargo.toml"] {
+        println!("cargo:rerun-if-changed={}/{}", crate_dir, path);
+    }
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let target_dir = out_dir.join("contracts");
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let status = Command::new(cargo)
+        .args(&[
+            "build",
+            "--release",
+            "--target",
+            WASM_TARGET,
+            "--manifest-path",
+        ])
+        .arg(Path::new(crate_dir).join("Cargo.toml"))
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .env("RUSTFLAGS", "-C link-arg=-s")
+        .env_remove("CARGO_TARGET_DIR")
+        .status()
+        .unwrap_or_else(|error| panic!("Can't run cargo to build {}: {}", crate_dir, error));
+    assert!(status.success(), "Building {} failed", crate_dir);
+    let code = fs::read(target_dir.join(WASM_TARGET).join("release").join(wasm_name))
+        .unwrap_or_else(|error| panic!("Can't read the code of {}: {}", crate_dir, error));
+    fs::write(out_dir.join(wasm_name), &code).unwrap();
+    code
+}
+
+/// JSON value of the generated ABI. The build script only depends on `std`, so it writes the
+/// JSON itself.
+enum Json {
+    Number(u64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn string(value: &str) -> Self {
+        Json::String(value.to_string())
+    }
+
+    fn object(fields: Vec<(&str, Json)>) -> Self {
+        Json::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    fn write(&self, out: &mut String) {
+        match self {
+            Json::Number(value) => out.push_str(&value.to_string()),
+            Json::String(value) => write_json_string(value, out),
+            Json::Array(values) => {
+                out.push('[');
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        out.push(',');
+                    }
+                    value.write(out);
+                }
+                out.push(']');
+            }
+            Json::Object(fields) => {
+                out.push('{');
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        out.push(',');
+                    }
+                    write_json_string(key, out);
+                    out.push(':');
+                    value.write(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+}
+
+fn write_json_string(value: &str, out: &mut String) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Method of a `#[near_bindgen]` impl block, with its doc comment and attributes.
+struct BindgenFunction {
+    name: String,
+    doc: Vec<String>,
+    attributes: Vec<String>,
+    receiver: Option<String>,
+    params: Vec<(String, String)>,
+    result: Option<String>,
+}
+
+/// Splits the text at the commas outside of brackets.
+fn split_top_level(text: &str) -> Vec<String> {
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut part = String::new();
+    for c in text.chars() {
+        match c {
+            '<' | '(' | '[' => depth += 1,
+            '>' | ')' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(part.trim().to_string());
+                part.clear();
+                continue;
+            }
+            _ => {}
+        }
+        part.push(c);
+    }
+    parts.push(part.trim().to_string());
+    parts.into_iter().filter(|part| !part.is_empty()).collect()
+}
+
+/// Parses a signature like `pub fn name(&self, a: A) -> R {` joined onto one line.
+fn parse_signature(signature: &str, doc: Vec<String>, attributes: Vec<String>) -> BindgenFunction {
+    let start = signature.find("fn ").unwrap() + 3;
+    let params_start = start + signature[start..].find('(').unwrap();
+    let name = signature[start..params_start].trim().to_string();
+    let mut depth = 0;
+    let mut params_end = params_start;
+    for (offset, c) in signature[params_start..].char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            params_end = params_start + offset;
+            break;
+        }
+    }
+    let mut receiver = None;
+    let mut params = vec![];
+    for param in split_top_level(&signature[params_start + 1..params_end]) {
+        if param.ends_with("self") {
+            receiver = Some(param);
+            continue;
+        }
+        let colon = param.find(':').unwrap();
+        let name = param[..colon].trim();
+        let name = name.strip_prefix("mut ").unwrap_or(name).to_string();
+        params.push((name, param[colon + 1..].trim().to_string()));
+    }
+    let rest = signature[params_end + 1..]
+        .trim()
+        .trim_end_matches('{')
+        .trim();
+    let result = rest
+        .strip_prefix("->")
+        .map(|result| result.trim().to_string());
+    BindgenFunction {
+        name,
+        doc,
+        attributes,
+        receiver,
+        params,
+        result,
+    }
+}
+
+/// Methods of the `#[near_bindgen]` impl blocks in the source, which rustfmt lays out with the
+/// blocks at the top level and their methods indented by four spaces. Blocks behind the `dev`
+/// feature are only included when it is enabled.
+fn bindgen_functions(source: &str, dev: bool) -> Vec<BindgenFunction> {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut functions = vec![];
+    let mut in_block = false;
+    let mut trait_impl = false;
+    let mut doc = vec![];
+    let mut attributes = vec![];
+    let mut index = 0;
+    while index < lines.len() {
+        let line = lines[index];
+        if !in_block {
+            if line == "#[near_bindgen]" {
+                let dev_only = index > 0 && lines[index - 1] == "#[cfg(feature = \"dev\")]";
+                let mut next = index + 1;
+                while lines[next].starts_with("#[") {
+                    next += 1;
+                }
+                if lines[next].starts_with("impl ") && (dev || !dev_only) {
+                    in_block = true;
+                    trait_impl = lines[next].contains(" for ");
+                    index = next;
+                }
+            }
+            index += 1;
+            continue;
+        }
+        if line == "}" {
+            in_block = false;
+        } else if let Some(member) = line
+            .strip_prefix("    ")
+            .filter(|member| !member.starts_with(' '))
+        {
+            if let Some(text) = member.strip_prefix("///") {
+                doc.push(text.trim().to_string());
+            } else if member.starts_with("#[") {
+                attributes.push(member.to_string());
+            } else if member.starts_with("pub fn ") || (trait_impl && member.starts_with("fn ")) {
+                let mut signature = String::new();
+                while !signature.trim_end().ends_with('{') {
+                    signature.push_str(lines[index].trim());
+                    signature.push(' ');
+                    index += 1;
+                }
+                functions.push(parse_signature(&signature, doc, attributes));
+                doc = vec![];
+                attributes = vec![];
+                continue;
+            } else {
+                doc.clear();
+                attributes.clear();
+            }
+        }
+        index += 1;
+    }
+    functions
+}
+
+/// Splits a type like `Option<Vec<T>>` into `Option` and its generic arguments.
+fn generic_args(ty: &str) -> (String, Vec<String>) {
+    match ty.find('<') {
+        Some(start) if ty.ends_with('>') => (
+            ty[..start].to_string(),
+            split_top_level(&ty[start + 1..ty.len() - 1]),
+        ),
+        _ => (ty.to_string(), vec![]),
+    }
+}
+
+/// JSON schema of the Rust type, in the form schemars gives to the ABI of near-sdk 4. Types
+/// defined in the sources are referenced, and added to `references` for their definitions.
+fn type_schema(ty: &str, references: &mut Vec<String>) -> Json {
+    let ty = ty.trim().trim_start_matches('&').trim();
+    if ty.starts_with('(') {
+        let items: Vec<Json> = split_top_level(&ty[1..ty.len() - 1])
+            .iter()
+            .map(|item| type_schema(item, references))
+            .collect();
+        return Json::object(vec![
+            ("type", Json::string("array")),
+            ("minItems", Json::Number(items.len() as u64)),
+            ("maxItems", Json::Number(items.len() as u64)),
+            ("items", Json::Array(items)),
+        ]);
+    }
+    let (base, args) = generic_args(ty);
+    let base = base.rsplit("::").next().unwrap().to_string();
+    match base.as_str() {
+        "String" | "str" | "char" | "AccountId" | "ValidAccountId" | "TokenId" => {
+            Json::object(vec![("type", Json::string("string"))])
+        }
+        "U128" | "U64" | "Base58PublicKey" | "Base64VecU8" => Json::object(vec![
+            ("type", Json::string("string")),
+            (
+                "description",
+                Json::string(&format!("{} of near-sdk", base)),
+            ),
+        ]),
+        "bool" => Json::object(vec![("type", Json::string("boolean"))]),
+        "u8" | "u16" | "u32" | "u64" | "u128" | "Gas" | "Balance" => {
+            let bits = match base.as_str() {
+                "Gas" => "64",
+                "Balance" => "128",
+                _ => &base[1..],
+            };
+            Json::object(vec![
+                ("type", Json::string("integer")),
+                ("format", Json::String(format!("uint{}", bits))),
+                ("minimum", Json::Number(0)),
+            ])
+        }
+        "Option" if args.len() == 1 => Json::object(vec![(
+            "anyOf",
+            Json::Array(vec![
+                type_schema(&args[0], references),
+                Json::object(vec![("type", Json::string("null"))]),
+            ]),
+        )]),
+        "Vec" | "HashSet" | "BTreeSet" if args.len() == 1 => Json::object(vec![
+            ("type", Json::string("array")),
+            ("items", type_schema(&args[0], references)),
+        ]),
+        "HashMap" | "BTreeMap" if args.len() == 2 => Json::object(vec![
+            ("type", Json::string("object")),
+            ("additionalProperties", type_schema(&args[1], references)),
+        ]),
+        "PromiseOrValue" | "Box" if args.len() == 1 => type_schema(&args[0], references),
+        "Value" => Json::object(vec![]),
+        _ => {
+            if !references.contains(&base) {
+                references.push(base.clone());
+            }
+            Json::object(vec![(
+                "$ref",
+                Json::String(format!("#/definitions/{}", base)),
+            )])
+        }
+    }
+}
+
+fn to_snake_case(name: &str) -> String {
+    let mut snake_case = String::new();
+    for (index, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if index > 0 {
+                snake_case.push('_');
+            }
+            snake_case.push(c.to_ascii_lowercase());
+        } else {
+            snake_case.push(c);
+        }
+    }
+    snake_case
+}
+
+/// Value of `key = "value"` in an attribute like `#[serde(tag = "event", content = "data")]`.
+fn serde_attribute(attributes: &[String], key: &str) -> Option<String> {
+    let pattern = format!("{} = \"", key);
+    attributes
+        .iter()
+        .filter(|attribute| attribute.starts_with("#[serde("))
+        .find_map(|attribute| {
+            let start = attribute.find(&pattern)? + pattern.len();
+            let end = start + attribute[start..].find('"')?;
+            Some(attribute[start..end].to_string())
+        })
+}
+
+fn has_serde_flag(attributes: &[String], flag: &str) -> bool {
+    attributes.iter().any(|attribute| {
+        attribute.strip_prefix("#[serde(").map_or(false, |args| {
+            args.trim_end_matches(")]")
+                .split(',')
+                .any(|arg| arg.trim() == flag || arg.trim().starts_with(&format!("{} =", flag)))
+        })
+    })
+}
+
+/// Object schema of the fields declared on the lines, each `name: Type,` after its attributes.
+fn fields_schema(lines: &[&str], references: &mut Vec<String>) -> Json {
+    let mut properties = vec![];
+    let mut required = vec![];
+    let mut flattened = vec![];
+    let mut attributes = vec![];
+    for line in lines {
+        let line = line.trim();
+        if line.starts_with("#[") {
+            attributes.push(line.to_string());
+            continue;
+        }
+        if line.starts_with("//") || line.is_empty() {
+            continue;
+        }
+        let field = line
+            .strip_prefix("pub(crate) ")
+            .or_else(|| line.strip_prefix("pub "))
+            .unwrap_or(line)
+            .trim_end_matches(',');
+        let colon = match field.find(':') {
+            Some(colon) => colon,
+            None => continue,
+        };
+        let name = field[..colon].trim().to_string();
+        let ty = field[colon + 1..].trim();
+        if has_serde_flag(&attributes, "skip") {
+            attributes.clear();
+            continue;
+        }
+        if has_serde_flag(&attributes, "flatten") {
+            flattened.push(type_schema(ty, references));
+        } else {
+            if !ty.starts_with("Option<") && !has_serde_flag(&attributes, "default") {
+                required.push(Json::String(name.clone()));
+            }
+            properties.push((name, type_schema(ty, references)));
+        }
+        attributes.clear();
+    }
+    let object = Json::Object(vec![
+        ("type".to_string(), Json::string("object")),
+        ("properties".to_string(), Json::Object(properties)),
+        ("required".to_string(), Json::Array(required)),
+    ]);
+    if flattened.is_empty() {
+        object
+    } else {
+        flattened.insert(0, object);
+        Json::object(vec![("allOf", Json::Array(flattened))])
+    }
+}
+
+/// Body lines of the item starting at `header`, up to its closing brace at the top level.
+fn item_lines<'a>(lines: &[&'a str], header: usize) -> Vec<&'a str> {
+    lines[header + 1..]
+        .iter()
+        .take_while(|line| !line.starts_with('}'))
+        .cloned()
+        .collect()
+}
+
+/// Doc comment and attributes on the lines above the item at `header`.
+fn item_attributes(lines: &[&str], header: usize) -> (String, Vec<String>) {
+    let mut doc = vec![];
+    let mut attributes = vec![];
+    for line in lines[..header].iter().rev() {
+        if let Some(text) = line.strip_prefix("///") {
+            doc.insert(0, text.trim().to_string());
+        } else if line.starts_with("#[") {
+            attributes.insert(0, line.to_string());
+        } else {
+            break;
+        }
+    }
+    (doc.join("\n"), attributes)
+}
+
+/// Schema of one variant of an enum with the serde representation of the attributes.
+fn variant_schema(name: &str, body: Option<Json>, attributes: &[String]) -> Json {
+    let name = match serde_attribute(attributes, "rename_all") {
+        Some(_) => to_snake_case(name),
+        None => name.to_string(),
+    };
+    let tag_schema = Json::object(vec![
+        ("type", Json::string("string")),
+        ("enum", Json::Array(vec![Json::String(name.clone())])),
+    ]);
+    match (
+        serde_attribute(attributes, "tag"),
+        serde_attribute(attributes, "content"),
+        body,
+    ) {
+        (None, _, None) => tag_schema,
+        (None, _, Some(body)) => Json::Object(vec![
+            ("type".to_string(), Json::string("object")),
+            (
+                "properties".to_string(),
+                Json::Object(vec![(name.clone(), body)]),
+            ),
+            (
+                "required".to_string(),
+                Json::Array(vec![Json::String(name)]),
+            ),
+        ]),
+        (Some(tag), None, body) => {
+            let tag_object = Json::Object(vec![
+                ("type".to_string(), Json::string("object")),
+                (
+                    "properties".to_string(),
+                    Json::Object(vec![(tag.clone(), tag_schema)]),
+                ),
+                ("required".to_string(), Json::Array(vec![Json::String(tag)])),
+            ]);
+            match body {
+                Some(body) => Json::object(vec![("allOf", Json::Array(vec![tag_object, body]))]),
+                None => tag_object,
+            }
+        }
+        (Some(tag), Some(content), body) => {
+            let mut properties = vec![(tag.clone(), tag_schema)];
+            let mut required = vec![Json::String(tag)];
+            if let Some(body) = body {
+                properties.push((content.clone(), body));
+                required.push(Json::String(content));
+            }
+            Json::Object(vec![
+                ("type".to_string(), Json::string("object")),
+                ("properties".to_string(), Json::Object(properties)),
+                ("required".to_string(), Json::Array(required)),
+            ])
+        }
+    }
+}
+
+/// Schema of the variants of an enum, each a unit `Name,`, a newtype `Name(Type),` or a struct
+/// `Name {` with its fields on the following lines.
+fn enum_schema(body: &[&str], attributes: &[String], references: &mut Vec<String>) -> Json {
+    let mut variants = vec![];
+    let mut index = 0;
+    while index < body.len() {
+        let line = body[index].trim();
+        index += 1;
+        if line.starts_with("//") || line.starts_with("#[") || line.is_empty() {
+            continue;
+        }
+        let variant = if let Some(name) = line.strip_suffix(" {") {
+            let start = index;
+            while !body[index].trim().starts_with('}') {
+                index += 1;
+            }
+            let fields = fields_schema(&body[start..index], references);
+            index += 1;
+            variant_schema(name, Some(fields), attributes)
+        } else if let Some(start) = line.find('(') {
+            let end = line.rfind(')').unwrap();
+            let inner = type_schema(&line[start + 1..end], references);
+            variant_schema(&line[..start], Some(inner), attributes)
+        } else {
+            variant_schema(line.trim_end_matches(','), None, attributes)
+        };
+        variants.push(variant);
+    }
+    Json::object(vec![("oneOf", Json::Array(variants))])
+}
+
+/// Definition of the type `name` declared in the sources. Types with a custom serialization are
+/// only described by their doc comment.
+fn type_definition(name: &str, sources: &[String], references: &mut Vec<String>) -> Json {
+    for source in sources {
+        let lines: Vec<&str> = source.lines().collect();
+        for (header, line) in lines.iter().enumerate() {
+            let (kind, ty) = if *line == format!("pub struct {} {{", name) {
+                ("struct", None)
+            } else if *line == format!("pub enum {} {{", name) {
+                ("enum", None)
+            } else if let Some(ty) = line.strip_prefix(&format!("pub type {} = ", name)) {
+                ("type", Some(ty.trim_end_matches(';')))
+            } else {
+                continue;
+            };
+            if let Some(ty) = ty {
+                return type_schema(ty, references);
+            }
+            let (doc, attributes) = item_attributes(&lines, header);
+            let derived = attributes
+                .iter()
+                .filter_map(|attribute| attribute.strip_prefix("#[derive("))
+                .flat_map(|derives| derives.trim_end_matches(")]").split(','))
+                .any(|derive| derive.trim() == "Serialize");
+            let body = item_lines(&lines, header);
+            let mut schema = match (derived, kind) {
+                (false, _) => vec![],
+                (true, "struct") => match fields_schema(&body, references) {
+                    Json::Object(fields) => fields,
+                    _ => unreachable!(),
+                },
+                (true, _) => match enum_schema(&body, &attributes, references) {
+                    Json::Object(fields) => fields,
+                    _ => unreachable!(),
+                },
+            };
+            if !doc.is_empty() {
+                schema.insert(0, ("description".to_string(), Json::String(doc)));
+            }
+            return Json::Object(schema);
+        }
+    }
+    panic!(
+        "The sources don't declare {}, add it to EXTERNAL_TYPES_SOURCE",
+        name
+    )
+}
+
+/// ABI entry of a method in the format of near-abi.
+fn abi_function(function: &BindgenFunction, references: &mut Vec<String>) -> Json {
+    let init = function
+        .attributes
+        .iter()
+        .any(|attribute| attribute.starts_with("#[init"));
+    let mut modifiers = vec![];
+    if init {
+        modifiers.push(Json::string("init"));
+    }
+    for (attribute, modifier) in &[("#[payable]", "payable"), ("#[private]", "private")] {
+        if function.attributes.iter().any(|a| a == attribute) {
+            modifiers.push(Json::string(modifier));
+        }
+    }
+    let kind = match function.receiver.as_deref() {
+        Some("&self") | None if !init => "view",
+        _ => "call",
+    };
+    let mut fields = vec![("name", Json::String(function.name.clone()))];
+    if !function.doc.is_empty() {
+        fields.push(("doc", Json::String(function.doc.join("\n"))));
+    }
+    fields.push(("kind", Json::string(kind)));
+    if !modifiers.is_empty() {
+        fields.push(("modifiers", Json::Array(modifiers)));
+    }
+    if !function.params.is_empty() {
+        let args = function
+            .params
+            .iter()
+            .map(|(name, ty)| {
+                Json::object(vec![
+                    ("name", Json::String(name.clone())),
+                    ("type_schema", type_schema(ty, references)),
+                ])
+            })
+            .collect();
+        fields.push((
+            "params",
+            Json::object(vec![
+                ("serialization_type", Json::string("json")),
+                ("args", Json::Array(args)),
+            ]),
+        ));
+    }
+    if let Some(result) = &function.result {
+        if result != "Self" && result != "Promise" {
+            fields.push((
+                "result",
+                Json::object(vec![
+                    ("serialization_type", Json::string("json")),
+                    ("type_schema", type_schema(result, references)),
+                ]),
+            ));
+        }
+    }
+    Json::object(fields)
+}
+
+/// Generates the ABI of the factory's methods from the sources of the crate, and the types of
+/// their params and results from the sources of the crate and `factory-types`.
+fn generate_abi() -> String {
+    let dev = env::var("CARGO_FEATURE_DEV").is_ok();
+    let mut sources = vec![];
+    for entry in fs::read_dir(FACTORY_SOURCE_DIR).unwrap() {
+        let path = entry.unwrap().path();
+        if path
+            .extension()
+            .map_or(false, |extension| extension == "rs")
+        {
+            sources.push(fs::read_to_string(path).unwrap());
+        }
+    }
+    let mut functions: Vec<BindgenFunction> = sources
+        .iter()
+        .flat_map(|source| bindgen_functions(source, dev))
+        .collect();
+    functions.sort_by(|a, b| a.name.cmp(&b.name));
+    for path in TYPES_SOURCE_PATHS {
+        sources.push(fs::read_to_string(path).unwrap());
+    }
+    sources.push(EXTERNAL_TYPES_SOURCE.to_string());
+    let mut references = vec![];
+    let functions = functions
+        .iter()
+        .map(|function| abi_function(function, &mut references))
+        .collect();
+    let mut definitions = BTreeMap::new();
+    while let Some(name) = references
+        .iter()
+        .find(|name| !definitions.contains_key(*name))
+        .cloned()
+    {
+        let definition = type_definition(&name, &sources, &mut references);
+        definitions.insert(name, definition);
+    }
+    let abi = Json::object(vec![
+        ("schema_version", Json::string(ABI_SCHEMA_VERSION)),
+        (
+            "metadata",
+            Json::object(vec![
+                ("name", Json::String(env::var("CARGO_PKG_NAME").unwrap())),
+                (
+                    "version",
+                    Json::String(env::var("CARGO_PKG_VERSION").unwrap()),
+                ),
+            ]),
+        ),
+        (
+            "body",
+            Json::object(vec![
+                ("functions", Json::Array(functions)),
+                (
+                    "root_schema",
+                    Json::object(vec![
+                        (
+                            "$schema",
+                            Json::string("http://json-schema.org/draft-07/schema#"),
+                        ),
+                        ("title", Json::string("String")),
+                        ("type", Json::string("string")),
+                        (
+                            "definitions",
+                            Json::Object(definitions.into_iter().collect()),
+                        ),
+                    ]),
+                ),
+            ]),
+        ),
+    ]);
+    let mut json = String::new();
+    abi.write(&mut json);
+    json
+}
+
+/// Wraps the data into a zstd frame of raw blocks. Readers of `__contract_abi` decompress it like
+/// the frames near-sdk 4 embeds, and the ABI is small enough to be stored uncompressed.
+fn zstd_raw_frame(data: &[u8]) -> Vec<u8> {
+    let mut frame = ZSTD_MAGIC.to_vec();
+    // Single segment with an 8 byte content size and no checksum.
+    frame.push(0xe0);
+    frame.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    let mut blocks: Vec<&[u8]> = data.chunks(ZSTD_MAX_BLOCK_SIZE).collect();
+    if blocks.is_empty() {
+        blocks.push(&[]);
+    }
+    for (index, block) in blocks.iter().enumerate() {
+        let last = index + 1 == blocks.len();
+        // Block type 0 is a raw block.
+        let header = ((block.len() as u32) << 3) | last as u32;
+        frame.extend_from_slice(&header.to_le_bytes()[..3]);
+        frame.extend_from_slice(block);
+    }
+    frame
+}
+
+fn main() {
+    println!("cargo:rerun-if-env-changed={}", MAX_TOKEN_WASM_SIZE_VAR);
+    for crate_dir in SHARED_CRATE_DIRS {
+        for path in &["src", "Cargo.toml"] {
+            println!("cargo:rerun-if-changed={}/{}", crate_dir, path);
+        }
+    }
+    build_contract(NAMESPACE_CRATE_DIR, NAMESPACE_WASM_NAME);
+    let code = build_contract(TOKEN_CRATE_DIR, TOKEN_WASM_NAME);
+    let max_size = env::var(MAX_TOKEN_WASM_SIZE_VAR).map_or(DEFAULT_MAX_TOKEN_WASM_SIZE, |size| {
+        size.parse()
+            .unwrap_or_else(|_| panic!("{} has to be a number of bytes", MAX_TOKEN_WASM_SIZE_VAR))
+    });
+    assert!(
+        code.len() <= max_size,
+        "Token code of {} has {} bytes, more than the {} bytes allowed by {}",
+        TOKEN_CRATE_DIR,
+        code.len(),
+        max_size,
+        MAX_TOKEN_WASM_SIZE_VAR
+    );
+    // The unit tests check the embedded code against the same threshold.
+    println!("cargo:rustc-env={}={}", MAX_TOKEN_WASM_SIZE_VAR, max_size);
+    let exports = function_exports(&code);
+    for name in REQUIRED_EXPORTS {
+        assert!(
+            exports.iter().any(|export| export == name),
+            "Token code of {} doesn't export {}",
+            TOKEN_CRATE_DIR,
+            name
+        );
+    }
+    check_new_params();
+
+    println!("cargo:rerun-if-changed={}", FACTORY_SOURCE_DIR);
+    let abi = generate_abi();
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    fs::write(out_dir.join(ABI_JSON_NAME), &abi).unwrap();
+    fs::write(out_dir.join(ABI_NAME), zstd_raw_frame(abi.as_bytes())).unwrap();
+}
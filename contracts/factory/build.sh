@@ -2,5 +2,6 @@
 set -e
 
 RUSTFLAGS='-C link-arg=-s' cargo build --target wasm32-unknown-unknown --release
+mkdir -p ./res
 cp target/wasm32-unknown-unknown/release/token_factory.wasm ./res/
 
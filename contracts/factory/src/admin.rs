@@ -0,0 +1,1027 @@
+//! Owner and moderator methods: code, templates, configuration, roles and moderation lists.
+use crate::*;
+
+/// Checks a new config, including that its gas fits into the factory's prepaid gas limit.
+fn assert_valid_config(config: &Config) {
+    assert!(
+        config.referral_fee_bps <= MAX_BPS,
+        "Referral fee can't exceed 100%"
+    );
+    assert!(
+        CREATE_TOKEN_GAS
+            + config.init_gas
+            + std::cmp::max(
+                4 * config.callback_gas,
+                NAMESPACE_INIT_GAS + NAMESPACE_CALL_GAS + 2 * config.callback_gas
+            )
+            <= MAX_PREPAID_GAS,
+        "Gas configuration doesn't fit into the maximum prepaid gas of {}",
+        MAX_PREPAID_GAS
+    );
+    assert!(
+        config.min_decimals <= config.max_decimals,
+        "Minimum decimals can't exceed the maximum decimals"
+    );
+    assert!(
+        config.min_commit_age_blocks < config.max_commit_age_blocks,
+        "Symbol commitments have to be able to mature before they expire"
+    );
+    if config.launch_certificate.is_some() {
+        assert!(
+            config.callback_gas > NFT_MINT_GAS,
+            "Callback gas has to cover minting the launch certificate"
+        );
+    }
+    if let Some(max_storage_balance) = config.max_storage_balance {
+        assert!(
+            config.min_storage_deposit.0 <= max_storage_balance.0,
+            "Minimum storage deposit can't exceed the maximum storage balance"
+        );
+    }
+    assert!(
+        config.min_similarity_bps > 0 && config.min_similarity_bps <= MAX_BPS,
+        "Minimum similarity has to be between 1 and 10000 basis points"
+    );
+    if config.token_namespace_id.is_some() {
+        // The namespace creates the account and deploys the code in a single call.
+        assert!(
+            !config.split_deployment,
+            "The split deployment is not supported with a token namespace"
+        );
+    }
+}
+
+#[near_bindgen]
+impl TokenFactory {
+    /// Replaces the code deployed to new tokens. The blob has to be a WebAssembly module of at
+    /// most `MAX_TOKEN_CODE_SIZE` bytes, matching `expected_hash` if given. Passing no code
+    /// restores the built-in fungible token. Already deployed tokens are not affected.
+    pub fn set_token_code(
+        &mut self,
+        code: Option<Base64VecU8>,
+        expected_hash: Option<Base64VecU8>,
+    ) -> TokenCodeUpdate {
+        self.assert_owner();
+        self.internal_log_admin_action(
+            "set_token_code",
+            serde_json::json!({"reset": code.is_none(), "expected_hash": expected_hash}),
+        );
+        let previous_code_storage = self.get_code_storage_cost(None);
+        let code = code.map(|code| code.0);
+        let info = assert_valid_token_code(code.as_deref().unwrap_or(FT_WASM_CODE), expected_hash);
+        if let Some(code) = &code {
+            self.token_code.set(code);
+        } else {
+            self.token_code.remove();
+        }
+        self.token_code_info = info.clone();
+        TokenCodeUpdate {
+            code: info,
+            previous_code_storage: previous_code_storage.into(),
+            code_storage: self.get_code_storage_cost(None).into(),
+        }
+    }
+
+    /// Registers or replaces a template that creators can select with the `template` of the
+    /// token args. The code is validated like in `set_token_code`, and `init_schema` has to be
+    /// a JSON schema object describing the init args of the template.
+    pub fn register_template(
+        &mut self,
+        name: String,
+        code: Base64VecU8,
+        init_schema: String,
+        expected_hash: Option<Base64VecU8>,
+    ) -> Template {
+        self.assert_owner();
+        self.internal_log_admin_action(
+            "register_template",
+            serde_json::json!({"name": name, "expected_hash": expected_hash}),
+        );
+        assert!(
+            name != DEFAULT_TEMPLATE
+                && !name.is_empty()
+                && name.len() <= MAX_TEMPLATE_NAME_LENGTH
+                && name
+                    .chars()
+                    .all(|c| matches!(c, '0'..='9' | 'a'..='z' | '-')),
+            "Template name is invalid"
+        );
+        assert!(
+            serde_json::from_str::<serde_json::Value>(&init_schema)
+                .map_or(false, |schema| schema.is_object()),
+            "Init schema has to be a JSON object"
+        );
+        let template = Template {
+            code: assert_valid_token_code(&code.0, expected_hash),
+            init_schema,
+            enabled: true,
+            storage_balance_bounds: None,
+            owner_registration: None,
+        };
+        self.template_code.insert(&name, &code.0);
+        self.templates.insert(&name, &template);
+        template
+    }
+
+    pub fn set_template_enabled(&mut self, name: String, enabled: bool) {
+        self.assert_owner();
+        self.internal_log_admin_action(
+            "set_template_enabled",
+            serde_json::json!({"name": name, "enabled": enabled}),
+        );
+        let mut template = self.templates.get(&name).expect("Template not found");
+        template.enabled = enabled;
+        self.templates.insert(&name, &template);
+    }
+
+    /// Declares the storage balance bounds of the template and the deposit of the owner
+    /// registration chained after its `new`, which has to be within the bounds. Passing no
+    /// deposit stops registering the owner.
+    pub fn set_template_owner_registration(
+        &mut self,
+        name: String,
+        storage_balance_bounds: TemplateStorageBounds,
+        owner_registration: Option<U128>,
+    ) {
+        self.assert_owner();
+        self.internal_log_admin_action(
+            "set_template_owner_registration",
+            serde_json::json!({
+                "name": name,
+                "storage_balance_bounds": storage_balance_bounds,
+                "owner_registration": owner_registration,
+            }),
+        );
+        let mut template = self.templates.get(&name).expect("Template not found");
+        if let Some(owner_registration) = owner_registration {
+            assert!(
+                owner_registration.0 >= storage_balance_bounds.min.0
+                    && storage_balance_bounds
+                        .max
+                        .map_or(true, |max| owner_registration.0 <= max.0),
+                "Owner registration has to be within the storage balance bounds of the template"
+            );
+        }
+        template.storage_balance_bounds = Some(storage_balance_bounds);
+        template.owner_registration = owner_registration;
+        self.templates.insert(&name, &template);
+    }
+
+    /// Creates or updates a campaign, which creators launch tokens in with the `campaign` of the
+    /// args.
+    pub fn create_campaign(&mut self, name: String, settings: CampaignSettings) -> Campaign {
+        self.assert_owner();
+        self.internal_log_admin_action(
+            "create_campaign",
+            serde_json::json!({"name": name, "settings": settings}),
+        );
+        assert!(
+            !name.is_empty()
+                && name.len() <= MAX_TEMPLATE_NAME_LENGTH
+                && name
+                    .chars()
+                    .all(|c| matches!(c, '0'..='9' | 'a'..='z' | '-')),
+            "Campaign name is invalid"
+        );
+        assert!(
+            settings.fee_discount_bps <= MAX_BPS,
+            "Fee discount can't exceed 100%"
+        );
+        if let Some(template) = &settings.template {
+            assert!(
+                self.templates.get(template).is_some(),
+                "Template {} not found",
+                template
+            );
+        }
+        let campaign = Campaign {
+            settings,
+            tokens: self
+                .campaigns
+                .get(&name)
+                .map_or(0, |campaign| campaign.tokens),
+        };
+        self.campaigns.insert(&name, &campaign);
+        campaign
+    }
+
+    /// Audits the given token records after the factory state was copied from
+    /// `old_factory_id`. Every token is probed with `ft_metadata` on its sub-account of the old
+    /// factory, and only the tokens answering there with their symbol are marked as detached by
+    /// `on_rebind_probed`, so the views return their actual accounts. Meant to be run over all
+    /// tokens right after the migration, before new tokens are created. Returns the number of
+    /// tokens probed.
+    pub fn rebind_registry(
+        &mut self,
+        old_factory_id: ValidAccountId,
+        token_ids: Vec<TokenId>,
+    ) -> u64 {
+        self.assert_not_migrating();
+        self.assert_owner();
+        self.internal_log_admin_action(
+            "rebind_registry",
+            serde_json::json!({
+                "old_factory_id": old_factory_id,
+                "token_ids": token_ids,
+            }),
+        );
+        assert!(
+            token_ids.len() as u64 <= MAX_PING_PAGE_SIZE,
+            "Can't rebind more than {} tokens at once",
+            MAX_PING_PAGE_SIZE
+        );
+        let old_factory_id: AccountId = old_factory_id.into();
+        if old_factory_id == env::current_account_id() {
+            return 0;
+        }
+        let mut probed = 0;
+        for token_id in token_ids {
+            let token = self.tokens.get(&token_id).expect("Token not found");
+            if token.detached_account_id.is_some() {
+                continue;
+            }
+            assert!(
+                env::prepaid_gas() - env::used_gas() >= PING_GAS + PING_CALLBACK_GAS,
+                "Not enough gas attached to probe the tokens"
+            );
+            let account_id = format!("{}.{}", token_id, old_factory_id);
+            ext_ft::ft_metadata(&account_id, 0, PING_GAS).then(ext_self::on_rebind_probed(
+                token_id,
+                account_id.clone(),
+                &env::current_account_id(),
+                0,
+                PING_CALLBACK_GAS,
+            ));
+            probed += 1;
+        }
+        probed
+    }
+
+    /// Marks the token as detached to `account_id` if the account answered the probe of
+    /// `rebind_registry` with the symbol of the token.
+    #[private]
+    pub fn on_rebind_probed(&mut self, token_id: TokenId, account_id: AccountId) -> bool {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "Contract expected a result on the callback"
+        );
+        let metadata = match env::promise_result(0) {
+            PromiseResult::Successful(data) => {
+                serde_json::from_slice::<FungibleTokenMetadata>(&data).ok()
+            }
+            _ => None,
+        };
+        let mut token = match self.tokens.get(&token_id) {
+            Some(token) if token.detached_account_id.is_none() => token,
+            _ => return false,
+        };
+        let verified = metadata.map_or(false, |metadata| {
+            metadata.symbol == token.args.metadata.symbol
+        });
+        if verified {
+            token.detached_account_id = Some(account_id);
+            self.tokens.insert(&token_id, &token);
+        }
+        verified
+    }
+
+    /// Recomputes the cached limits, e.g. after the factory state was moved to another account.
+    pub fn update_limits(&mut self) -> Limits {
+        self.assert_owner();
+        self.internal_log_admin_action("update_limits", serde_json::json!({}));
+        self.limits = Limits::new(&self.internal_token_parent_id());
+        self.limits.clone()
+    }
+
+    pub fn set_owner(&mut self, owner_id: ValidAccountId) {
+        self.assert_owner();
+        self.internal_log_admin_action("set_owner", serde_json::json!({ "owner_id": owner_id }));
+        let old_owner_id = std::mem::replace(&mut self.owner_id, owner_id.into());
+        self.internal_emit(FactoryEvent::OwnerChanged {
+            old_owner_id,
+            new_owner_id: self.owner_id.clone(),
+        });
+    }
+
+    pub fn set_config(&mut self, config: Config) {
+        self.assert_owner();
+        self.internal_log_admin_action("set_config", serde_json::json!({ "config": config }));
+        assert_valid_config(&config);
+        let namespace_changed = config.token_namespace_id != self.config.token_namespace_id;
+        if namespace_changed {
+            // Token account ids are derived from the namespace, so existing records would point
+            // to the wrong accounts.
+            assert!(
+                self.tokens.is_empty()
+                    && self.proposals.is_empty()
+                    && self.deployments.is_empty()
+                    && self.creation_queue.is_empty()
+                    && self.preprovisioned_accounts.is_empty(),
+                "The token namespace can only change while the registry is empty"
+            );
+        }
+        self.config = config.clone();
+        if namespace_changed {
+            self.limits = Limits::new(&self.internal_token_parent_id());
+        }
+        self.internal_emit(FactoryEvent::ConfigUpdated {
+            config: Box::new(config),
+        });
+    }
+
+    /// Marks the factory as deprecated in favor of `successor_id`. From `sunset_block_height` on,
+    /// new creations are refused.
+    pub fn deprecate(
+        &mut self,
+        successor_id: Option<ValidAccountId>,
+        sunset_block_height: Option<U64>,
+    ) {
+        self.assert_owner();
+        self.internal_log_admin_action(
+            "deprecate",
+            serde_json::json!({
+                "successor_id": successor_id,
+                "sunset_block_height": sunset_block_height,
+            }),
+        );
+        if let Some(sunset_block_height) = sunset_block_height {
+            assert!(
+                sunset_block_height.0 >= env::block_index(),
+                "Sunset block height is in the past"
+            );
+        }
+        self.config.deprecation = Some(Deprecation {
+            successor_id,
+            sunset_block_height,
+        });
+        self.internal_emit(FactoryEvent::ConfigUpdated {
+            config: Box::new(self.config.clone()),
+        });
+    }
+
+    pub fn cancel_deprecation(&mut self) {
+        self.assert_owner();
+        self.internal_log_admin_action("cancel_deprecation", serde_json::json!({}));
+        assert!(
+            self.config.deprecation.take().is_some(),
+            "Factory is not deprecated"
+        );
+        self.internal_emit(FactoryEvent::ConfigUpdated {
+            config: Box::new(self.config.clone()),
+        });
+    }
+
+    /// Transfers collected fees to the owner. Withdraws everything available by default.
+    pub fn withdraw_fees(&mut self, amount: Option<U128>) -> Promise {
+        self.assert_owner();
+        self.internal_log_admin_action("withdraw_fees", serde_json::json!({ "amount": amount }));
+        let available = self.get_available_fees();
+        let amount = amount.map_or(available, |amount| amount.into());
+        assert!(amount <= available, "Not enough fees collected");
+        self.fees_withdrawn += amount;
+        Promise::new(self.owner_id.clone()).transfer(amount)
+    }
+
+    /// Withdraws surplus balance that isn't owed to anyone, e.g. leftovers of refunds. Refuses
+    /// to dip below the storage staking, the storage balances and escrows of the accounts, the
+    /// collected fees and a safety buffer.
+    pub fn withdraw_excess_balance(&mut self, to: ValidAccountId, amount: U128) -> Promise {
+        self.assert_owner();
+        self.internal_log_admin_action(
+            "withdraw_excess_balance",
+            serde_json::json!({"to": to, "amount": amount}),
+        );
+        assert!(
+            amount.0 <= self.get_excess_balance().0,
+            "Amount exceeds the excess balance of {}",
+            self.get_excess_balance().0
+        );
+        Promise::new(to.into()).transfer(amount.0)
+    }
+
+    pub fn grant_role(&mut self, account_id: ValidAccountId, role: Role) {
+        self.assert_owner();
+        self.internal_log_admin_action(
+            "grant_role",
+            serde_json::json!({"account_id": account_id, "role": role}),
+        );
+        let mut roles = self.get_roles(account_id.clone());
+        if !roles.contains(&role) {
+            roles.push(role.clone());
+            self.roles.insert(account_id.as_ref(), &roles);
+            self.internal_emit(FactoryEvent::RoleGranted {
+                account_id: account_id.into(),
+                role,
+            });
+        }
+    }
+
+    /// Records what the attester observed off chain about the factory account, which the
+    /// contract can't inspect itself: the hash of its code, the hash of the published release
+    /// build and the number of its full access keys.
+    pub fn attest_trust(
+        &mut self,
+        code_hash: Base64VecU8,
+        release_code_hash: Base64VecU8,
+        full_access_keys: u32,
+    ) {
+        self.assert_role(Role::Attester);
+        self.internal_log_admin_action(
+            "attest_trust",
+            serde_json::json!({
+                "code_hash": code_hash,
+                "release_code_hash": release_code_hash,
+                "full_access_keys": full_access_keys,
+            }),
+        );
+        assert!(
+            code_hash.0.len() == 32 && release_code_hash.0.len() == 32,
+            "Code hashes have to be 32 bytes"
+        );
+        self.trust_attestation = Some(TrustAttestation {
+            attester_id: env::predecessor_account_id(),
+            code_hash,
+            release_code_hash,
+            full_access_keys,
+            attested_at: env::block_timestamp().into(),
+        });
+    }
+
+    pub fn revoke_role(&mut self, account_id: ValidAccountId, role: Role) {
+        self.assert_owner();
+        self.internal_log_admin_action(
+            "revoke_role",
+            serde_json::json!({"account_id": account_id, "role": role}),
+        );
+        let mut roles = self.get_roles(account_id.clone());
+        roles.retain(|r| r != &role);
+        if roles.is_empty() {
+            self.roles.remove(account_id.as_ref());
+        } else {
+            self.roles.insert(account_id.as_ref(), &roles);
+        }
+        self.internal_emit(FactoryEvent::RoleRevoked {
+            account_id: account_id.into(),
+            role,
+        });
+    }
+
+    pub fn ban_account(&mut self, account_id: ValidAccountId) {
+        self.assert_owner();
+        self.internal_log_admin_action(
+            "ban_account",
+            serde_json::json!({ "account_id": account_id }),
+        );
+        self.banned_accounts.insert(account_id.as_ref());
+        self.internal_emit(FactoryEvent::AccountBanned {
+            account_id: account_id.into(),
+        });
+    }
+
+    pub fn unban_account(&mut self, account_id: ValidAccountId) {
+        self.assert_owner();
+        self.internal_log_admin_action(
+            "unban_account",
+            serde_json::json!({ "account_id": account_id }),
+        );
+        self.banned_accounts.remove(account_id.as_ref());
+        self.internal_emit(FactoryEvent::AccountUnbanned {
+            account_id: account_id.into(),
+        });
+    }
+
+    /// Accepts the fungible token in place of NEAR at the given rate, or stops accepting it
+    /// when `rate` is unset. The factory keeps backing the credited storage balances with its
+    /// own NEAR, and the owner withdraws the received tokens with `withdraw_payment_token`.
+    pub fn set_payment_token(&mut self, token_id: ValidAccountId, rate: Option<PaymentTokenRate>) {
+        self.assert_owner();
+        self.internal_log_admin_action(
+            "set_payment_token",
+            serde_json::json!({"token_id": token_id, "rate": rate}),
+        );
+        match rate {
+            Some(rate) => {
+                assert!(
+                    rate.yocto_near.0 > 0 && rate.token_amount.0 > 0,
+                    "Rate can't be zero"
+                );
+                self.payment_tokens.insert(token_id.as_ref(), &rate);
+            }
+            None => {
+                self.payment_tokens.remove(token_id.as_ref());
+            }
+        }
+    }
+
+    /// Transfers received payment tokens to the owner. Withdraws everything by default.
+    pub fn withdraw_payment_token(
+        &mut self,
+        token_id: ValidAccountId,
+        amount: Option<U128>,
+    ) -> Promise {
+        self.assert_owner();
+        self.internal_log_admin_action(
+            "withdraw_payment_token",
+            serde_json::json!({"token_id": token_id, "amount": amount}),
+        );
+        let balance = self
+            .payment_token_balances
+            .get(token_id.as_ref())
+            .unwrap_or(0);
+        let amount = amount.map_or(balance, |amount| amount.into());
+        assert!(amount <= balance, "Not enough payment tokens received");
+        self.payment_token_balances
+            .insert(token_id.as_ref(), &(balance - amount));
+        ext_ft::ft_transfer(
+            self.owner_id.clone(),
+            amount.into(),
+            None,
+            token_id.as_ref(),
+            ONE_YOCTO,
+            FT_TRANSFER_GAS,
+        )
+    }
+
+    /// Adds or replaces a promotional discount on the creation fee, or removes it when
+    /// `discount` is unset. Replacing a capped promotion keeps the discounted creations counted.
+    pub fn set_fee_discount(&mut self, name: String, discount: Option<FeeDiscount>) {
+        self.assert_owner();
+        self.internal_log_admin_action(
+            "set_fee_discount",
+            serde_json::json!({"name": name, "discount": discount}),
+        );
+        match discount {
+            Some(discount) => {
+                assert!(
+                    discount.discount_bps <= MAX_BPS,
+                    "Discount can't exceed 100%"
+                );
+                assert!(
+                    discount.starts_at.0 < discount.ends_at.0,
+                    "Discount has to end after it starts"
+                );
+                assert!(
+                    self.fee_discounts.get(&name).is_some()
+                        || self.fee_discounts.len() < MAX_FEE_DISCOUNTS,
+                    "Can't have more than {} fee discounts",
+                    MAX_FEE_DISCOUNTS
+                );
+                self.fee_discounts.insert(&name, &discount);
+            }
+            None => {
+                self.fee_discounts.remove(&name);
+            }
+        }
+    }
+
+    pub fn reserve_symbols(&mut self, symbols: Vec<String>) {
+        self.assert_owner();
+        self.internal_log_admin_action(
+            "reserve_symbols",
+            serde_json::json!({ "symbols": symbols }),
+        );
+        for symbol in symbols {
+            self.reserved_symbols.insert(&symbol.to_ascii_lowercase());
+        }
+    }
+
+    pub fn unreserve_symbols(&mut self, symbols: Vec<String>) {
+        self.assert_owner();
+        self.internal_log_admin_action(
+            "unreserve_symbols",
+            serde_json::json!({ "symbols": symbols }),
+        );
+        for symbol in symbols {
+            self.reserved_symbols.remove(&symbol.to_ascii_lowercase());
+        }
+    }
+
+    /// Permanently retires the symbols, e.g. after a scam, so no token can be created with them
+    /// again, also once the token using them was archived. Unlike reserved symbols, retired
+    /// symbols can't be released. Creators asking to retire the symbol of their token go through
+    /// the owner, which can be a DAO approving the retirement by a proposal.
+    pub fn retire_symbols(&mut self, symbols: Vec<String>) {
+        self.assert_owner();
+        self.internal_log_admin_action("retire_symbols", serde_json::json!({ "symbols": symbols }));
+        for symbol in symbols {
+            self.retired_symbols.insert(&symbol.to_ascii_lowercase());
+        }
+    }
+
+    /// Adds names of popular tokens, e.g. `Tether USD` or `USDT`, that the symbols and names of
+    /// new tokens are compared with by the similarity check.
+    pub fn add_similarity_references(&mut self, names: Vec<String>) {
+        self.assert_owner();
+        self.internal_log_admin_action(
+            "add_similarity_references",
+            serde_json::json!({ "names": names }),
+        );
+        for name in names {
+            let name = normalize_name(&name);
+            assert!(
+                !name.is_empty() && name.chars().count() <= MAX_SIMILARITY_REFERENCE_LENGTH,
+                "Similarity reference has to have between 1 and {} letters or digits",
+                MAX_SIMILARITY_REFERENCE_LENGTH
+            );
+            self.similarity_references.insert(&name);
+        }
+        assert!(
+            self.similarity_references.len() <= MAX_SIMILARITY_REFERENCES,
+            "Can't have more than {} similarity references",
+            MAX_SIMILARITY_REFERENCES
+        );
+    }
+
+    pub fn remove_similarity_references(&mut self, names: Vec<String>) {
+        self.assert_owner();
+        self.internal_log_admin_action(
+            "remove_similarity_references",
+            serde_json::json!({ "names": names }),
+        );
+        for name in names {
+            self.similarity_references.remove(&normalize_name(&name));
+        }
+    }
+
+    /// Denies creating tokens whose metadata references the domains or their subdomains.
+    pub fn deny_domains(&mut self, domains: Vec<String>) {
+        self.assert_owner();
+        self.internal_log_admin_action("deny_domains", serde_json::json!({ "domains": domains }));
+        for domain in domains {
+            self.denied_domains.insert(&domain.to_ascii_lowercase());
+        }
+    }
+
+    pub fn allow_domains(&mut self, domains: Vec<String>) {
+        self.assert_owner();
+        self.internal_log_admin_action("allow_domains", serde_json::json!({ "domains": domains }));
+        for domain in domains {
+            self.denied_domains.remove(&domain.to_ascii_lowercase());
+        }
+    }
+
+    /// Refuses creating tokens with names that normalize to one of the given names.
+    pub fn block_names(&mut self, names: Vec<String>) {
+        self.assert_owner();
+        self.internal_log_admin_action("block_names", serde_json::json!({ "names": names }));
+        for name in names {
+            self.blocked_names.insert(&normalize_name(&name));
+        }
+    }
+
+    pub fn unblock_names(&mut self, names: Vec<String>) {
+        self.assert_owner();
+        self.internal_log_admin_action("unblock_names", serde_json::json!({ "names": names }));
+        for name in names {
+            self.blocked_names.remove(&normalize_name(&name));
+        }
+    }
+
+    /// Moves frozen or unresponsive tokens out of the registry into compact archived entries, to
+    /// keep the registry within `registry_soft_cap`. The full records are logged before they
+    /// are removed, so they can be exported from the logs. Returns the number of archived
+    /// tokens.
+    pub fn archive_tokens(&mut self, token_ids: Vec<TokenId>) -> u64 {
+        self.assert_owner();
+        self.assert_not_migrating();
+        self.internal_log_admin_action(
+            "archive_tokens",
+            serde_json::json!({ "token_ids": token_ids }),
+        );
+        let mut archived = 0;
+        for token_id in token_ids {
+            let token = match self.tokens.get(&token_id) {
+                Some(token) if token.frozen || token.unresponsive => token,
+                _ => continue,
+            };
+            let token_account_id = self.internal_token_account_id(&token_id, &token);
+            env::log(
+                format!(
+                    "Archived token {}: {}",
+                    token_id,
+                    serde_json::to_string(&token).unwrap()
+                )
+                .as_bytes(),
+            );
+            self.tokens.remove(&token_id);
+            self.holder_milestones.remove(&token_id);
+            self.internal_remove_icon_blob(&token_id);
+            self.token_managers.remove(&token_id);
+            self.similarity_flags.remove(&token_id);
+            self.internal_remove_from_symbol_index(&token.args.metadata.symbol, &token_id);
+            self.internal_remove_from_name_index(&token.args.metadata.name);
+            self.internal_remove_from_tag_index(&token.args.tags, &token_id);
+            self.internal_remove_from_owner_index(token.args.owner_id.as_ref(), &token_id);
+            self.internal_remove_from_campaign(token.args.campaign.as_ref(), &token_id);
+            self.archived_tokens.insert(
+                &token_id,
+                &ArchivedToken {
+                    token_account_id: token_account_id.clone(),
+                    creator_id: token.creator_id.clone(),
+                    owner_id: token.args.owner_id.into(),
+                    symbol: token.args.metadata.symbol,
+                    archived_at: env::block_timestamp().into(),
+                },
+            );
+            self.archived_token_count += 1;
+            self.internal_emit(FactoryEvent::TokenArchived {
+                token_id,
+                token_account_id,
+                creator_id: token.creator_id,
+            });
+            archived += 1;
+        }
+        archived
+    }
+
+    /// Hides the token from the default list views. Can only be called by the owner or the
+    /// compliance role.
+    pub fn freeze_token(&mut self, token_id: TokenId, reason: String) {
+        self.assert_role(Role::Compliance);
+        self.internal_log_admin_action(
+            "freeze_token",
+            serde_json::json!({"token_id": token_id, "reason": reason}),
+        );
+        self.internal_set_frozen(&token_id, true);
+        self.internal_emit(FactoryEvent::TokenFrozen { token_id, reason });
+    }
+
+    pub fn unfreeze_token(&mut self, token_id: TokenId) {
+        self.assert_role(Role::Compliance);
+        self.internal_log_admin_action(
+            "unfreeze_token",
+            serde_json::json!({ "token_id": token_id }),
+        );
+        self.internal_set_frozen(&token_id, false);
+        self.internal_emit(FactoryEvent::TokenUnfrozen { token_id });
+    }
+
+    fn internal_set_frozen(&mut self, token_id: &TokenId, frozen: bool) {
+        self.assert_not_migrating();
+        let mut token = self.tokens.get(token_id).expect("Token not found");
+        token.frozen = frozen;
+        self.tokens.insert(token_id, &token);
+    }
+
+    pub fn add_trusted_integrator(&mut self, account_id: ValidAccountId) {
+        self.assert_owner();
+        self.internal_log_admin_action(
+            "add_trusted_integrator",
+            serde_json::json!({ "account_id": account_id }),
+        );
+        self.trusted_integrators.insert(account_id.as_ref());
+    }
+
+    pub fn remove_trusted_integrator(&mut self, account_id: ValidAccountId) {
+        self.assert_owner();
+        self.internal_log_admin_action(
+            "remove_trusted_integrator",
+            serde_json::json!({ "account_id": account_id }),
+        );
+        self.trusted_integrators.remove(account_id.as_ref());
+    }
+
+    /// Deploys a proposed token. Can only be called by the owner or a reviewer.
+    pub fn approve_token(&mut self, token_id: TokenId) -> Promise {
+        self.assert_role(Role::Reviewer);
+        self.assert_not_migrating();
+        self.internal_log_admin_action(
+            "approve_token",
+            serde_json::json!({ "token_id": token_id }),
+        );
+        let initial_storage_usage = env::storage_usage();
+        let proposal = self
+            .proposals
+            .remove(&token_id)
+            .expect("Proposal not found");
+        self.assert_not_banned(&proposal.creator_id);
+        let payer_id = proposal.payer_id.clone();
+        let promise = self.internal_deploy(token_id, proposal);
+        self.internal_settle_storage(&payer_id, initial_storage_usage);
+        promise
+    }
+
+    /// Rejects a proposed token and refunds the escrowed balance to the creator.
+    pub fn reject_token(&mut self, token_id: TokenId, reason: String) -> Promise {
+        self.assert_role(Role::Reviewer);
+        self.internal_log_admin_action(
+            "reject_token",
+            serde_json::json!({"token_id": token_id, "reason": reason}),
+        );
+        let initial_storage_usage = env::storage_usage();
+        let proposal = self
+            .proposals
+            .remove(&token_id)
+            .expect("Proposal not found");
+        self.internal_remove_icon_blob(&token_id);
+        self.internal_release_fee_discount(&proposal.payer_id, proposal.fee_discount.as_ref());
+        self.internal_settle_callback_storage(&proposal.payer_id, initial_storage_usage);
+        env::log(format!("Rejected token {}: {}", token_id, reason).as_bytes());
+        self.internal_cancel_prelaunch(&token_id);
+        self.pending_escrow -= proposal.escrow.0;
+        Promise::new(proposal.payer_id).transfer(proposal.escrow.into())
+    }
+}
+
+/// Helpers for sandbox tests and local networks, to quickly get the registry into a known state.
+/// Only compiled with the `dev` feature.
+#[cfg(feature = "dev")]
+#[near_bindgen]
+impl TokenFactory {
+    /// Removes all tokens, proposals and deployments from the registry.
+    pub fn dev_clear_state(&mut self) {
+        self.assert_owner();
+        for (token_id, token) in self.tokens.to_vec() {
+            self.holder_milestones.remove(&token_id);
+            self.internal_remove_icon_blob(&token_id);
+            self.token_managers.remove(&token_id);
+            self.similarity_flags.remove(&token_id);
+            self.creation_receipts.remove(&token_id);
+            index_remove(
+                &mut self.tokens_by_symbol,
+                &token.args.metadata.symbol.to_ascii_lowercase(),
+                &token_id,
+            );
+            self.symbol_skeletons
+                .remove(&confusable_skeleton(&token.args.metadata.symbol));
+            self.token_names
+                .remove(&normalize_name(&token.args.metadata.name));
+            for tag in &token.args.tags {
+                index_remove(&mut self.tokens_by_tag, tag, &token_id);
+            }
+            index_remove(
+                &mut self.tokens_by_owner,
+                token.args.owner_id.as_ref(),
+                &token_id,
+            );
+            if let Some(name) = &token.args.campaign {
+                index_remove(&mut self.tokens_by_campaign, name, &token_id);
+                if let Some(mut campaign) = self.campaigns.get(name) {
+                    campaign.tokens = 0;
+                    self.campaigns.insert(name, &campaign);
+                }
+            }
+        }
+        self.tokens.clear();
+        self.proposals.clear();
+        self.deployments.clear();
+        self.creation_queue.clear();
+        self.pending_escrow = 0;
+        self.registry_leaves.clear();
+        self.registry_tree.set(&MerkleTree::new());
+    }
+
+    /// Adds the tokens to the registry without deploying them. The owner is recorded as their
+    /// creator.
+    pub fn dev_seed_tokens(&mut self, records: Vec<TokenArgs>) {
+        self.assert_owner();
+        for args in records {
+            let token_id = args.metadata.symbol.to_ascii_lowercase();
+            self.internal_add_to_symbol_index(&args.metadata.symbol, &token_id);
+            self.internal_add_to_name_index(&args.metadata.name);
+            self.internal_add_to_tag_index(&args.tags, &token_id);
+            self.internal_add_to_owner_index(args.owner_id.as_ref(), &token_id);
+            self.internal_add_to_campaign(args.campaign.as_ref(), &token_id);
+            let fee_on_transfer = args.has_transfer_fee();
+            self.tokens.insert(
+                &token_id,
+                &TokenRecord {
+                    args,
+                    creator_id: self.owner_id.clone(),
+                    frozen: false,
+                    creation_stats: None,
+                    last_seen_ok: None,
+                    unresponsive: false,
+                    full_access_key: None,
+                    detached_account_id: None,
+                    fee_on_transfer,
+                    created_at: Some(env::block_timestamp().into()),
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::test_utils::{accounts, get_created_receipts};
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    const CREATION_DEPOSIT: Balance = 10_000_000_000_000_000_000_000_000;
+
+    /// Proposes the `abc` token of `accounts(1)` to a factory that requires approval, with
+    /// `accounts(2)` as a reviewer. Returns the escrow of the proposal.
+    fn propose_token(factory: &mut TokenFactory) -> Balance {
+        factory.config.approval_required = true;
+        factory.grant_role(accounts(2), Role::Reviewer);
+        testing_env!(context(accounts(1))
+            .attached_deposit(CREATION_DEPOSIT)
+            .build());
+        let result = factory.create_token(
+            token_args(accounts(1), "ABC"),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        assert!(matches!(result, PromiseOrValue::Value(false)));
+        let escrow = factory.proposals.get(&"abc".to_string()).unwrap().escrow.0;
+        assert_eq!(factory.pending_escrow, escrow);
+        escrow
+    }
+
+    #[test]
+    #[should_panic(expected = "Can only be called by the owner")]
+    fn test_reserve_symbols_by_other_account() {
+        let mut factory = setup_factory();
+        testing_env!(context(accounts(1)).build());
+        factory.reserve_symbols(vec!["ABC".to_string()]);
+    }
+
+    #[test]
+    fn test_granted_role_can_freeze_tokens() {
+        let mut factory = setup_factory();
+        let token_id = seed_token(&mut factory, token_args(accounts(2), "ABC"));
+        factory.grant_role(accounts(1), Role::Compliance);
+        assert!(factory.get_roles(accounts(1)).contains(&Role::Compliance));
+
+        testing_env!(context(accounts(1)).build());
+        factory.freeze_token(token_id.clone(), "Phishing".to_string());
+        assert!(factory.tokens.get(&token_id).unwrap().frozen);
+        factory.unfreeze_token(token_id.clone());
+        assert!(!factory.tokens.get(&token_id).unwrap().frozen);
+        assert_eq!(factory.get_admin_log_length(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "Missing required role")]
+    fn test_freeze_token_without_role() {
+        let mut factory = setup_factory();
+        let token_id = seed_token(&mut factory, token_args(accounts(2), "ABC"));
+        factory.grant_role(accounts(1), Role::Reviewer);
+        testing_env!(context(accounts(1)).build());
+        factory.freeze_token(token_id, "Phishing".to_string());
+    }
+
+    #[test]
+    fn test_approved_proposal_is_deployed() {
+        let mut factory = setup_factory();
+        propose_token(&mut factory);
+        let token_id = "abc".to_string();
+        assert!(factory.tokens.get(&token_id).is_none());
+
+        testing_env!(context(accounts(2)).build());
+        drop(factory.approve_token(token_id.clone()));
+        assert!(factory.proposals.get(&token_id).is_none());
+        assert!(factory.deployments.get(&token_id).is_some());
+        let token_account_id = factory.get_token_account_id(&token_id);
+        assert!(get_created_receipts().iter().any(|receipt| {
+            let receipt = serde_json::to_string(receipt).unwrap();
+            receipt.contains(&format!(r#""receiver_id":"{}""#, token_account_id))
+                && receipt.contains("DeployContract")
+        }));
+
+        testing_env!(
+            context(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        assert!(factory.on_create_token(token_id.clone()));
+        assert!(factory.deployments.get(&token_id).is_none());
+        assert_eq!(factory.pending_escrow, 0);
+        let token = factory.tokens.get(&token_id).unwrap();
+        assert_eq!(token.creator_id, accounts(1).as_ref().clone());
+    }
+
+    #[test]
+    #[should_panic(expected = "Missing required role")]
+    fn test_approve_token_without_role() {
+        let mut factory = setup_factory();
+        propose_token(&mut factory);
+        testing_env!(context(accounts(3)).build());
+        drop(factory.approve_token("abc".to_string()));
+    }
+
+    #[test]
+    fn test_rejected_proposal_refunds_the_escrow() {
+        let mut factory = setup_factory();
+        let escrow = propose_token(&mut factory);
+        let token_id = "abc".to_string();
+
+        testing_env!(context(accounts(2)).build());
+        drop(factory.reject_token(token_id.clone(), "Spam".to_string()));
+        assert!(transfers(accounts(1), escrow));
+        assert!(factory.proposals.get(&token_id).is_none());
+        assert!(factory.deployments.get(&token_id).is_none());
+        assert_eq!(factory.pending_escrow, 0);
+        // Nothing of the rejected proposal holds on to the symbol.
+        assert!(factory.internal_is_token_id_free(&token_id));
+    }
+}
@@ -0,0 +1,2731 @@
+//! Token creation: validation of the args, proposals, the creation queue, prelaunch pools and
+//! the deployment of the token accounts with their callbacks.
+use crate::*;
+
+/// Serializes the arguments of the token's `new` call with the factory's account, merging in the
+/// extra init args. These can't use the name of any token args field, even of one that is unset
+/// and so not serialized.
+fn get_init_args(args: &TokenArgs, extra_init_json: Option<&String>) -> Vec<u8> {
+    let mut init_args = serde_json::to_value(args).unwrap();
+    let init_args = init_args.as_object_mut().unwrap();
+    for field in REGISTRY_ONLY_FIELDS {
+        init_args.remove(*field);
+    }
+    init_args.insert("factory_id".to_string(), env::current_account_id().into());
+    if let Some(extra_init_json) = extra_init_json {
+        let extra_init_args: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(extra_init_json).expect("Extra init args must be a JSON object");
+        for (key, value) in extra_init_args {
+            assert!(
+                !TOKEN_ARGS_FIELDS.contains(&key.as_str()) && !init_args.contains_key(&key),
+                "Extra init args can't override {}",
+                key
+            );
+            init_args.insert(key, value);
+        }
+    }
+    serde_json::to_vec(&init_args).unwrap()
+}
+
+/// NEP-177 token metadata of the launch certificate passed to `nft_mint`. near-contract-standards
+/// 3.1 has no NFT types, so the fields are declared here.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CertificateMetadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub media: Option<String>,
+    pub media_hash: Option<Base64VecU8>,
+    pub copies: Option<u64>,
+    pub issued_at: Option<String>,
+    pub expires_at: Option<String>,
+    pub starts_at: Option<String>,
+    pub updated_at: Option<String>,
+    pub extra: Option<String>,
+    pub reference: Option<String>,
+    pub reference_hash: Option<Base64VecU8>,
+}
+
+/// Treasury split of the total supply in a launch manifest.
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde", deny_unknown_fields)]
+pub struct ManifestDistribution {
+    pub treasury_id: ValidAccountId,
+    pub treasury_split_bps: u16,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde", deny_unknown_fields)]
+pub struct ManifestSale {
+    pub whitelist: SaleWhitelist,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde", deny_unknown_fields)]
+pub struct ManifestTransferFee {
+    pub fee_bps: u16,
+    pub fee_sink_id: ValidAccountId,
+}
+
+/// Everything needed to launch a token in a single call, following
+/// `get_launch_manifest_schema`. Unknown fields are rejected, so a typo doesn't silently drop a
+/// part of the launch.
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde", deny_unknown_fields)]
+pub struct LaunchManifest {
+    pub metadata: FungibleTokenMetadata,
+    pub total_supply: U128,
+    /// Defaults to the creator.
+    #[serde(default)]
+    pub owner_id: Option<ValidAccountId>,
+    #[serde(default)]
+    pub distribution: Option<ManifestDistribution>,
+    /// Vesting schedule passed as is to the `vesting` init arg of templates declaring it.
+    #[serde(default)]
+    pub vesting: Option<serde_json::Value>,
+    #[serde(default)]
+    pub sale: Option<ManifestSale>,
+    #[serde(default)]
+    pub transfer_fee: Option<ManifestTransferFee>,
+    #[serde(default)]
+    pub icon_upload_id: Option<String>,
+    #[serde(default)]
+    pub icon_blob: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub localizations: BTreeMap<String, Localization>,
+    #[serde(default)]
+    pub template: Option<String>,
+    #[serde(default)]
+    pub campaign: Option<String>,
+    #[serde(default)]
+    pub project: Option<String>,
+    #[serde(default)]
+    pub public_key: Option<Base58PublicKey>,
+    #[serde(default)]
+    pub delete_key_after_init: bool,
+    #[serde(default)]
+    pub referrer_id: Option<ValidAccountId>,
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Quote {
+    pub quote_id: U64,
+    /// Hash of the serialized init args the quote was made for.
+    pub args_hash: Base64VecU8,
+    /// Balance charged from the storage deposit, including the fee.
+    pub required_balance: U128,
+    pub fee: U128,
+    pub valid_until_block_height: U64,
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum DeploymentStatus {
+    CreatingAccount,
+    Deploying,
+    DeployFailed,
+    Probing,
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Deployment {
+    pub proposal: Proposal,
+    pub status: DeploymentStatus,
+    pub stats: CreationStats,
+}
+
+/// NEAR amounts of a successful creation. The escrow was taken from the payer's storage balance
+/// and split into the fee, the token deposit, the namespace deposit and the certificate deposit.
+/// A deposit that wasn't needed anymore when the token was deployed, like the namespace deposit
+/// of a namespace created in the meantime, is refunded to the storage balance instead.
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CreationReceipt {
+    pub creator_id: AccountId,
+    pub payer_id: AccountId,
+    pub referrer_id: Option<AccountId>,
+    /// Escrow kept by the creation after the refunds.
+    pub escrow_consumed: U128,
+    pub fee: U128,
+    pub token_deposit: U128,
+    pub namespace_deposit: U128,
+    pub certificate_deposit: U128,
+    pub refund: U128,
+    /// Block timestamp the creation was requested at.
+    pub requested_at: Option<U64>,
+    pub finished_block_height: U64,
+    pub finished_at: U64,
+    /// Sequence number of the creation among all successful ones, starting at 1.
+    pub creation_index: U64,
+    /// Index of the token in the registry Merkle tree, whose leaf proves the registration.
+    pub registry_leaf_index: U64,
+}
+
+/// Commitment to a symbol by `commit_symbol`, which only its committer can reveal.
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SymbolCommit {
+    pub account_id: AccountId,
+    pub block_height: U64,
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Proposal {
+    pub creator_id: AccountId,
+    /// Account whose storage balance paid the escrow and gets the refunds. It differs from the
+    /// creator for tokens created by an integrator with `create_token_for`.
+    pub payer_id: AccountId,
+    pub args: TokenArgs,
+    pub public_key: Option<Base58PublicKey>,
+    /// Balance taken from the creator's storage deposit, refunded on rejection.
+    pub escrow: U128,
+    /// Part of the escrow that is collected as the creation fee on approval.
+    pub fee: U128,
+    pub referrer_id: Option<AccountId>,
+    pub extra_init_json: Option<String>,
+    /// Part of the escrow that funds the project namespace account, if it has to be created.
+    pub namespace_deposit: U128,
+    /// Part of the escrow attached to minting the launch certificate.
+    pub certificate_deposit: U128,
+    /// The token is initialized on a preprovisioned account instead of creating its account.
+    #[serde(default)]
+    pub preprovisioned: bool,
+    /// Capped promotion the creation counts against, given back if the token isn't created.
+    #[serde(default)]
+    pub fee_discount: Option<String>,
+}
+
+impl Proposal {
+    /// Part of the escrow transferred to the token account.
+    fn token_deposit(&self) -> Balance {
+        self.escrow.0 - self.fee.0 - self.namespace_deposit.0 - self.certificate_deposit.0
+    }
+}
+
+/// Creation waiting in the queue of the congested factory. Its escrow is already charged.
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct QueuedCreation {
+    pub proposal: Proposal,
+    /// Part of the proposal fee paid for the priority.
+    pub priority_fee: U128,
+    /// Order in which the creation was queued, breaking ties between equal priority fees.
+    pub queue_index: u64,
+}
+
+/// NEAR deposited by supporters of a token before its launch. The pool is released to the
+/// creator once the token is deployed, and refunded to the supporters if the launch is cancelled.
+/// The contribution of each supporter is kept in `prelaunch_contributions`.
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PrelaunchPool {
+    pub supporters: u64,
+    pub total: U128,
+    /// Set when the proposal was rejected or the deployment failed. The supporters withdraw
+    /// their contributions with `refund_prelaunch`.
+    pub cancelled: bool,
+}
+
+/// Borsh serialized arguments of the namespace `create_token_account` call.
+#[derive(BorshSerialize)]
+struct NamespaceCreateArgs {
+    name: String,
+    public_key: Option<Vec<u8>>,
+    code: Vec<u8>,
+    init_args: Vec<u8>,
+    init_gas: Gas,
+    delete_key_after_init: bool,
+}
+
+#[near_bindgen]
+impl TokenFactory {
+    fn assert_not_sunset(&self) {
+        if let Some(deprecation) = &self.config.deprecation {
+            if deprecation.is_sunset(env::block_index()) {
+                match &deprecation.successor_id {
+                    Some(successor_id) => env::panic(
+                        format!(
+                            "Factory is sunset, create tokens with {} instead",
+                            successor_id.as_ref()
+                        )
+                        .as_bytes(),
+                    ),
+                    None => env::panic(b"Factory is sunset"),
+                }
+            }
+        }
+    }
+
+    /// Locks the current price for creating a token with the given args. The quote is stored for
+    /// the predecessor, replacing its previous one, and `create_token` charges the quoted amount
+    /// when called with the `quote_id` before the quote expires.
+    pub fn get_quote(&mut self, args: TokenArgs, extra_init_json: Option<String>) -> Quote {
+        let mut args = args;
+        let account_id = env::predecessor_account_id();
+        assert!(
+            self.internal_storage_balance(&account_id).is_some(),
+            "Account has to be registered with storage_deposit"
+        );
+        self.internal_resolve_icon(&account_id, &mut args);
+        let fee = self.internal_get_creation_fee(&args, &account_id);
+        self.quote_nonce += 1;
+        let quote = Quote {
+            quote_id: self.quote_nonce.into(),
+            args_hash: env::sha256(&get_init_args(&args, extra_init_json.as_ref())).into(),
+            required_balance: (self.get_min_attached_balance(&args, extra_init_json.as_ref())
+                + fee)
+                .into(),
+            fee: fee.into(),
+            valid_until_block_height: (env::block_index() + self.config.quote_validity_blocks)
+                .into(),
+        };
+        self.quotes.insert(&account_id, &quote);
+        quote
+    }
+
+    /// Returns the balance to charge and the fee of the given quote, consuming it.
+    fn internal_take_quote(
+        &mut self,
+        account_id: &AccountId,
+        quote_id: u64,
+        init_args: &[u8],
+    ) -> (Balance, Balance) {
+        let quote = self.quotes.remove(account_id).expect("Quote not found");
+        assert_eq!(quote.quote_id.0, quote_id, "Quote not found");
+        assert!(
+            env::block_index() <= quote.valid_until_block_height.0,
+            "Quote has expired"
+        );
+        assert_eq!(
+            quote.args_hash.0,
+            env::sha256(init_args),
+            "Quote was made for different args"
+        );
+        (quote.required_balance.0, quote.fee.0)
+    }
+
+    /// Creates a new token account as a sub-account of the factory. The creator is always the
+    /// predecessor, so the call works the same when relayed through a delegate action or made
+    /// by another contract, which can attach the deposit in the same call. The optional
+    /// `public_key` is added as a full access key to the new token account.
+    ///
+    /// When the factory requires approval, the token is only recorded as a proposal and gets
+    /// deployed once a reviewer calls `approve_token`.
+    ///
+    /// The creation fee is charged together with the storage cost, and the optional
+    /// `referrer_id` receives its configured share of it once the token is deployed.
+    ///
+    /// `extra_init_json` is an optional JSON object merged into the arguments of the token's
+    /// `new` call, for templates that take additional init parameters.
+    ///
+    /// When `quote_id` is given, the price locked by the predecessor's `get_quote` is charged.
+    ///
+    /// With a `project`, the token is deployed to `token.project.factory`, and the project
+    /// namespace account is created on first use. The first creator launching under a project
+    /// owns it, and only the owner can launch further tokens under it.
+    ///
+    /// While the factory is congested, creations are queued and deployed by `process_queue` in
+    /// the order of their `priority_fee`, which is only charged for queued creations and
+    /// collected together with the creation fee.
+    ///
+    /// Resolves to whether the token was deployed, so a calling contract can act on the result
+    /// in its own callback.
+    #[payable]
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_token(
+        &mut self,
+        args: TokenArgs,
+        public_key: Option<Base58PublicKey>,
+        referrer_id: Option<ValidAccountId>,
+        extra_init_json: Option<String>,
+        quote_id: Option<U64>,
+        project: Option<String>,
+        priority_fee: Option<U128>,
+    ) -> PromiseOrValue<bool> {
+        self.assert_symbol_commit_not_required();
+        let account_id = env::predecessor_account_id();
+        self.internal_create_token(
+            &account_id,
+            account_id.clone(),
+            args,
+            public_key,
+            referrer_id,
+            extra_init_json,
+            quote_id,
+            project,
+            priority_fee.map_or(0, |priority_fee| priority_fee.0),
+        )
+    }
+
+    /// Creates a token from a launch manifest, which bundles the metadata, the treasury
+    /// distribution, the vesting schedule and the sale whitelist of the launch into a single
+    /// JSON document, so a launch script doesn't have to assemble the args and the extra init
+    /// args itself. The manifest is expanded into the args of `create_token` and goes through
+    /// the same checks and deposits.
+    #[payable]
+    pub fn create_from_manifest(&mut self, manifest_json: String) -> PromiseOrValue<bool> {
+        self.assert_symbol_commit_not_required();
+        let manifest: LaunchManifest = serde_json::from_str(&manifest_json)
+            .unwrap_or_else(|e| env::panic(format!("Invalid launch manifest: {}", e).as_bytes()));
+        let account_id = env::predecessor_account_id();
+        let owner_id = manifest
+            .owner_id
+            .unwrap_or_else(|| ValidAccountId::try_from(account_id.clone()).unwrap());
+        let mut args = TokenArgs::new(owner_id, manifest.total_supply, manifest.metadata);
+        if let Some(distribution) = manifest.distribution {
+            args.treasury_id = Some(distribution.treasury_id);
+            args.treasury_split_bps = Some(distribution.treasury_split_bps);
+        }
+        if let Some(sale) = manifest.sale {
+            args.whitelist = Some(sale.whitelist);
+        }
+        if let Some(transfer_fee) = manifest.transfer_fee {
+            args.transfer_fee_bps = Some(transfer_fee.fee_bps);
+            args.fee_sink_id = Some(transfer_fee.fee_sink_id);
+        }
+        args.icon_upload_id = manifest.icon_upload_id;
+        args.icon_blob = manifest.icon_blob;
+        args.tags = manifest.tags;
+        args.localizations = manifest.localizations;
+        args.template = manifest.template;
+        args.campaign = manifest.campaign;
+        args.delete_key_after_init = manifest.delete_key_after_init;
+        let extra_init_json = manifest.vesting.map(|vesting| {
+            assert!(
+                self.internal_template_has_init_arg(&args, "vesting"),
+                "Template doesn't support vesting"
+            );
+            serde_json::json!({ "vesting": vesting }).to_string()
+        });
+        self.internal_create_token(
+            &account_id,
+            account_id.clone(),
+            args,
+            manifest.public_key,
+            manifest.referrer_id,
+            extra_init_json,
+            None,
+            manifest.project,
+            0,
+        )
+    }
+
+    /// Commits to creating a token with a symbol without revealing it, with the
+    /// `get_symbol_commit_hash` of the symbol, the predecessor and a secret salt. The
+    /// commitment can be revealed by `reveal_and_create` once it is `min_commit_age_blocks`
+    /// old, so a bot seeing the reveal can't get a commitment of its own in before it. The
+    /// storage is charged to the storage balance and refunded on the reveal.
+    pub fn commit_symbol(&mut self, hash: Base64VecU8) {
+        let account_id = env::predecessor_account_id();
+        self.assert_not_banned(&account_id);
+        assert_eq!(hash.0.len(), 32, "Hash must be 32 bytes");
+        if let Some(commit) = self.symbol_commits.get(&hash.0) {
+            assert!(
+                self.internal_is_commit_expired(&commit),
+                "Symbol commitment already exists"
+            );
+        }
+        let initial_storage_usage = env::storage_usage();
+        self.symbol_commits.insert(
+            &hash.0,
+            &SymbolCommit {
+                account_id: account_id.clone(),
+                block_height: env::block_index().into(),
+            },
+        );
+        self.internal_settle_storage(&account_id, initial_storage_usage);
+    }
+
+    /// Creates the token committed to with `commit_symbol`, see `create_token` for the other
+    /// arguments. The symbol of the args has to match the committed one.
+    #[payable]
+    #[allow(clippy::too_many_arguments)]
+    pub fn reveal_and_create(
+        &mut self,
+        symbol: String,
+        salt: String,
+        args: TokenArgs,
+        public_key: Option<Base58PublicKey>,
+        referrer_id: Option<ValidAccountId>,
+        extra_init_json: Option<String>,
+        project: Option<String>,
+    ) -> PromiseOrValue<bool> {
+        let account_id = env::predecessor_account_id();
+        assert_eq!(
+            args.metadata.symbol, symbol,
+            "Symbol of the args doesn't match the revealed symbol"
+        );
+        let hash = self.get_symbol_commit_hash(
+            ValidAccountId::try_from(account_id.clone()).unwrap(),
+            symbol,
+            salt,
+        );
+        let commit = self
+            .symbol_commits
+            .get(&hash.0)
+            .expect("Symbol commitment not found");
+        assert!(
+            !self.internal_is_commit_expired(&commit),
+            "Symbol commitment has expired"
+        );
+        assert!(
+            env::block_index() >= commit.block_height.0 + self.config.min_commit_age_blocks,
+            "Symbol commitment can't be revealed before it is {} blocks old",
+            self.config.min_commit_age_blocks
+        );
+        let result = self.internal_create_token(
+            &account_id,
+            account_id.clone(),
+            args,
+            public_key,
+            referrer_id,
+            extra_init_json,
+            None,
+            project,
+            0,
+        );
+        let initial_storage_usage = env::storage_usage();
+        self.symbol_commits.remove(&hash.0);
+        self.internal_settle_storage(&account_id, initial_storage_usage);
+        result
+    }
+
+    fn internal_is_commit_expired(&self, commit: &SymbolCommit) -> bool {
+        env::block_index() > commit.block_height.0 + self.config.max_commit_age_blocks
+    }
+
+    fn assert_symbol_commit_not_required(&self) {
+        assert!(
+            !self.config.symbol_commit_required,
+            "Symbols have to be committed with commit_symbol and revealed with reveal_and_create"
+        );
+    }
+
+    /// Creates a token paying with the attached deposit in a single step. The deposit has to
+    /// cover `get_required_deposit` for the predecessor, and the part above it is refunded right
+    /// away instead of staying in the storage balance. Refused when `separate_deposits` is set.
+    #[payable]
+    pub fn deposit_and_create(
+        &mut self,
+        args: TokenArgs,
+        public_key: Option<Base58PublicKey>,
+    ) -> PromiseOrValue<bool> {
+        self.assert_symbol_commit_not_required();
+        let account_id = env::predecessor_account_id();
+        let required_deposit = self
+            .get_required_deposit(
+                args.clone(),
+                ValidAccountId::try_from(account_id.clone()).unwrap(),
+                None,
+                None,
+            )
+            .total
+            .0;
+        let deposit = env::attached_deposit();
+        assert!(
+            deposit >= required_deposit,
+            "Attached deposit is below the required deposit of {}",
+            required_deposit
+        );
+        // The whole deposit is credited to the storage balance before the creation is charged.
+        let result = self.internal_create_token(
+            &account_id,
+            account_id.clone(),
+            args,
+            public_key,
+            None,
+            None,
+            None,
+            None,
+            0,
+        );
+        let balance = self.internal_storage_balance(&account_id).unwrap_or(0);
+        let refund = std::cmp::min(deposit - required_deposit, balance);
+        if refund > 0 {
+            self.internal_set_storage_balance(&account_id, balance - refund, "excess_refund");
+            Promise::new(account_id).transfer(refund);
+        }
+        result
+    }
+
+    /// Creates a token on behalf of `account_id`, which is recorded as its creator, while the
+    /// storage and the fee are charged to the calling integrator. Can only be called by trusted
+    /// integrators, see `create_token` for the other arguments.
+    #[payable]
+    pub fn create_token_for(
+        &mut self,
+        account_id: ValidAccountId,
+        args: TokenArgs,
+        public_key: Option<Base58PublicKey>,
+        referrer_id: Option<ValidAccountId>,
+        extra_init_json: Option<String>,
+        project: Option<String>,
+    ) -> PromiseOrValue<bool> {
+        self.assert_symbol_commit_not_required();
+        let payer_id = env::predecessor_account_id();
+        assert!(
+            self.trusted_integrators.contains(&payer_id),
+            "Only trusted integrators can create tokens for other accounts"
+        );
+        self.internal_create_token(
+            &payer_id,
+            account_id.into(),
+            args,
+            public_key,
+            referrer_id,
+            extra_init_json,
+            None,
+            project,
+            0,
+        )
+    }
+
+    /// Checks the args of a new token against the metadata, naming and template policies.
+    fn internal_assert_valid_args(&self, args: &TokenArgs) {
+        self.internal_log_diagnostic(
+            "args_validation",
+            serde_json::json!({
+                "symbol": args.metadata.symbol,
+                "name": args.metadata.name,
+                "decimals": args.metadata.decimals,
+                "template": args.template,
+                "campaign": args.campaign,
+                "tags": args.tags,
+            }),
+        );
+        args.metadata.assert_valid();
+        // Also applies when the token id isn't derived from the symbol.
+        assert!(
+            !self
+                .retired_symbols
+                .contains(&args.metadata.symbol.to_ascii_lowercase()),
+            "{}",
+            errors::SYMBOL_RETIRED
+        );
+        self.config.assert_valid_decimals(args.metadata.decimals);
+        self.internal_assert_not_confusable(&args.metadata);
+        self.internal_assert_not_similar(&args.metadata);
+        if let Some(domain) = self.internal_check_metadata_domains(&args.metadata) {
+            panic!("Metadata references the denied domain {}", domain);
+        }
+        if let Some(whitelist) = &args.whitelist {
+            whitelist.assert_valid();
+        }
+        assert!(
+            self.is_name_available(args.metadata.name.clone()),
+            "Token name is not available"
+        );
+        args.assert_valid_tags();
+        args.assert_valid_localizations();
+        args.assert_valid_treasury_split();
+        if args.treasury_id.is_some() {
+            assert!(
+                self.internal_template_has_init_arg(args, "treasury_split_bps"),
+                "Template doesn't support the treasury split"
+            );
+        }
+        args.assert_valid_transfer_fee();
+        if args.fee_sink_id.is_some() {
+            assert!(
+                self.internal_template_has_init_arg(args, "transfer_fee_bps"),
+                "Template doesn't support a transfer fee"
+            );
+        }
+        if let Some(template) = &args.template {
+            assert!(
+                self.templates
+                    .get(template)
+                    .map_or(false, |template| template.enabled),
+                "Template {} is not available",
+                template
+            );
+        }
+        if let Some(campaign) = &args.campaign {
+            let campaign = self.campaigns.get(campaign).expect("Campaign not found");
+            assert!(campaign.is_active(), "Campaign has ended");
+            if let Some(template) = &campaign.settings.template {
+                assert_eq!(
+                    args.template.as_ref(),
+                    Some(template),
+                    "Tokens of the campaign have to use its template"
+                );
+            }
+        }
+    }
+
+    /// Creates up to `MAX_PREPROVISION_BATCH` token accounts with the default token code
+    /// deployed but not initialized, so creators can claim them with `claim_preprovisioned`
+    /// without waiting for the account creation. The attached deposit has to cover the code
+    /// storage of every account, and is collected back from the creators with the creation
+    /// fee. The default code has to refuse initialization by accounts other than the factory.
+    #[payable]
+    pub fn preprovision_accounts(&mut self, count: u64) -> Vec<TokenId> {
+        self.assert_owner();
+        self.internal_log_admin_action(
+            "preprovision_accounts",
+            serde_json::json!({ "count": count }),
+        );
+        assert!(
+            count <= MAX_PREPROVISION_BATCH,
+            "Can't preprovision more than {} accounts at once",
+            MAX_PREPROVISION_BATCH
+        );
+        assert!(
+            self.config.token_namespace_id.is_none(),
+            "Preprovisioning is not supported with a token namespace"
+        );
+        let code_storage = self.get_code_storage_cost(None);
+        assert!(
+            env::attached_deposit() >= Balance::from(count) * code_storage,
+            "Attached deposit has to cover the code storage of {} per account",
+            code_storage
+        );
+        let mut token_ids = vec![];
+        while (token_ids.len() as u64) < count {
+            self.preprovision_nonce += 1;
+            let token_id = format!("p{}", self.preprovision_nonce);
+            if !self.internal_is_token_id_free(&token_id) {
+                continue;
+            }
+            self.preprovisioned_accounts.insert(&token_id, &false);
+            Promise::new(self.get_token_account_id(&token_id))
+                .create_account()
+                .transfer(code_storage)
+                .deploy_contract(self.internal_get_token_code(None))
+                .then(ext_self::on_account_preprovisioned(
+                    token_id.clone(),
+                    &env::current_account_id(),
+                    0,
+                    self.config.callback_gas,
+                ));
+            token_ids.push(token_id);
+        }
+        token_ids
+    }
+
+    /// Marks the account as ready to be claimed. The deposit of a failed account comes back to
+    /// the factory balance.
+    #[private]
+    pub fn on_account_preprovisioned(&mut self, token_id: TokenId) -> bool {
+        let success = is_promise_success();
+        if success {
+            self.preprovisioned_accounts.insert(&token_id, &true);
+        } else {
+            self.preprovisioned_accounts.remove(&token_id);
+        }
+        success
+    }
+
+    /// Creates a token on the preprovisioned account `token_id` of `get_preprovisioned_accounts`
+    /// by only initializing its code. The token gets the id of the account, and as the account
+    /// exists already no key and no template can be used. The symbol goes through the same
+    /// checks as for `create_token`, and can't be registered by another token either. The
+    /// creator pays the same as for `create_token`, with the code storage collected as part of
+    /// the fee.
+    #[payable]
+    pub fn claim_preprovisioned(&mut self, token_id: TokenId, args: TokenArgs) -> Promise {
+        let mut args = args;
+        self.assert_not_migrating();
+        self.assert_symbol_commit_not_required();
+        self.assert_not_sunset();
+        self.internal_credit_creation_deposit();
+        let account_id = env::predecessor_account_id();
+        self.assert_not_banned(&account_id);
+        assert!(
+            !self.config.approval_required,
+            "Preprovisioned accounts can't be claimed while creations need approval"
+        );
+        assert!(
+            args.template.is_none(),
+            "Preprovisioned accounts only have the default token code"
+        );
+        assert!(
+            !args.delete_key_after_init,
+            "Preprovisioned accounts don't have a key to delete"
+        );
+        assert!(
+            self.preprovisioned_accounts
+                .get(&token_id)
+                .expect("Preprovisioned account not found"),
+            "Preprovisioned account is not ready yet"
+        );
+        let icon_blob = self.internal_take_icon_upload(&account_id, &mut args);
+        self.internal_assert_valid_args(&args);
+        // The token keeps the id of the account, so only the checks of the symbol matter here.
+        self.internal_validate_symbol(&args.metadata.symbol)
+            .unwrap_token_id();
+        assert!(
+            self.tokens_by_symbol
+                .get(&args.metadata.symbol.to_ascii_lowercase())
+                .is_none(),
+            "Symbol is already registered"
+        );
+        if let Some(registry_soft_cap) = self.config.registry_soft_cap {
+            assert!(
+                self.tokens.len() < registry_soft_cap,
+                "Registry is full, the owner has to archive tokens"
+            );
+        }
+
+        let fee =
+            self.internal_get_creation_fee(&args, &account_id) + self.get_code_storage_cost(None);
+        let certificate_deposit = self
+            .config
+            .launch_certificate
+            .as_ref()
+            .map_or(0, |certificate| certificate.mint_deposit.0);
+        let required_balance = self.get_args_storage_cost(&args, None) + fee + certificate_deposit;
+        let user_balance = self.internal_storage_balance(&account_id).unwrap_or(0);
+        assert!(
+            user_balance >= required_balance,
+            "Not enough required balance"
+        );
+        self.internal_set_storage_balance(&account_id, user_balance - required_balance, "creation");
+        self.pending_escrow += required_balance;
+
+        let initial_storage_usage = env::storage_usage();
+        let fee_discount = self.internal_use_fee_discount(&account_id);
+        self.preprovisioned_accounts.remove(&token_id);
+        if let Some(icon) = icon_blob {
+            self.internal_store_icon_blob(&token_id, &icon);
+        }
+        let proposal = Proposal {
+            creator_id: account_id.clone(),
+            payer_id: account_id.clone(),
+            args,
+            public_key: None,
+            escrow: required_balance.into(),
+            fee: fee.into(),
+            referrer_id: None,
+            extra_init_json: None,
+            namespace_deposit: 0.into(),
+            certificate_deposit: certificate_deposit.into(),
+            preprovisioned: true,
+            fee_discount,
+        };
+        let promise = self.internal_deploy(token_id, proposal);
+        self.internal_settle_storage(&account_id, initial_storage_usage);
+        promise
+    }
+
+    /// Creates the token for `account_id`, charging the storage balance of `payer_id`.
+    #[allow(clippy::too_many_arguments)]
+    fn internal_create_token(
+        &mut self,
+        payer_id: &AccountId,
+        account_id: AccountId,
+        mut args: TokenArgs,
+        public_key: Option<Base58PublicKey>,
+        referrer_id: Option<ValidAccountId>,
+        extra_init_json: Option<String>,
+        quote_id: Option<U64>,
+        project: Option<String>,
+        priority_fee: Balance,
+    ) -> PromiseOrValue<bool> {
+        self.assert_not_migrating();
+        self.assert_not_sunset();
+        self.internal_credit_creation_deposit();
+        self.assert_not_banned(payer_id);
+        self.assert_not_banned(&account_id);
+        let icon_blob = self.internal_take_icon_upload(payer_id, &mut args);
+        self.internal_assert_valid_args(&args);
+        if args.delete_key_after_init {
+            assert!(
+                public_key.is_some(),
+                "Deleting the key after init needs a public_key"
+            );
+            // Later receipts can't manage the keys of the token account anymore.
+            assert!(
+                !self.config.split_deployment || project.is_some(),
+                "Deleting the key after init is not supported by the split deployment"
+            );
+        }
+        if self.get_owner_registration_deposit(args.template.as_ref()) > 0 {
+            assert!(
+                !self.config.split_deployment
+                    && project.is_none()
+                    && self.config.token_namespace_id.is_none(),
+                "Templates registering the owner are not supported by the split deployment, \
+                 project tokens and a token namespace"
+            );
+        }
+        let init_args = get_init_args(&args, extra_init_json.as_ref());
+        assert_eq!(
+            self.limits.factory_account_id,
+            env::current_account_id(),
+            "Limits are stale, the owner has to call update_limits"
+        );
+        let token_id = match self.config.token_id_mode {
+            TokenIdMode::Symbol => {
+                let validation = self.internal_validate_symbol(&args.metadata.symbol);
+                self.internal_log_diagnostic(
+                    "symbol_validation",
+                    serde_json::to_value(&validation).unwrap(),
+                );
+                match validation {
+                    // Within a project the symbol only has to be unique among the project tokens.
+                    SymbolValidation::Taken if project.is_some() => {
+                        args.metadata.symbol.to_ascii_lowercase()
+                    }
+                    validation => validation.unwrap_token_id(),
+                }
+            }
+            TokenIdMode::Counter => {
+                self.token_counter += 1;
+                format!("t{}", self.token_counter)
+            }
+        };
+        let namespace_deposit = self.get_namespace_deposit(project.as_ref());
+        let token_id = if let Some(project) = &project {
+            self.internal_use_project(&account_id, project);
+            format!("{}.{}", token_id, project)
+        } else {
+            token_id
+        };
+        assert!(
+            env::is_valid_account_id(self.get_token_account_id(&token_id).as_bytes()),
+            "Token Account ID is invalid"
+        );
+        assert!(
+            self.internal_is_token_id_free(&token_id),
+            "Token ID is already taken"
+        );
+        if let Some(registry_soft_cap) = self.config.registry_soft_cap {
+            assert!(
+                self.tokens.len() < registry_soft_cap,
+                "Registry is full, the owner has to archive tokens"
+            );
+        }
+
+        let referrer_id: Option<AccountId> = referrer_id.map(|referrer_id| referrer_id.into());
+        assert!(
+            referrer_id.as_ref() != Some(&account_id),
+            "Can't refer yourself"
+        );
+
+        let (required_balance, fee) = if let Some(quote_id) = quote_id {
+            self.internal_take_quote(payer_id, quote_id.0, &init_args)
+        } else {
+            let fee = self.internal_get_creation_fee(&args, payer_id);
+            (
+                self.get_min_attached_balance(&args, extra_init_json.as_ref()) + fee,
+                fee,
+            )
+        };
+        let certificate_deposit = self
+            .config
+            .launch_certificate
+            .as_ref()
+            .map_or(0, |certificate| certificate.mint_deposit.0);
+        let queued = self.config.congested && !self.config.approval_required;
+        let priority_fee = if queued { priority_fee } else { 0 };
+        let fee = fee + priority_fee;
+        let required_balance =
+            required_balance + namespace_deposit + certificate_deposit + priority_fee;
+        let user_balance = self.internal_storage_balance(payer_id).unwrap_or(0);
+        self.internal_log_diagnostic(
+            "creation_balance",
+            serde_json::json!({
+                "token_id": token_id,
+                "payer_id": payer_id,
+                "balance": U128(user_balance),
+                "required_balance": U128(required_balance),
+                "fee": U128(fee),
+                "namespace_deposit": U128(namespace_deposit),
+                "certificate_deposit": U128(certificate_deposit),
+                "queued": queued,
+            }),
+        );
+        assert!(
+            user_balance >= required_balance,
+            "Not enough required balance"
+        );
+        self.internal_set_storage_balance(payer_id, user_balance - required_balance, "creation");
+        self.pending_escrow += required_balance;
+
+        // The registry entries are charged to the payer at their exact size.
+        let initial_storage_usage = env::storage_usage();
+        let fee_discount = self.internal_use_fee_discount(payer_id);
+        if let Some(icon) = icon_blob {
+            self.internal_store_icon_blob(&token_id, &icon);
+        }
+        let proposal = Proposal {
+            creator_id: account_id,
+            payer_id: payer_id.clone(),
+            args,
+            public_key,
+            escrow: required_balance.into(),
+            fee: fee.into(),
+            referrer_id,
+            extra_init_json,
+            namespace_deposit: namespace_deposit.into(),
+            certificate_deposit: certificate_deposit.into(),
+            preprovisioned: false,
+            fee_discount,
+        };
+        let result = if self.config.approval_required {
+            self.proposals.insert(&token_id, &proposal);
+            PromiseOrValue::Value(false)
+        } else if queued {
+            assert!(
+                self.creation_queue.len() < MAX_QUEUE_SIZE,
+                "Creation queue is full"
+            );
+            self.queue_nonce += 1;
+            self.creation_queue.insert(
+                &token_id,
+                &QueuedCreation {
+                    proposal,
+                    priority_fee: priority_fee.into(),
+                    queue_index: self.queue_nonce,
+                },
+            );
+            PromiseOrValue::Value(false)
+        } else {
+            self.internal_deploy(token_id, proposal).into()
+        };
+        self.internal_settle_storage(payer_id, initial_storage_usage);
+        result
+    }
+
+    /// Deploys up to `limit` queued creations, highest priority fee first. Can be called by
+    /// anyone, and stops early when the attached gas doesn't cover another deployment. Returns
+    /// the number of deployed creations.
+    pub fn process_queue(&mut self, limit: u64) -> u64 {
+        self.assert_not_migrating();
+        let mut queue: Vec<(TokenId, QueuedCreation)> = self.creation_queue.to_vec();
+        queue.sort_by(|(_, a), (_, b)| {
+            b.priority_fee
+                .0
+                .cmp(&a.priority_fee.0)
+                .then(a.queue_index.cmp(&b.queue_index))
+        });
+        let mut processed = 0;
+        for (token_id, queued) in queue.into_iter().take(limit as usize) {
+            // The gas needs some headroom for the storage settlement after the deployment.
+            if env::prepaid_gas() - env::used_gas()
+                < self.get_deploy_gas(&token_id, &queued.proposal) + DEFAULT_CALLBACK_GAS
+            {
+                break;
+            }
+            let initial_storage_usage = env::storage_usage();
+            self.creation_queue.remove(&token_id);
+            let payer_id = queued.proposal.payer_id.clone();
+            self.internal_deploy(token_id, queued.proposal);
+            self.internal_settle_callback_storage(&payer_id, initial_storage_usage);
+            processed += 1;
+        }
+        processed
+    }
+
+    /// Adds the attached deposit to the prelaunch pool of a token that waits for approval or in
+    /// the creation queue. The storage of a new contribution is taken from the deposit. Returns
+    /// the contribution of the supporter.
+    #[payable]
+    pub fn fund_prelaunch(&mut self, token_id: TokenId) -> U128 {
+        let account_id = env::predecessor_account_id();
+        self.assert_not_banned(&account_id);
+        assert!(
+            self.proposals.get(&token_id).is_some() || self.creation_queue.get(&token_id).is_some(),
+            "Token is not waiting for its launch"
+        );
+        let initial_storage_usage = env::storage_usage();
+        let mut pool = self
+            .prelaunch_pools
+            .get(&token_id)
+            .unwrap_or(PrelaunchPool {
+                supporters: 0,
+                total: 0.into(),
+                cancelled: false,
+            });
+        assert!(!pool.cancelled, "Prelaunch pool was cancelled");
+        let key = (token_id.clone(), account_id);
+        let contribution = match self.prelaunch_contributions.get(&key) {
+            Some(contribution) => contribution,
+            None => {
+                pool.supporters += 1;
+                0
+            }
+        };
+        // Written before the storage is measured, at their final size.
+        self.prelaunch_pools.insert(&token_id, &pool);
+        self.prelaunch_contributions.insert(&key, &contribution);
+        let storage_cost = pricing::storage_cost(
+            env::storage_usage() - initial_storage_usage,
+            STORAGE_PRICE_PER_BYTE,
+        );
+        assert!(
+            env::attached_deposit() > storage_cost,
+            "Attached deposit has to exceed the storage cost of {}",
+            storage_cost
+        );
+        let amount = env::attached_deposit() - storage_cost;
+        let contribution = contribution + amount;
+        pool.total = (pool.total.0 + amount).into();
+        self.prelaunch_pools.insert(&token_id, &pool);
+        self.prelaunch_contributions.insert(&key, &contribution);
+        self.prelaunch_funds += amount;
+        contribution.into()
+    }
+
+    /// Withdraws the contribution of the predecessor from a cancelled prelaunch pool, together
+    /// with the storage it released.
+    pub fn refund_prelaunch(&mut self, token_id: TokenId) -> Promise {
+        let account_id = env::predecessor_account_id();
+        let mut pool = self
+            .prelaunch_pools
+            .get(&token_id)
+            .expect("Prelaunch pool not found");
+        assert!(pool.cancelled, "Prelaunch pool wasn't cancelled");
+        let initial_storage_usage = env::storage_usage();
+        let contribution = self
+            .prelaunch_contributions
+            .remove(&(token_id.clone(), account_id.clone()))
+            .expect("No contribution to refund");
+        pool.supporters -= 1;
+        pool.total = (pool.total.0 - contribution).into();
+        if pool.supporters == 0 {
+            self.prelaunch_pools.remove(&token_id);
+        } else {
+            self.prelaunch_pools.insert(&token_id, &pool);
+        }
+        let storage_refund = pricing::storage_cost(
+            initial_storage_usage - env::storage_usage(),
+            STORAGE_PRICE_PER_BYTE,
+        );
+        self.prelaunch_funds -= contribution;
+        Promise::new(account_id).transfer(contribution + storage_refund)
+    }
+
+    /// Transfers the prelaunch pool of a deployed token to its creator. The released storage is
+    /// refunded to the payer together with the rest of the callback storage. The contributions
+    /// are kept as the record of the supporters, since they can't be listed to be removed, and
+    /// the ID of a deployed token isn't used again.
+    fn internal_release_prelaunch(&mut self, token_id: &TokenId, creator_id: &AccountId) {
+        if let Some(pool) = self.prelaunch_pools.remove(token_id) {
+            let total = pool.total.0;
+            self.prelaunch_funds -= total;
+            env::log(
+                format!("Released prelaunch pool of {} to {}", token_id, creator_id).as_bytes(),
+            );
+            Promise::new(creator_id.clone()).transfer(total);
+        }
+    }
+
+    pub(crate) fn internal_cancel_prelaunch(&mut self, token_id: &TokenId) {
+        if let Some(mut pool) = self.prelaunch_pools.get(token_id) {
+            pool.cancelled = true;
+            self.prelaunch_pools.insert(token_id, &pool);
+        }
+    }
+
+    /// Replaces the metadata of a proposed token, e.g. to fix a typo while the proposal waits
+    /// for approval. The symbol can only change in case. The difference in the args storage
+    /// cost is charged to or refunded from the payer's storage balance.
+    pub fn update_pending_metadata(&mut self, token_id: TokenId, metadata: FungibleTokenMetadata) {
+        let initial_storage_usage = env::storage_usage();
+        let mut proposal = self.proposals.get(&token_id).expect("Proposal not found");
+        assert_eq!(
+            env::predecessor_account_id(),
+            proposal.creator_id,
+            "Only the creator can update the metadata"
+        );
+        assert_eq!(
+            metadata.symbol.to_ascii_lowercase(),
+            proposal.args.metadata.symbol.to_ascii_lowercase(),
+            "Symbol can't be changed"
+        );
+        metadata.assert_valid();
+        self.config.assert_valid_decimals(metadata.decimals);
+        self.internal_assert_not_confusable(&metadata);
+        self.internal_assert_not_similar(&metadata);
+        if let Some(domain) = self.internal_check_metadata_domains(&metadata) {
+            panic!("Metadata references the denied domain {}", domain);
+        }
+        if normalize_name(&metadata.name) != normalize_name(&proposal.args.metadata.name) {
+            assert!(
+                self.is_name_available(metadata.name.clone()),
+                "Token name is not available"
+            );
+        }
+
+        let extra_init_json = proposal.extra_init_json.as_ref();
+        let previous_cost = self.get_args_storage_cost(&proposal.args, extra_init_json);
+        proposal.args.metadata = metadata;
+        let cost = self.get_args_storage_cost(&proposal.args, extra_init_json);
+        let balance = self
+            .internal_storage_balance(&proposal.payer_id)
+            .unwrap_or(0);
+        if cost > previous_cost {
+            assert!(
+                balance >= cost - previous_cost,
+                "Not enough storage balance"
+            );
+        }
+        self.internal_set_storage_balance(
+            &proposal.payer_id,
+            balance + previous_cost - cost,
+            "metadata_update",
+        );
+        self.pending_escrow = self.pending_escrow + cost - previous_cost;
+        proposal.escrow = (proposal.escrow.0 + cost - previous_cost).into();
+        self.proposals.insert(&token_id, &proposal);
+        self.internal_settle_storage(&proposal.payer_id, initial_storage_usage);
+    }
+
+    /// Finalizes the token creation. On failure the registry entry is removed and the escrow is
+    /// returned to the creator's storage balance, otherwise the creation fee is collected.
+    #[private]
+    pub fn on_create_token(&mut self, token_id: TokenId) -> bool {
+        let initial_storage_usage = env::storage_usage();
+        let deployment = match self.deployments.remove(&token_id) {
+            Some(deployment) => deployment,
+            // Aborted by `on_namespace_created` already.
+            None => return false,
+        };
+        let payer_id = deployment.proposal.payer_id.clone();
+        // A namespace returns the deposit of a failed creation before resolving to `false`.
+        let success = if self.is_namespace_deployment(&token_id, &deployment.proposal) {
+            is_promise_success_and_not_false()
+        } else {
+            is_promise_success()
+        };
+        self.internal_log_diagnostic(
+            "deployment_result",
+            serde_json::json!({ "token_id": token_id, "step": "create_token", "success": success }),
+        );
+        if success {
+            self.internal_finish_deployment(token_id, deployment);
+        } else {
+            self.internal_abort_deployment(token_id, deployment.proposal);
+        }
+        self.internal_settle_callback_storage(&payer_id, initial_storage_usage);
+        success
+    }
+
+    /// Continues a project deployment with the creation of the token account once the namespace
+    /// account of the project exists. If the namespace couldn't be created its deposit came back
+    /// to the factory, so the deployment is aborted with the whole escrow refunded.
+    #[private]
+    pub fn on_namespace_created(
+        &mut self,
+        token_id: TokenId,
+        project: String,
+    ) -> PromiseOrValue<bool> {
+        let success = is_promise_success();
+        self.internal_log_diagnostic(
+            "deployment_result",
+            serde_json::json!({
+                "token_id": token_id,
+                "step": "create_namespace",
+                "success": success,
+            }),
+        );
+        if let Some(mut info) = self.projects.get(&project) {
+            info.namespace_created = success;
+            info.namespace_pending = false;
+            self.projects.insert(&project, &info);
+        }
+        let mut deployment = match self.deployments.get(&token_id) {
+            Some(deployment) => deployment,
+            None => return PromiseOrValue::Value(false),
+        };
+        if success {
+            deployment.stats.factory_gas_used += env::used_gas();
+            self.deployments.insert(&token_id, &deployment);
+            return self
+                .internal_namespace_create_token_account(&token_id, &project, &deployment.proposal)
+                .into();
+        }
+        let initial_storage_usage = env::storage_usage();
+        self.deployments.remove(&token_id);
+        let payer_id = deployment.proposal.payer_id.clone();
+        deployment.proposal.namespace_deposit = 0.into();
+        self.internal_abort_deployment(token_id, deployment.proposal);
+        self.internal_settle_callback_storage(&payer_id, initial_storage_usage);
+        PromiseOrValue::Value(false)
+    }
+
+    /// First step of the split deployment. Once the token account exists, its code is deployed
+    /// and initialized in a separate receipt.
+    #[private]
+    pub fn on_account_created(&mut self, token_id: TokenId) -> PromiseOrValue<bool> {
+        let success = is_promise_success();
+        self.internal_log_diagnostic(
+            "deployment_result",
+            serde_json::json!({
+                "token_id": token_id,
+                "step": "create_account",
+                "success": success,
+            }),
+        );
+        if success {
+            let mut deployment = self
+                .deployments
+                .get(&token_id)
+                .expect("Deployment not found");
+            deployment.status = DeploymentStatus::Deploying;
+            deployment.stats.factory_gas_used += env::used_gas();
+            self.deployments.insert(&token_id, &deployment);
+            self.internal_deploy_code(token_id, &deployment.proposal)
+                .into()
+        } else {
+            let initial_storage_usage = env::storage_usage();
+            let deployment = self
+                .deployments
+                .remove(&token_id)
+                .expect("Deployment not found");
+            let payer_id = deployment.proposal.payer_id.clone();
+            self.internal_abort_deployment(token_id, deployment.proposal);
+            self.internal_settle_callback_storage(&payer_id, initial_storage_usage);
+            PromiseOrValue::Value(false)
+        }
+    }
+
+    /// Second step of the split deployment. The token account already holds the escrow, so a
+    /// failed deployment is kept around to be retried with `retry_deployment`.
+    #[private]
+    pub fn on_token_deployed(&mut self, token_id: TokenId) -> bool {
+        let mut deployment = self
+            .deployments
+            .get(&token_id)
+            .expect("Deployment not found");
+        let success = is_promise_success();
+        self.internal_log_diagnostic(
+            "deployment_result",
+            serde_json::json!({ "token_id": token_id, "step": "deploy_code", "success": success }),
+        );
+        if success {
+            let initial_storage_usage = env::storage_usage();
+            let payer_id = deployment.proposal.payer_id.clone();
+            self.deployments.remove(&token_id);
+            self.internal_finish_deployment(token_id, deployment);
+            self.internal_settle_callback_storage(&payer_id, initial_storage_usage);
+            true
+        } else {
+            deployment.status = DeploymentStatus::DeployFailed;
+            self.deployments.insert(&token_id, &deployment);
+            env::log(format!("Failed to deploy the code of token {}", token_id).as_bytes());
+            false
+        }
+    }
+
+    /// Continues a deployment in the strict mode once the probe of the token account resolved.
+    /// A transfer of nothing only succeeds if the account exists, in which case the creation is
+    /// aborted before any of the escrow is spent.
+    #[private]
+    pub fn on_token_account_probed(&mut self, token_id: TokenId) -> PromiseOrValue<bool> {
+        let deployment = self
+            .deployments
+            .remove(&token_id)
+            .expect("Deployment not found");
+        if is_promise_success() {
+            env::log(
+                format!(
+                    "Token account {} exists already",
+                    self.get_token_account_id(&token_id)
+                )
+                .as_bytes(),
+            );
+            let initial_storage_usage = env::storage_usage();
+            let payer_id = deployment.proposal.payer_id.clone();
+            self.internal_abort_deployment(token_id, deployment.proposal);
+            self.internal_settle_callback_storage(&payer_id, initial_storage_usage);
+            PromiseOrValue::Value(false)
+        } else {
+            self.internal_create_token_account(token_id, deployment.proposal, deployment.stats)
+                .into()
+        }
+    }
+
+    /// Retries deploying the code of a token, whose account was created by the split deployment
+    /// but the code deployment failed. Can be called by the creator, its managers or the owner.
+    pub fn retry_deployment(&mut self, token_id: TokenId) -> Promise {
+        self.assert_not_migrating();
+        let mut deployment = self
+            .deployments
+            .get(&token_id)
+            .expect("Deployment not found");
+        let account_id = env::predecessor_account_id();
+        assert!(
+            account_id == self.owner_id
+                || self.internal_is_token_manager(
+                    &token_id,
+                    &deployment.proposal.creator_id,
+                    &account_id
+                ),
+            "Only the creator, a manager or the owner can retry the deployment"
+        );
+        assert!(
+            deployment.status == DeploymentStatus::DeployFailed,
+            "Deployment can't be retried"
+        );
+        deployment.status = DeploymentStatus::Deploying;
+        self.deployments.insert(&token_id, &deployment);
+        self.internal_deploy_code(token_id, &deployment.proposal)
+    }
+
+    /// Whether the token account is created by a namespace, the project's or the configured
+    /// token namespace, rather than by the factory.
+    fn is_namespace_deployment(&self, token_id: &str, proposal: &Proposal) -> bool {
+        split_project(token_id).1.is_some()
+            || (self.config.token_namespace_id.is_some() && !proposal.preprovisioned)
+    }
+
+    /// Gas the deployment promises need, so callers such as other contracts find out they
+    /// attached too little gas before any state is changed on their behalf.
+    fn get_deploy_gas(&self, token_id: &str, proposal: &Proposal) -> Gas {
+        if split_project(token_id).1.is_some() {
+            // The callback of the namespace creation passes on the gas of the token creation.
+            return self.config.init_gas
+                + NAMESPACE_INIT_GAS
+                + NAMESPACE_CALL_GAS
+                + 2 * self.config.callback_gas;
+        }
+        let gas = if self.config.split_deployment {
+            self.config.init_gas + 4 * self.config.callback_gas
+        } else if self.config.token_namespace_id.is_some() {
+            self.config.init_gas + NAMESPACE_CALL_GAS + self.config.callback_gas
+        } else if self.get_owner_registration_deposit(proposal.args.template.as_ref()) > 0 {
+            self.config.init_gas + OWNER_REGISTRATION_GAS + self.config.callback_gas
+        } else {
+            self.config.init_gas + self.config.callback_gas
+        };
+        if self.config.probe_token_accounts {
+            // The probe callback creates the account with the rest.
+            gas + self.config.callback_gas
+        } else {
+            gas
+        }
+    }
+
+    pub(crate) fn internal_deploy(&mut self, token_id: TokenId, mut proposal: Proposal) -> Promise {
+        assert!(
+            env::prepaid_gas() - env::used_gas() >= self.get_deploy_gas(&token_id, &proposal),
+            "Not enough gas attached, need at least {} for the deployment",
+            self.get_deploy_gas(&token_id, &proposal)
+        );
+        assert!(
+            self.deployments.get(&token_id).is_none(),
+            "Token is already being deployed"
+        );
+        let token_account_id = self.get_token_account_id(&token_id);
+        let initial_storage_usage = env::storage_usage();
+
+        assert!(
+            self.tokens
+                .insert(
+                    &token_id,
+                    &TokenRecord {
+                        args: proposal.args.clone(),
+                        creator_id: proposal.creator_id.clone(),
+                        frozen: false,
+                        creation_stats: None,
+                        last_seen_ok: None,
+                        unresponsive: false,
+                        full_access_key: proposal.public_key.clone(),
+                        detached_account_id: None,
+                        fee_on_transfer: proposal.args.has_transfer_fee(),
+                        created_at: Some(env::block_timestamp().into()),
+                    }
+                )
+                .is_none(),
+            "Token ID is already taken"
+        );
+
+        self.internal_add_to_symbol_index(&proposal.args.metadata.symbol, &token_id);
+        self.internal_add_to_name_index(&proposal.args.metadata.name);
+        self.internal_add_to_tag_index(&proposal.args.tags, &token_id);
+        self.internal_add_to_owner_index(proposal.args.owner_id.as_ref(), &token_id);
+        self.internal_add_to_campaign(proposal.args.campaign.as_ref(), &token_id);
+        let similarity_flag = if self.config.similarity_check == SimilarityCheck::Flag {
+            self.internal_find_similar(&proposal.args.metadata)
+        } else {
+            None
+        };
+        if let Some(flag) = &similarity_flag {
+            self.similarity_flags.insert(&token_id, flag);
+        }
+
+        let registry_bytes = env::storage_usage() - initial_storage_usage;
+        let stats = CreationStats {
+            registry_bytes,
+            code_bytes: self
+                .get_template_code_info(proposal.args.template.as_ref())
+                .code_size,
+            gas_attached: self.get_deploy_gas(&token_id, &proposal),
+            factory_gas_used: 0,
+        };
+
+        self.internal_emit(FactoryEvent::TokenCreationStarted {
+            token_id: token_id.clone(),
+            token_account_id: token_account_id.clone(),
+            creator_id: proposal.creator_id.clone(),
+        });
+        if let Some(flag) = similarity_flag {
+            self.internal_emit(FactoryEvent::TokenFlaggedSimilar {
+                token_id: token_id.clone(),
+                similar_to: flag.similar_to,
+                similarity_bps: flag.similarity_bps,
+            });
+        }
+
+        if proposal.preprovisioned {
+            // The account and its code exist already, so only a transfer and the init remain.
+            let promise = Promise::new(token_account_id)
+                .transfer(proposal.token_deposit())
+                .function_call(
+                    b"new".to_vec(),
+                    get_init_args(&proposal.args, proposal.extra_init_json.as_ref()),
+                    0,
+                    self.config.init_gas,
+                );
+            self.deployments.insert(
+                &token_id,
+                &Deployment {
+                    proposal,
+                    status: DeploymentStatus::Deploying,
+                    stats: CreationStats {
+                        factory_gas_used: env::used_gas(),
+                        code_bytes: 0,
+                        ..stats
+                    },
+                },
+            );
+            return promise.then(ext_self::on_create_token(
+                token_id,
+                &env::current_account_id(),
+                0,
+                self.config.callback_gas,
+            ));
+        }
+
+        if let Some(project) = split_project(&token_id)
+            .1
+            .map(|project| project.to_string())
+        {
+            let promise = self.internal_deploy_to_project(&token_id, &project, &mut proposal);
+            self.deployments.insert(
+                &token_id,
+                &Deployment {
+                    proposal,
+                    status: DeploymentStatus::Deploying,
+                    stats: CreationStats {
+                        factory_gas_used: env::used_gas(),
+                        ..stats
+                    },
+                },
+            );
+            return promise.then(ext_self::on_create_token(
+                token_id,
+                &env::current_account_id(),
+                0,
+                self.config.callback_gas,
+            ));
+        }
+
+        if self.config.probe_token_accounts {
+            let gas = self.get_deploy_gas(&token_id, &proposal);
+            self.deployments.insert(
+                &token_id,
+                &Deployment {
+                    proposal,
+                    status: DeploymentStatus::Probing,
+                    stats: CreationStats {
+                        factory_gas_used: env::used_gas(),
+                        ..stats
+                    },
+                },
+            );
+            return Promise::new(token_account_id).transfer(0).then(
+                ext_self::on_token_account_probed(token_id, &env::current_account_id(), 0, gas),
+            );
+        }
+        self.internal_create_token_account(token_id, proposal, stats)
+    }
+
+    /// Creates the token account and deploys its code, or only creates it for the split
+    /// deployment.
+    fn internal_create_token_account(
+        &mut self,
+        token_id: TokenId,
+        proposal: Proposal,
+        stats: CreationStats,
+    ) -> Promise {
+        if let Some(namespace_id) = self.config.token_namespace_id.clone() {
+            return self.internal_deploy_to_namespace(
+                token_id,
+                namespace_id.into(),
+                proposal,
+                stats,
+            );
+        }
+        let token_account_id = self.get_token_account_id(&token_id);
+        // The split deployment is refused for templates registering the owner, and a toggled
+        // config leaves the whole deposit with the token account.
+        let owner_registration = if self.config.split_deployment {
+            0
+        } else {
+            self.get_owner_registration_deposit(proposal.args.template.as_ref())
+        };
+        let mut promise = Promise::new(token_account_id)
+            .create_account()
+            .transfer(proposal.token_deposit() - owner_registration);
+        if let Some(public_key) = proposal.public_key.clone() {
+            promise = promise.add_full_access_key(public_key.into());
+        }
+
+        if self.config.split_deployment {
+            self.deployments.insert(
+                &token_id,
+                &Deployment {
+                    proposal,
+                    status: DeploymentStatus::CreatingAccount,
+                    stats: CreationStats {
+                        factory_gas_used: stats.factory_gas_used + env::used_gas(),
+                        ..stats
+                    },
+                },
+            );
+            return promise.then(ext_self::on_account_created(
+                token_id,
+                &env::current_account_id(),
+                0,
+                self.config.init_gas + 3 * self.config.callback_gas,
+            ));
+        }
+
+        let mut promise = promise
+            .deploy_contract(self.internal_get_token_code(proposal.args.template.as_ref()))
+            .function_call(
+                b"new".to_vec(),
+                get_init_args(&proposal.args, proposal.extra_init_json.as_ref()),
+                0,
+                self.config.init_gas,
+            );
+        if owner_registration > 0 {
+            promise = promise.function_call(
+                b"storage_deposit".to_vec(),
+                serde_json::json!({
+                    "account_id": proposal.args.owner_id,
+                    "registration_only": true,
+                })
+                .to_string()
+                .into_bytes(),
+                owner_registration,
+                OWNER_REGISTRATION_GAS,
+            );
+        }
+        if let (Some(public_key), true) = (
+            proposal.public_key.clone(),
+            proposal.args.delete_key_after_init,
+        ) {
+            promise = promise.delete_key(public_key.into());
+        }
+        self.deployments.insert(
+            &token_id,
+            &Deployment {
+                proposal,
+                status: DeploymentStatus::Deploying,
+                stats: CreationStats {
+                    factory_gas_used: stats.factory_gas_used + env::used_gas(),
+                    ..stats
+                },
+            },
+        );
+        promise.then(ext_self::on_create_token(
+            token_id,
+            &env::current_account_id(),
+            0,
+            self.config.callback_gas,
+        ))
+    }
+
+    /// Creates the token account through the configured token namespace, which is set up by its
+    /// own key holder, so no namespace deposit is collected. If the creation fails the token
+    /// deposit is refunded to the namespace, which returns it to the factory.
+    fn internal_deploy_to_namespace(
+        &mut self,
+        token_id: TokenId,
+        namespace_id: AccountId,
+        proposal: Proposal,
+        stats: CreationStats,
+    ) -> Promise {
+        let promise = Promise::new(namespace_id).function_call(
+            b"create_token_account".to_vec(),
+            NamespaceCreateArgs {
+                name: token_id.clone(),
+                public_key: proposal
+                    .public_key
+                    .clone()
+                    .map(|public_key| public_key.into()),
+                code: self.internal_get_token_code(proposal.args.template.as_ref()),
+                init_args: get_init_args(&proposal.args, proposal.extra_init_json.as_ref()),
+                init_gas: self.config.init_gas,
+                delete_key_after_init: proposal.args.delete_key_after_init,
+            }
+            .try_to_vec()
+            .unwrap(),
+            proposal.token_deposit(),
+            self.config.init_gas + NAMESPACE_CALL_GAS,
+        );
+        self.deployments.insert(
+            &token_id,
+            &Deployment {
+                proposal,
+                status: DeploymentStatus::Deploying,
+                stats: CreationStats {
+                    factory_gas_used: stats.factory_gas_used + env::used_gas(),
+                    ..stats
+                },
+            },
+        );
+        promise.then(ext_self::on_create_token(
+            token_id,
+            &env::current_account_id(),
+            0,
+            self.config.callback_gas,
+        ))
+    }
+
+    /// Creates the token account through the project namespace, creating the namespace account
+    /// first if the project doesn't have one yet. The split deployment isn't used for projects.
+    fn internal_deploy_to_project(
+        &mut self,
+        token_id: &str,
+        project: &str,
+        proposal: &mut Proposal,
+    ) -> Promise {
+        let namespace_account_id = format!("{}.{}", project, env::current_account_id());
+        let mut info = self
+            .projects
+            .get(&project.to_string())
+            .expect("Project not found");
+        let namespace_deposit = proposal.namespace_deposit.0;
+        if info.namespace_created || info.namespace_pending {
+            // The namespace was created by another deployment since this one was requested, or
+            // is still being created by one. Then the call can arrive before the namespace
+            // exists, and the deployment fails with the whole escrow refunded.
+            if namespace_deposit > 0 {
+                let balance = self
+                    .internal_storage_balance(&proposal.payer_id)
+                    .unwrap_or(0);
+                self.internal_set_storage_balance(
+                    &proposal.payer_id,
+                    balance + namespace_deposit,
+                    "namespace_refund",
+                );
+                self.pending_escrow -= namespace_deposit;
+                proposal.escrow = (proposal.escrow.0 - namespace_deposit).into();
+                proposal.namespace_deposit = 0.into();
+            }
+            return self.internal_namespace_create_token_account(token_id, project, proposal);
+        }
+        info.namespace_pending = true;
+        self.projects.insert(&project.to_string(), &info);
+        Promise::new(namespace_account_id)
+            .create_account()
+            .transfer(namespace_deposit)
+            .deploy_contract(NAMESPACE_WASM_CODE.to_vec())
+            .function_call(b"new".to_vec(), b"{}".to_vec(), 0, NAMESPACE_INIT_GAS)
+            .then(ext_self::on_namespace_created(
+                token_id.to_string(),
+                project.to_string(),
+                &env::current_account_id(),
+                0,
+                self.config.init_gas + NAMESPACE_CALL_GAS + self.config.callback_gas,
+            ))
+    }
+
+    /// Calls the namespace of the project to create the token account. If the creation fails the
+    /// token deposit is refunded to the namespace, which returns it to the factory.
+    fn internal_namespace_create_token_account(
+        &self,
+        token_id: &str,
+        project: &str,
+        proposal: &Proposal,
+    ) -> Promise {
+        let namespace_account_id = format!("{}.{}", project, env::current_account_id());
+        let token_deposit = proposal.token_deposit();
+        Promise::new(namespace_account_id).function_call(
+            b"create_token_account".to_vec(),
+            NamespaceCreateArgs {
+                name: split_project(token_id).0.to_string(),
+                public_key: proposal
+                    .public_key
+                    .clone()
+                    .map(|public_key| public_key.into()),
+                code: self.internal_get_token_code(proposal.args.template.as_ref()),
+                init_args: get_init_args(&proposal.args, proposal.extra_init_json.as_ref()),
+                init_gas: self.config.init_gas,
+                delete_key_after_init: proposal.args.delete_key_after_init,
+            }
+            .try_to_vec()
+            .unwrap(),
+            token_deposit,
+            self.config.init_gas + NAMESPACE_CALL_GAS,
+        )
+    }
+
+    fn internal_deploy_code(&self, token_id: TokenId, proposal: &Proposal) -> Promise {
+        Promise::new(self.get_token_account_id(&token_id))
+            .deploy_contract(self.internal_get_token_code(proposal.args.template.as_ref()))
+            .function_call(
+                b"new".to_vec(),
+                get_init_args(&proposal.args, proposal.extra_init_json.as_ref()),
+                0,
+                self.config.init_gas,
+            )
+            .then(ext_self::on_token_deployed(
+                token_id,
+                &env::current_account_id(),
+                0,
+                self.config.callback_gas,
+            ))
+    }
+
+    fn internal_finish_deployment(&mut self, token_id: TokenId, deployment: Deployment) {
+        let Deployment {
+            proposal,
+            mut stats,
+            ..
+        } = deployment;
+        stats.factory_gas_used += env::used_gas();
+        self.total_created += 1;
+        let mut requested_at = None;
+        if let Some(mut token) = self.tokens.get(&token_id) {
+            requested_at = token.created_at;
+            token.creation_stats = Some(stats.clone());
+            if proposal.args.delete_key_after_init {
+                token.full_access_key = None;
+            }
+            self.tokens.insert(&token_id, &token);
+        }
+        self.creation_stats_count += 1;
+        self.creation_stats_total.add(&stats);
+        self.internal_record_template_creation(&proposal.args);
+
+        self.pending_escrow -= proposal.escrow.0;
+        self.internal_collect_fee(proposal.fee.0, proposal.referrer_id.clone());
+        self.internal_record_creation(&proposal.creator_id);
+        let leaf = registry_leaf(&token_id, &proposal.args);
+        let mut tree = self.registry_tree.get().unwrap();
+        tree.append(leaf.clone());
+        self.registry_tree.set(&tree);
+        self.registry_leaves.push(&leaf);
+        // Mirrors the refund of `internal_mint_launch_certificate`.
+        let refund = if self.config.launch_certificate.is_none() {
+            proposal.certificate_deposit.0
+        } else {
+            0
+        };
+        self.creation_receipts.insert(
+            &token_id,
+            &CreationReceipt {
+                creator_id: proposal.creator_id.clone(),
+                payer_id: proposal.payer_id.clone(),
+                referrer_id: proposal.referrer_id.clone(),
+                escrow_consumed: (proposal.escrow.0 - refund).into(),
+                fee: proposal.fee,
+                token_deposit: proposal.token_deposit().into(),
+                namespace_deposit: proposal.namespace_deposit,
+                certificate_deposit: (proposal.certificate_deposit.0 - refund).into(),
+                refund: refund.into(),
+                requested_at,
+                finished_block_height: env::block_index().into(),
+                finished_at: env::block_timestamp().into(),
+                creation_index: self.total_created.into(),
+                registry_leaf_index: (self.registry_leaves.len() - 1).into(),
+            },
+        );
+        self.internal_mint_launch_certificate(&token_id, &proposal);
+        self.internal_release_prelaunch(&token_id, &proposal.creator_id);
+        self.internal_emit(FactoryEvent::TokenCreationFinished {
+            token_account_id: self.get_token_account_id(&token_id),
+            token_id,
+            creator_id: proposal.creator_id,
+            success: true,
+        });
+    }
+
+    /// Mints the launch certificate to the creator, or refunds its deposit to the creator's
+    /// storage balance if the certificate was disabled since the creation was requested.
+    fn internal_mint_launch_certificate(&mut self, token_id: &TokenId, proposal: &Proposal) {
+        let mint_deposit = proposal.certificate_deposit.0;
+        if mint_deposit == 0 {
+            return;
+        }
+        if let Some(certificate) = self.config.launch_certificate.as_ref() {
+            let token_account_id = self.get_token_account_id(token_id);
+            let block_height = env::block_index();
+            ext_nft::nft_mint(
+                token_id.clone(),
+                proposal.creator_id.clone(),
+                CertificateMetadata {
+                    title: Some(format!("{} launch", proposal.args.metadata.symbol)),
+                    description: Some(format!(
+                        "Launched {} at block {}",
+                        token_account_id, block_height
+                    )),
+                    media: None,
+                    media_hash: None,
+                    copies: Some(1),
+                    issued_at: Some((env::block_timestamp() / 1_000_000).to_string()),
+                    expires_at: None,
+                    starts_at: None,
+                    updated_at: None,
+                    extra: Some(
+                        serde_json::json!({
+                            "token_account_id": token_account_id,
+                            "block_height": U64(block_height),
+                        })
+                        .to_string(),
+                    ),
+                    reference: None,
+                    reference_hash: None,
+                },
+                certificate.contract_id.as_ref(),
+                mint_deposit,
+                NFT_MINT_GAS,
+            );
+        } else {
+            let balance = self
+                .internal_storage_balance(&proposal.payer_id)
+                .unwrap_or(0);
+            self.internal_set_storage_balance(
+                &proposal.payer_id,
+                balance + mint_deposit,
+                "certificate_refund",
+            );
+        }
+    }
+
+    fn internal_abort_deployment(&mut self, token_id: TokenId, proposal: Proposal) {
+        self.total_failed += 1;
+        self.tokens.remove(&token_id);
+        self.internal_remove_icon_blob(&token_id);
+        self.token_managers.remove(&token_id);
+        self.similarity_flags.remove(&token_id);
+        self.internal_remove_from_symbol_index(&proposal.args.metadata.symbol, &token_id);
+        self.internal_remove_from_name_index(&proposal.args.metadata.name);
+        self.internal_remove_from_tag_index(&proposal.args.tags, &token_id);
+        self.internal_remove_from_owner_index(proposal.args.owner_id.as_ref(), &token_id);
+        self.internal_remove_from_campaign(proposal.args.campaign.as_ref(), &token_id);
+        // A namespace deposit left in the proposal went to the new namespace account. The token
+        // deposit of a failed namespace creation was returned to the factory by the namespace.
+        let balance = self
+            .internal_storage_balance(&proposal.payer_id)
+            .unwrap_or(0);
+        self.internal_set_storage_balance(
+            &proposal.payer_id,
+            balance + proposal.escrow.0 - proposal.namespace_deposit.0,
+            "failed_creation_refund",
+        );
+        self.pending_escrow -= proposal.escrow.0;
+        self.internal_cancel_prelaunch(&token_id);
+        self.internal_release_fee_discount(&proposal.payer_id, proposal.fee_discount.as_ref());
+        if proposal.preprovisioned {
+            // The failed init left the account without state, so it can be claimed again.
+            self.preprovisioned_accounts.insert(&token_id, &true);
+        }
+        self.internal_emit(FactoryEvent::TokenCreationFinished {
+            token_account_id: self.get_token_account_id(&token_id),
+            token_id,
+            creator_id: proposal.creator_id,
+            success: false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::test_utils::{accounts, get_created_receipts};
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    const CREATION_DEPOSIT: Balance = 10_000_000_000_000_000_000_000_000;
+    const ONE_NEAR: Balance = 1_000_000_000_000_000_000_000_000;
+
+    fn public_key() -> Base58PublicKey {
+        Base58PublicKey::try_from("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp").unwrap()
+    }
+
+    /// Calls `create_token` from `predecessor`, in a transaction signed by `signer` with the
+    /// creation deposit attached.
+    fn create_token(
+        factory: &mut TokenFactory,
+        predecessor: ValidAccountId,
+        signer: ValidAccountId,
+    ) -> PromiseOrValue<bool> {
+        testing_env!(context(predecessor.clone())
+            .signer_account_id(signer)
+            .attached_deposit(CREATION_DEPOSIT)
+            .build());
+        factory.create_token(
+            token_args(predecessor, "ABC"),
+            Some(public_key()),
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+    }
+
+    /// The receipts created so far, in their JSON form, since the fields of the mocked receipts
+    /// aren't public. They go through a string because `serde_json::Value` can't hold the u128
+    /// deposits.
+    fn created_receipts() -> Vec<serde_json::Value> {
+        get_created_receipts()
+            .iter()
+            .map(|receipt| serde_json::from_str(&serde_json::to_string(receipt).unwrap()).unwrap())
+            .collect()
+    }
+
+    /// Whether a receipt to the token account adds `public_key` as its full access key.
+    fn adds_full_access_key(public_key: &Base58PublicKey) -> bool {
+        let public_key = near_sdk::bs58::encode(&public_key.0).into_string();
+        created_receipts().iter().any(|receipt| {
+            receipt["receiver_id"] == "abc.".to_string() + accounts(0).as_ref()
+                && receipt["actions"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .any(|action| action["AddKeyWithFullAccess"]["public_key"] == public_key)
+        })
+    }
+
+    /// Creates the `abc.proj` token through a new project namespace, up to the namespace's
+    /// `create_token_account` promise.
+    fn create_project_token(factory: &mut TokenFactory) {
+        testing_env!(context(accounts(1))
+            .attached_deposit(CREATION_DEPOSIT)
+            .build());
+        factory.create_token(
+            token_args(accounts(1), "ABC"),
+            None,
+            None,
+            None,
+            None,
+            Some("proj".to_string()),
+            None,
+        );
+        set_promise_result(PromiseResult::Successful(vec![]));
+        factory.on_namespace_created("abc.proj".to_string(), "proj".to_string());
+    }
+
+    fn set_promise_result(result: PromiseResult) {
+        testing_env!(
+            context(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![result]
+        );
+    }
+
+    /// Proposes the `abc` token of `accounts(1)` to a factory that requires approval.
+    fn propose_token() -> TokenFactory {
+        let mut factory = setup_factory();
+        factory.config.approval_required = true;
+        drop(create_token(&mut factory, accounts(1), accounts(1)));
+        assert!(factory.proposals.get(&"abc".to_string()).is_some());
+        factory
+    }
+
+    fn fund_prelaunch(
+        factory: &mut TokenFactory,
+        supporter: ValidAccountId,
+        amount: Balance,
+    ) -> Balance {
+        testing_env!(context(supporter).attached_deposit(amount).build());
+        factory.fund_prelaunch("abc".to_string()).0
+    }
+
+    fn setup_preprovisioned() -> TokenFactory {
+        let mut factory = setup_factory();
+        factory
+            .preprovisioned_accounts
+            .insert(&"p1".to_string(), &true);
+        testing_env!(context(accounts(1)).build());
+        factory
+    }
+
+    #[test]
+    fn test_token_args_fields_name_every_field() {
+        let mut args = token_args(accounts(1), "ABC");
+        args.whitelist = Some(SaleWhitelist::MerkleRoot(vec![0; 32].into()));
+        args.icon_upload_id = Some("icon".to_string());
+        args.treasury_id = Some(accounts(2));
+        args.treasury_split_bps = Some(1_000);
+        args.transfer_fee_bps = Some(100);
+        args.fee_sink_id = Some(accounts(2));
+        args.tags = vec!["defi".to_string()];
+        args.template = Some("taxable".to_string());
+        args.delete_key_after_init = true;
+        args.campaign = Some("launch".to_string());
+        args.icon_blob = true;
+        args.localizations.insert(
+            "de".to_string(),
+            Localization {
+                name: "ABC".to_string(),
+                description: None,
+            },
+        );
+        let value = serde_json::to_value(&args).unwrap();
+        let mut fields: Vec<&str> = value
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(|key| key.as_str())
+            .collect();
+        let mut expected = TOKEN_ARGS_FIELDS.to_vec();
+        fields.sort_unstable();
+        expected.sort_unstable();
+        assert_eq!(fields, expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "Extra init args can't override transfer_fee_bps")]
+    fn test_extra_init_args_cant_set_an_unset_transfer_fee() {
+        let mut factory = setup_factory();
+        testing_env!(context(accounts(1))
+            .attached_deposit(CREATION_DEPOSIT)
+            .build());
+        factory.create_token(
+            token_args(accounts(1), "ABC"),
+            None,
+            None,
+            Some(r#"{"transfer_fee_bps":10000}"#.to_string()),
+            None,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    fn test_init_args_pass_the_factory() {
+        setup_factory();
+        let init_args: serde_json::Value =
+            serde_json::from_slice(&get_init_args(&token_args(accounts(1), "ABC"), None)).unwrap();
+        assert_eq!(init_args["factory_id"], accounts(0).as_ref().as_str());
+    }
+
+    #[test]
+    #[should_panic(expected = "Extra init args can't override factory_id")]
+    fn test_extra_init_args_cant_override_the_factory() {
+        setup_factory();
+        get_init_args(
+            &token_args(accounts(1), "ABC"),
+            Some(&r#"{"factory_id":"attacker"}"#.to_string()),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Preprovisioned account not found")]
+    fn test_claim_unknown_preprovisioned_account() {
+        let mut factory = setup_preprovisioned();
+        factory.claim_preprovisioned("p2".to_string(), token_args(accounts(1), "ABC"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Symbol is reserved")]
+    fn test_claim_preprovisioned_with_reserved_symbol() {
+        let mut factory = setup_preprovisioned();
+        factory.reserved_symbols.insert(&"abc".to_string());
+        factory.claim_preprovisioned("p1".to_string(), token_args(accounts(1), "ABC"));
+    }
+
+    #[test]
+    fn test_relayed_creation_is_made_for_the_predecessor() {
+        let mut factory = setup_factory();
+        // A relayer signs the transaction of the delegated action.
+        create_token(&mut factory, accounts(1), accounts(2));
+        let proposal = factory
+            .deployments
+            .get(&"abc".to_string())
+            .unwrap()
+            .proposal;
+        assert_eq!(proposal.creator_id, accounts(1).as_ref().clone());
+        assert_eq!(proposal.payer_id, accounts(1).as_ref().clone());
+        // The key is the explicit one, not the relayer's.
+        assert!(adds_full_access_key(&public_key()));
+        assert!(factory.storage_balance_of(accounts(1)).is_some());
+        assert!(factory.storage_balance_of(accounts(2)).is_none());
+    }
+
+    #[test]
+    fn test_contract_caller_gets_the_result_through_the_promise() {
+        let mut factory = setup_factory();
+        // A DAO contract creates the token with the deposit attached to the same call.
+        let result = create_token(&mut factory, accounts(3), accounts(4));
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        // The receipts of a promise are only created once it is dropped.
+        drop(result);
+        assert!(adds_full_access_key(&public_key()));
+        let callback = created_receipts().pop().unwrap();
+        assert_eq!(callback["receiver_id"], accounts(0).as_ref().as_str());
+        assert_eq!(callback["actions"].as_array().unwrap().len(), 1);
+        assert_eq!(
+            callback["actions"][0]["FunctionCall"]["method_name"],
+            "on_create_token"
+        );
+
+        testing_env!(
+            context(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        assert!(factory.on_create_token("abc".to_string()));
+        let token = factory.tokens.get(&"abc".to_string()).unwrap();
+        assert_eq!(token.creator_id, accounts(3).as_ref().clone());
+        // What's left of the deposit stays with the contract.
+        assert!(factory.storage_balance_of(accounts(3)).is_some());
+        assert!(factory.storage_balance_of(accounts(4)).is_none());
+    }
+
+    /// Creates the `abc` token of `accounts(1)` with its icon uploaded as a blob.
+    fn create_token_with_icon_blob(factory: &mut TokenFactory, icon: &str) {
+        testing_env!(context(accounts(1)).attached_deposit(ONE_NEAR).build());
+        factory.storage_deposit();
+        factory.upload_icon_chunk("icon".to_string(), icon.to_string());
+        factory.finalize_icon("icon".to_string());
+        let mut args = token_args(accounts(1), "ABC");
+        args.icon_upload_id = Some("icon".to_string());
+        args.icon_blob = true;
+        testing_env!(context(accounts(1))
+            .attached_deposit(CREATION_DEPOSIT)
+            .build());
+        drop(factory.create_token(args, None, None, None, None, None, None));
+    }
+
+    #[test]
+    fn test_icon_blob_chunks_hash_to_the_reference_hash() {
+        let mut factory = setup_factory();
+        // Multibyte characters across the chunk boundaries.
+        let icon = format!("data:image/svg+xml;utf8,<svg>{}</svg>", "é".repeat(40_000));
+        create_token_with_icon_blob(&mut factory, &icon);
+        set_promise_result(PromiseResult::Successful(vec![]));
+        assert!(factory.on_create_token("abc".to_string()));
+
+        let token_id = "abc".to_string();
+        let chunks = factory.get_icon(token_id.clone(), 0).unwrap().chunks;
+        assert_eq!(chunks, 3);
+        let data: String = (0..chunks)
+            .map(|chunk| factory.get_icon(token_id.clone(), chunk).unwrap().data)
+            .collect();
+        assert_eq!(data, icon);
+        assert!(factory.get_icon(token_id.clone(), chunks).is_none());
+        let metadata = factory.tokens.get(&token_id).unwrap().args.metadata;
+        assert_eq!(metadata.icon, None);
+        assert_eq!(
+            metadata.reference.unwrap(),
+            format!("near://{}/get_icon", accounts(0))
+        );
+        assert_eq!(
+            metadata.reference_hash.unwrap().0,
+            env::sha256(data.as_bytes())
+        );
+    }
+
+    #[test]
+    fn test_failed_creation_removes_the_icon_blob() {
+        let mut factory = setup_factory();
+        create_token_with_icon_blob(
+            &mut factory,
+            &format!("data:image/png;{}", "i".repeat(40_000)),
+        );
+        let token_id = "abc".to_string();
+        assert!(factory.get_icon(token_id.clone(), 1).is_some());
+        set_promise_result(PromiseResult::Failed);
+        assert!(!factory.on_create_token(token_id.clone()));
+        assert!(factory.icon_blobs.get(&token_id).is_none());
+        for chunk in 0..2 {
+            assert!(factory
+                .icon_chunks
+                .get(&(token_id.clone(), chunk))
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn test_failed_namespace_creation_refunds_the_returned_deposit() {
+        let mut factory = setup_factory();
+        create_project_token(&mut factory);
+        let token_id = "abc.proj".to_string();
+        let proposal = factory.deployments.get(&token_id).unwrap().proposal;
+        assert!(proposal.namespace_deposit.0 > 0);
+        let balance = factory.storage_balance_of(accounts(1)).unwrap().total.0;
+
+        // The namespace returned the token deposit to the factory before resolving to `false`.
+        set_promise_result(PromiseResult::Successful(b"false".to_vec()));
+        let initial_storage_usage = env::storage_usage();
+        assert!(!factory.on_create_token(token_id.clone()));
+        let released_storage = (initial_storage_usage - env::storage_usage()) as Balance;
+        assert!(factory.tokens.get(&token_id).is_none());
+        assert_eq!(factory.pending_escrow, 0);
+        // Only the namespace deposit stays with the namespace account.
+        assert_eq!(
+            factory.storage_balance_of(accounts(1)).unwrap().total.0,
+            balance + proposal.escrow.0 - proposal.namespace_deposit.0
+                + released_storage * STORAGE_PRICE_PER_BYTE
+        );
+    }
+
+    #[test]
+    fn test_namespace_creation_resolving_to_true_finishes_the_deployment() {
+        let mut factory = setup_factory();
+        create_project_token(&mut factory);
+        set_promise_result(PromiseResult::Successful(b"true".to_vec()));
+        assert!(factory.on_create_token("abc.proj".to_string()));
+        assert!(factory.tokens.get(&"abc.proj".to_string()).is_some());
+        assert!(factory.deployments.get(&"abc.proj".to_string()).is_none());
+    }
+
+    #[test]
+    fn test_failed_creation_through_the_token_namespace_refunds_the_escrow() {
+        let mut factory = setup_factory();
+        factory.config.token_namespace_id = Some(accounts(2));
+        drop(create_token(&mut factory, accounts(1), accounts(1)));
+        let token_id = "abc".to_string();
+        assert_eq!(
+            factory.get_token_account_id(&token_id),
+            "abc.".to_string() + accounts(2).as_ref()
+        );
+        let proposal = factory.deployments.get(&token_id).unwrap().proposal;
+        assert_eq!(proposal.namespace_deposit.0, 0);
+        let balance = factory.storage_balance_of(accounts(1)).unwrap().total.0;
+
+        // The namespace returned the token deposit to the factory before resolving to `false`.
+        set_promise_result(PromiseResult::Successful(b"false".to_vec()));
+        let initial_storage_usage = env::storage_usage();
+        assert!(!factory.on_create_token(token_id.clone()));
+        let released_storage = (initial_storage_usage - env::storage_usage()) as Balance;
+        assert!(factory.tokens.get(&token_id).is_none());
+        assert_eq!(factory.pending_escrow, 0);
+        assert_eq!(
+            factory.storage_balance_of(accounts(1)).unwrap().total.0,
+            balance + proposal.escrow.0 + released_storage * STORAGE_PRICE_PER_BYTE
+        );
+    }
+
+    #[test]
+    fn test_prelaunch_pool_is_released_to_the_creator() {
+        let mut factory = propose_token();
+        let first = fund_prelaunch(&mut factory, accounts(2), ONE_NEAR);
+        assert!(first < ONE_NEAR);
+        // The second contribution of a supporter takes no more storage.
+        let contribution = fund_prelaunch(&mut factory, accounts(2), ONE_NEAR);
+        assert_eq!(contribution, first + ONE_NEAR);
+        let other = fund_prelaunch(&mut factory, accounts(3), ONE_NEAR);
+        let pool = factory.get_prelaunch_pool("abc".to_string()).unwrap();
+        assert_eq!(pool.supporters, 2);
+        assert_eq!(pool.total.0, contribution + other);
+        assert_eq!(factory.prelaunch_funds, contribution + other);
+
+        testing_env!(context(accounts(0)).build());
+        drop(factory.approve_token("abc".to_string()));
+        set_promise_result(PromiseResult::Successful(vec![]));
+        assert!(factory.on_create_token("abc".to_string()));
+        assert!(transfers(accounts(1), contribution + other));
+        assert!(factory.get_prelaunch_pool("abc".to_string()).is_none());
+        assert_eq!(factory.prelaunch_funds, 0);
+        assert_eq!(
+            factory
+                .get_prelaunch_contribution("abc".to_string(), accounts(2))
+                .unwrap()
+                .0,
+            contribution
+        );
+    }
+
+    #[test]
+    fn test_cancelled_prelaunch_pool_is_refunded() {
+        let mut factory = propose_token();
+        let contribution = fund_prelaunch(&mut factory, accounts(2), ONE_NEAR);
+        let other = fund_prelaunch(&mut factory, accounts(3), ONE_NEAR);
+        testing_env!(context(accounts(0)).build());
+        drop(factory.reject_token("abc".to_string(), "spam".to_string()));
+        assert!(
+            factory
+                .get_prelaunch_pool("abc".to_string())
+                .unwrap()
+                .cancelled
+        );
+
+        testing_env!(context(accounts(2)).build());
+        let initial_storage_usage = env::storage_usage();
+        drop(factory.refund_prelaunch("abc".to_string()));
+        let storage_refund =
+            Balance::from(initial_storage_usage - env::storage_usage()) * STORAGE_PRICE_PER_BYTE;
+        assert!(transfers(accounts(2), contribution + storage_refund));
+        assert!(factory
+            .get_prelaunch_contribution("abc".to_string(), accounts(2))
+            .is_none());
+        let pool = factory.get_prelaunch_pool("abc".to_string()).unwrap();
+        assert_eq!(pool.supporters, 1);
+        assert_eq!(pool.total.0, other);
+        assert_eq!(factory.prelaunch_funds, other);
+
+        // The last refund removes the pool.
+        testing_env!(context(accounts(3)).build());
+        drop(factory.refund_prelaunch("abc".to_string()));
+        assert!(factory.get_prelaunch_pool("abc".to_string()).is_none());
+        assert_eq!(factory.prelaunch_funds, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Prelaunch pool was cancelled")]
+    fn test_fund_cancelled_prelaunch_pool() {
+        let mut factory = propose_token();
+        fund_prelaunch(&mut factory, accounts(2), ONE_NEAR);
+        testing_env!(context(accounts(0)).build());
+        drop(factory.reject_token("abc".to_string(), "spam".to_string()));
+        // The pool waits for its refunds when the token is proposed again.
+        drop(create_token(&mut factory, accounts(1), accounts(1)));
+        fund_prelaunch(&mut factory, accounts(3), ONE_NEAR);
+    }
+
+    /// Queues the token of `accounts(1)` with the priority fee on a congested factory.
+    fn queue_token(factory: &mut TokenFactory, symbol: &str, priority_fee: Balance) {
+        testing_env!(context(accounts(1))
+            .attached_deposit(CREATION_DEPOSIT)
+            .build());
+        let result = factory.create_token(
+            token_args(accounts(1), symbol),
+            None,
+            None,
+            None,
+            None,
+            None,
+            Some(priority_fee.into()),
+        );
+        assert!(matches!(result, PromiseOrValue::Value(false)));
+    }
+
+    /// Token accounts the receipts created so far deploy to, in their order.
+    fn deployed_accounts() -> Vec<String> {
+        created_receipts()
+            .iter()
+            .filter(|receipt| {
+                serde_json::to_string(&receipt["actions"])
+                    .unwrap()
+                    .contains("DeployContract")
+            })
+            .map(|receipt| receipt["receiver_id"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_queue_is_processed_by_priority_fee_then_in_order() {
+        let mut factory = setup_factory();
+        factory.config.congested = true;
+        queue_token(&mut factory, "AAA", ONE_NEAR / 10);
+        queue_token(&mut factory, "BBB", ONE_NEAR / 2);
+        queue_token(&mut factory, "CCC", 0);
+        // Same fee as `BBB`, so it goes after it.
+        queue_token(&mut factory, "DDD", ONE_NEAR / 2);
+        assert_eq!(factory.creation_queue.len(), 4);
+
+        testing_env!(context(accounts(2))
+            .prepaid_gas(3 * MAX_PREPAID_GAS)
+            .build());
+        assert_eq!(factory.process_queue(3), 3);
+        let token_account = |token_id: &str| format!("{}.{}", token_id, accounts(0));
+        assert_eq!(
+            deployed_accounts(),
+            vec![
+                token_account("bbb"),
+                token_account("ddd"),
+                token_account("aaa")
+            ]
+        );
+        for token_id in &["bbb", "ddd", "aaa"] {
+            assert!(factory.deployments.get(&token_id.to_string()).is_some());
+        }
+        assert_eq!(factory.creation_queue.len(), 1);
+        assert!(factory.creation_queue.get(&"ccc".to_string()).is_some());
+
+        testing_env!(context(accounts(2)).prepaid_gas(MAX_PREPAID_GAS).build());
+        assert_eq!(factory.process_queue(10), 1);
+        assert_eq!(deployed_accounts(), vec![token_account("ccc")]);
+        assert!(factory.creation_queue.is_empty());
+    }
+
+    /// Deposit `get_required_deposit` asks `accounts(1)` to attach for the `ABC` token.
+    fn required_deposit(factory: &TokenFactory) -> Balance {
+        factory
+            .get_required_deposit(token_args(accounts(1), "ABC"), accounts(1), None, None)
+            .total
+            .0
+    }
+
+    fn deposit_and_create(factory: &mut TokenFactory, deposit: Balance) -> PromiseOrValue<bool> {
+        testing_env!(context(accounts(1)).attached_deposit(deposit).build());
+        factory.deposit_and_create(token_args(accounts(1), "ABC"), None)
+    }
+
+    #[test]
+    fn test_deposit_and_create_refunds_the_remainder() {
+        let mut factory = setup_factory();
+        let required_deposit = required_deposit(&factory);
+        let result = deposit_and_create(&mut factory, required_deposit + ONE_NEAR);
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        drop(result);
+        assert!(factory.deployments.get(&"abc".to_string()).is_some());
+        // Only the part above the required deposit goes back, the rest paid for the creation.
+        assert!(transfers(accounts(1), ONE_NEAR));
+        let balance = factory.storage_balance_of(accounts(1)).unwrap();
+        assert!(balance.available.0 < ONE_NEAR);
+    }
+
+    #[test]
+    fn test_deposit_and_create_uses_the_storage_balance() {
+        let mut factory = setup_factory();
+        let fresh =
+            factory.get_required_deposit(token_args(accounts(1), "ABC"), accounts(1), None, None);
+        testing_env!(context(accounts(1)).attached_deposit(ONE_NEAR).build());
+        factory.storage_deposit();
+        let required_deposit = required_deposit(&factory);
+        // The storage deposit paid for the registration and part of the creation.
+        assert_eq!(required_deposit, fresh.total.0 - ONE_NEAR);
+
+        drop(deposit_and_create(&mut factory, required_deposit));
+        assert!(factory.deployments.get(&"abc".to_string()).is_some());
+        // The registry entries were charged from the storage balance at their exact size.
+        assert_eq!(
+            factory.pending_escrow,
+            fresh.code_storage.0 + fresh.args_storage.0 + fresh.fee.0
+        );
+        // Both were credited before the creation was charged, and nothing is left to refund.
+        assert!(!created_receipts()
+            .iter()
+            .any(|receipt| receipt["receiver_id"] == accounts(1).as_ref().as_str()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Attached deposit is below the required deposit")]
+    fn test_deposit_and_create_below_the_required_deposit() {
+        let mut factory = setup_factory();
+        let required_deposit = required_deposit(&factory);
+        deposit_and_create(&mut factory, required_deposit - 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Attached deposit is below the required deposit")]
+    fn test_deposit_and_create_checks_the_deposit_again() {
+        let mut factory = setup_factory();
+        // The deposit was quoted before the owner raised the fee.
+        let required_deposit = required_deposit(&factory);
+        factory.config.creation_fee = (factory.config.creation_fee.0 + ONE_NEAR).into();
+        deposit_and_create(&mut factory, required_deposit);
+    }
+
+    const COMMIT_BLOCK: u64 = 100;
+
+    /// Registers `accounts(1)` and commits it to the `ABC` symbol with the salt at
+    /// `COMMIT_BLOCK`, on a factory that requires commitments.
+    fn commit_symbol(factory: &mut TokenFactory, salt: &str) {
+        factory.config.symbol_commit_required = true;
+        testing_env!(context(accounts(1))
+            .block_index(COMMIT_BLOCK)
+            .attached_deposit(CREATION_DEPOSIT)
+            .build());
+        factory.storage_deposit();
+        let hash = factory.get_symbol_commit_hash(accounts(1), "ABC".to_string(), salt.to_string());
+        factory.commit_symbol(hash);
+    }
+
+    fn reveal_and_create(
+        factory: &mut TokenFactory,
+        account_id: ValidAccountId,
+        block_index: u64,
+        salt: &str,
+    ) -> PromiseOrValue<bool> {
+        testing_env!(context(account_id.clone()).block_index(block_index).build());
+        factory.reveal_and_create(
+            "ABC".to_string(),
+            salt.to_string(),
+            token_args(account_id, "ABC"),
+            None,
+            None,
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_reveal_and_create_once_the_commitment_is_old_enough() {
+        let mut factory = setup_factory();
+        commit_symbol(&mut factory, "salt");
+        let min_commit_age_blocks = factory.config.min_commit_age_blocks;
+        let result = reveal_and_create(
+            &mut factory,
+            accounts(1),
+            COMMIT_BLOCK + min_commit_age_blocks,
+            "salt",
+        );
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        assert!(factory.deployments.get(&"abc".to_string()).is_some());
+        let hash =
+            factory.get_symbol_commit_hash(accounts(1), "ABC".to_string(), "salt".to_string());
+        assert!(factory.get_symbol_commit(hash).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Symbol commitment can't be revealed before it is 5 blocks old")]
+    fn test_reveal_and_create_too_early() {
+        let mut factory = setup_factory();
+        commit_symbol(&mut factory, "salt");
+        let min_commit_age_blocks = factory.config.min_commit_age_blocks;
+        drop(reveal_and_create(
+            &mut factory,
+            accounts(1),
+            COMMIT_BLOCK + min_commit_age_blocks - 1,
+            "salt",
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "Symbol commitment has expired")]
+    fn test_reveal_and_create_after_the_commitment_expired() {
+        let mut factory = setup_factory();
+        commit_symbol(&mut factory, "salt");
+        let max_commit_age_blocks = factory.config.max_commit_age_blocks;
+        drop(reveal_and_create(
+            &mut factory,
+            accounts(1),
+            COMMIT_BLOCK + max_commit_age_blocks + 1,
+            "salt",
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "Symbol commitment not found")]
+    fn test_reveal_and_create_with_another_salt() {
+        let mut factory = setup_factory();
+        commit_symbol(&mut factory, "salt");
+        drop(reveal_and_create(
+            &mut factory,
+            accounts(1),
+            COMMIT_BLOCK + 10,
+            "pepper",
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "Symbol commitment not found")]
+    fn test_reveal_and_create_by_another_account() {
+        let mut factory = setup_factory();
+        commit_symbol(&mut factory, "salt");
+        // The commitment binds the symbol to the committing account, so a bot seeing the reveal
+        // can't use the salt.
+        drop(reveal_and_create(
+            &mut factory,
+            accounts(2),
+            COMMIT_BLOCK + 10,
+            "salt",
+        ));
+    }
+
+    const DAY: u64 = 86_400_000_000_000;
+
+    /// Promotion halving the fee of 1 NEAR during the second day, for up to `max_per_account`
+    /// creations of each account.
+    fn set_fee_discount(factory: &mut TokenFactory, max_per_account: Option<u64>) {
+        factory.config.creation_fee = ONE_NEAR.into();
+        testing_env!(context(accounts(0)).build());
+        factory.set_fee_discount(
+            "launch".to_string(),
+            Some(FeeDiscount {
+                starts_at: DAY.into(),
+                ends_at: (2 * DAY).into(),
+                discount_bps: 5_000,
+                max_per_account,
+            }),
+        );
+    }
+
+    /// Creates the token of `accounts(1)` at the timestamp and returns the fee it was charged.
+    fn creation_fee(factory: &mut TokenFactory, symbol: &str, timestamp: u64) -> Balance {
+        testing_env!(context(accounts(1))
+            .block_timestamp(timestamp)
+            .attached_deposit(CREATION_DEPOSIT)
+            .build());
+        drop(factory.create_token(
+            token_args(accounts(1), symbol),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        ));
+        let token_id = symbol.to_ascii_lowercase();
+        let proposal = match factory.deployments.get(&token_id) {
+            Some(deployment) => deployment.proposal,
+            None => factory.proposals.get(&token_id).unwrap(),
+        };
+        proposal.fee.0
+    }
+
+    #[test]
+    fn test_fee_discount_applies_within_its_time_window() {
+        let mut factory = setup_factory();
+        set_fee_discount(&mut factory, None);
+        assert_eq!(creation_fee(&mut factory, "AAA", DAY - 1), ONE_NEAR);
+        assert_eq!(creation_fee(&mut factory, "BBB", DAY), ONE_NEAR / 2);
+        assert_eq!(creation_fee(&mut factory, "CCC", 2 * DAY - 1), ONE_NEAR / 2);
+        assert_eq!(creation_fee(&mut factory, "DDD", 2 * DAY), ONE_NEAR);
+    }
+
+    #[test]
+    fn test_fee_discount_is_capped_per_account() {
+        let mut factory = setup_factory();
+        set_fee_discount(&mut factory, Some(2));
+        assert_eq!(creation_fee(&mut factory, "AAA", DAY), ONE_NEAR / 2);
+        assert_eq!(creation_fee(&mut factory, "BBB", DAY), ONE_NEAR / 2);
+        assert_eq!(creation_fee(&mut factory, "CCC", DAY), ONE_NEAR);
+        assert_eq!(
+            factory
+                .fee_discount_uses
+                .get(&("launch".to_string(), accounts(1).into())),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn test_failed_creation_releases_the_fee_discount() {
+        let mut factory = setup_factory();
+        set_fee_discount(&mut factory, Some(1));
+        assert_eq!(creation_fee(&mut factory, "AAA", DAY), ONE_NEAR / 2);
+        set_promise_result(PromiseResult::Failed);
+        assert!(!factory.on_create_token("aaa".to_string()));
+        assert!(factory
+            .fee_discount_uses
+            .get(&("launch".to_string(), accounts(1).into()))
+            .is_none());
+        // The refunded creation didn't use up the discount.
+        assert_eq!(creation_fee(&mut factory, "BBB", DAY), ONE_NEAR / 2);
+    }
+
+    #[test]
+    fn test_rejected_proposal_releases_the_fee_discount() {
+        let mut factory = setup_factory();
+        factory.config.approval_required = true;
+        set_fee_discount(&mut factory, Some(1));
+        assert_eq!(creation_fee(&mut factory, "AAA", DAY), ONE_NEAR / 2);
+        testing_env!(context(accounts(0)).build());
+        drop(factory.reject_token("aaa".to_string(), "Spam".to_string()));
+        assert_eq!(creation_fee(&mut factory, "BBB", DAY), ONE_NEAR / 2);
+        assert_eq!(creation_fee(&mut factory, "CCC", DAY), ONE_NEAR);
+    }
+}
@@ -0,0 +1,134 @@
+//! Events and the admin log.
+use crate::*;
+
+/// Privileged call recorded in the admin log, with its arguments as a JSON string.
+#[derive(Serialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AdminLogEntry {
+    pub block_height: U64,
+    pub timestamp: U64,
+    pub actor_id: AccountId,
+    pub action: String,
+    pub details: String,
+}
+
+#[derive(Serialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RecordedEvent {
+    pub block_height: U64,
+    pub timestamp: U64,
+    #[serde(flatten)]
+    pub event: FactoryEvent,
+}
+
+#[near_bindgen]
+impl TokenFactory {
+    /// Logs the event and keeps it in the recent events ring buffer.
+    pub(crate) fn internal_emit(&mut self, event: FactoryEvent) {
+        event.emit();
+        let recorded_event = RecordedEvent {
+            block_height: env::block_index().into(),
+            timestamp: env::block_timestamp().into(),
+            event,
+        };
+        if self.recent_events.len() < MAX_RECENT_EVENTS {
+            self.recent_events.push(&recorded_event);
+        } else {
+            self.recent_events
+                .replace(self.event_count % MAX_RECENT_EVENTS, &recorded_event);
+        }
+        self.event_count += 1;
+    }
+
+    /// Appends a privileged call to the admin log, which is kept for good so the operator can be
+    /// audited without an indexer.
+    pub(crate) fn internal_log_admin_action(&mut self, action: &str, details: serde_json::Value) {
+        self.admin_log.push(&AdminLogEntry {
+            block_height: env::block_index().into(),
+            timestamp: env::block_timestamp().into(),
+            actor_id: env::predecessor_account_id(),
+            action: action.to_string(),
+            details: details.to_string(),
+        });
+    }
+
+    /// Logs a diagnostic of a decision point when `verbose_logging` is set. Logs of a failed
+    /// receipt are kept, so the diagnostics preceding a panic show what the check saw.
+    pub(crate) fn internal_log_diagnostic(&self, point: &str, details: serde_json::Value) {
+        if self.config.verbose_logging {
+            let log = serde_json::json!({ "point": point, "details": details });
+            env::log(format!("DIAGNOSTIC_JSON:{}", log).as_bytes());
+        }
+    }
+
+    pub fn get_admin_log(&self, from_index: u64, limit: Option<u64>) -> Vec<AdminLogEntry> {
+        page_range(from_index, limit, self.admin_log.len())
+            .filter_map(|index| self.admin_log.get(index))
+            .collect()
+    }
+
+    pub fn get_admin_log_length(&self) -> u64 {
+        self.admin_log.len()
+    }
+
+    /// Returns the most recent events, newest first.
+    pub fn get_recent_events(&self, limit: Option<u64>) -> Vec<RecordedEvent> {
+        let len = self.recent_events.len();
+        let limit = std::cmp::min(limit.unwrap_or(MAX_RECENT_EVENTS), len);
+        (1..=limit)
+            .filter_map(|offset| {
+                self.recent_events
+                    .get((self.event_count - offset) % MAX_RECENT_EVENTS)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::test_utils::accounts;
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    #[test]
+    fn test_recent_events_keep_the_newest_first() {
+        let mut factory = setup_factory();
+        for index in 0..MAX_RECENT_EVENTS + 5 {
+            // Each emission logs, and a call can't log more than 100 times.
+            testing_env!(context(accounts(0)).build());
+            factory.internal_emit(FactoryEvent::TokenUnfrozen {
+                token_id: format!("t{}", index),
+            });
+        }
+        let token_ids = |events: Vec<RecordedEvent>| -> Vec<String> {
+            events
+                .iter()
+                .map(|event| {
+                    let value = serde_json::to_value(event).unwrap();
+                    assert_eq!(value["event"], "token_unfrozen");
+                    value["data"]["token_id"].as_str().unwrap().to_string()
+                })
+                .collect()
+        };
+        assert_eq!(
+            token_ids(factory.get_recent_events(Some(3))),
+            vec!["t104", "t103", "t102"]
+        );
+        let events = factory.get_recent_events(None);
+        assert_eq!(events.len() as u64, MAX_RECENT_EVENTS);
+        assert_eq!(token_ids(events).last().unwrap(), "t5");
+    }
+
+    #[test]
+    fn test_admin_log_records_the_actor() {
+        let mut factory = setup_factory();
+        factory.reserve_symbols(vec!["ABC".to_string()]);
+        assert_eq!(factory.get_admin_log_length(), 1);
+        let entry = factory.get_admin_log(0, None).pop().unwrap();
+        assert_eq!(entry.actor_id, accounts(0).as_ref().clone());
+        assert_eq!(entry.action, "reserve_symbols");
+        assert_eq!(entry.details, r#"{"symbols":["ABC"]}"#);
+        assert!(factory.get_admin_log(1, None).is_empty());
+    }
+}
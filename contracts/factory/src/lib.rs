@@ -1,21 +1,287 @@
+// `AccountId` and `TokenId` are aliases of `String`, and the signatures keep them to say which
+// strings they take.
+#![allow(clippy::ptr_arg)]
+
+use factory_types::{
+    assert_valid_localizations, errors, Config, ConfusableCheck, CreationStats, Deprecation,
+    FactoryEvent, Localization, Role, SaleWhitelist, SimilarityCheck, SymbolValidation, TokenArgs,
+    TokenId, TokenIdMode, TokenRecord, VersionedTokenRecord, DEFAULT_CALLBACK_GAS,
+    HOLDER_MILESTONES, MAX_BPS, MAX_PAGE_SIZE, REGISTRY_ONLY_FIELDS, TOKEN_ARGS_FIELDS,
+};
 use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_contract_standards::storage_management::StorageBalance;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::{LookupMap, UnorderedMap};
+use near_sdk::collections::{LazyOption, LookupMap, UnorderedMap, UnorderedSet, Vector};
 use near_sdk::env::STORAGE_PRICE_PER_BYTE;
-use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::json_types::{Base58PublicKey, Base64VecU8, ValidAccountId, U128, U64};
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::serde_json;
 use near_sdk::{
-    env, near_bindgen, AccountId, Balance, BorshStorageKey, Gas, PanicOnDefault, Promise,
+    env, ext_contract, near_bindgen, AccountId, Balance, BorshStorageKey, Gas, PanicOnDefault,
+    Promise, PromiseOrValue, PromiseResult,
 };
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+mod admin;
+mod deploy;
+mod events;
+mod pricing;
+mod registry;
+mod storage;
+#[cfg(test)]
+mod test_utils;
+mod views;
+
+pub use crate::admin::*;
+pub use crate::deploy::*;
+pub use crate::events::*;
+pub use crate::registry::*;
+pub use crate::storage::*;
+pub use crate::views::*;
 
 near_sdk::setup_alloc!();
 
-const FT_WASM_CODE: &[u8] = include_bytes!("../../token/res/fungible_token.wasm");
+/// Built from the token and the namespace crates by the build script.
+const FT_WASM_CODE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/fungible_token.wasm"));
+const NAMESPACE_WASM_CODE: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/token_namespace.wasm"));
+/// ABI of the factory's methods in the near-abi format, generated by the build script into a
+/// zstd frame like the ABI near-sdk 4 embeds.
+#[cfg(any(target_arch = "wasm32", test))]
+const CONTRACT_ABI: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/token_factory_abi.zst"));
+
+/// Returns the ABI like the `__contract_abi` export of near-sdk 4, which near-sdk 3.1 doesn't
+/// generate. It doesn't read the contract state.
+#[cfg(target_arch = "wasm32")]
+#[no_mangle]
+pub extern "C" fn __contract_abi() {
+    extern "C" {
+        fn value_return(value_len: u64, value_ptr: u64);
+    }
+    unsafe { value_return(CONTRACT_ABI.len() as u64, CONTRACT_ABI.as_ptr() as u64) }
+}
 
-const EXTRA_BYTES: usize = 10000;
-const GAS: Gas = 50_000_000_000_000;
-type TokenId = String;
+/// Largest token code accepted by `set_token_code`, leaving room for the other actions and
+/// arguments in the receipts that carry the code under the 4 MiB receipt limit.
+const MAX_TOKEN_CODE_SIZE: usize = 3 * 1024 * 1024;
+const WASM_MAGIC: &[u8] = b"\0asm\x01\0\0\0";
+/// Name of the template used when the token args don't name one.
+const DEFAULT_TEMPLATE: &str = "default";
+const MAX_TEMPLATE_NAME_LENGTH: usize = 32;
+/// JSON schema of the init args of the built-in fungible token.
+const DEFAULT_TEMPLATE_SCHEMA: &str = r#"{
+  "type": "object",
+  "required": ["owner_id", "total_supply", "metadata"],
+  "properties": {
+    "owner_id": { "type": "string" },
+    "total_supply": { "type": "string", "pattern": "^[0-9]+$" },
+    "treasury_id": { "type": ["string", "null"] },
+    "treasury_split_bps": { "type": ["integer", "null"], "minimum": 0, "maximum": 10000 },
+    "metadata": {
+      "type": "object",
+      "required": ["spec", "name", "symbol", "decimals"],
+      "properties": {
+        "spec": { "type": "string" },
+        "name": { "type": "string" },
+        "symbol": { "type": "string" },
+        "icon": { "type": ["string", "null"] },
+        "reference": { "type": ["string", "null"] },
+        "reference_hash": { "type": ["string", "null"] },
+        "decimals": { "type": "integer", "minimum": 0, "maximum": 255 }
+      }
+    }
+  }
+}"#;
+/// JSON schema of the manifest taken by `create_from_manifest`.
+const LAUNCH_MANIFEST_SCHEMA: &str = r#"{
+  "type": "object",
+  "required": ["metadata", "total_supply"],
+  "additionalProperties": false,
+  "properties": {
+    "metadata": { "type": "object" },
+    "total_supply": { "type": "string", "pattern": "^[0-9]+$" },
+    "owner_id": { "type": ["string", "null"] },
+    "distribution": {
+      "type": ["object", "null"],
+      "required": ["treasury_id", "treasury_split_bps"],
+      "additionalProperties": false,
+      "properties": {
+        "treasury_id": { "type": "string" },
+        "treasury_split_bps": { "type": "integer", "minimum": 0, "maximum": 10000 }
+      }
+    },
+    "vesting": { "type": ["object", "array", "null"] },
+    "sale": {
+      "type": ["object", "null"],
+      "required": ["whitelist"],
+      "additionalProperties": false,
+      "properties": {
+        "whitelist": { "type": "object" }
+      }
+    },
+    "transfer_fee": {
+      "type": ["object", "null"],
+      "required": ["fee_bps", "fee_sink_id"],
+      "additionalProperties": false,
+      "properties": {
+        "fee_bps": { "type": "integer", "minimum": 0, "maximum": 10000 },
+        "fee_sink_id": { "type": "string" }
+      }
+    },
+    "icon_upload_id": { "type": ["string", "null"] },
+    "icon_blob": { "type": "boolean" },
+    "tags": { "type": "array", "items": { "type": "string" } },
+    "localizations": {
+      "type": "object",
+      "additionalProperties": {
+        "type": "object",
+        "required": ["name"],
+        "properties": {
+          "name": { "type": "string" },
+          "description": { "type": ["string", "null"] }
+        }
+      }
+    },
+    "template": { "type": ["string", "null"] },
+    "campaign": { "type": ["string", "null"] },
+    "project": { "type": ["string", "null"] },
+    "public_key": { "type": ["string", "null"] },
+    "delete_key_after_init": { "type": "boolean" },
+    "referrer_id": { "type": ["string", "null"] }
+  }
+}"#;
+const MAX_ACCOUNT_ID_LEN: usize = 64;
+const MAX_PREPAID_GAS: Gas = 300_000_000_000_000;
+/// Gas `create_token` itself needs on top of the gas attached to the deployment promises.
+const CREATE_TOKEN_GAS: Gas = 30_000_000_000_000;
+/// Upper bound on the borsh size of the token records in a page. Records carry their icons, so
+/// `MAX_PAGE_SIZE` records with uploaded icons wouldn't fit into a view call. A page that
+/// reaches this size ends early, but always holds at least one record.
+pub const MAX_PAGE_BYTES: u64 = 512 * 1024;
+const DEFAULT_PAGE_SIZE: u64 = 50;
+const MAX_TOP_CREATORS: usize = 20;
+const MAX_ICON_LENGTH: usize = 256 * 1024;
+/// Bytes of an icon blob returned by a single `get_icon` call.
+const ICON_CHUNK_SIZE: usize = 32 * 1024;
+const MAX_QUEUE_SIZE: u64 = 100;
+const MAX_FEE_DISCOUNTS: u64 = 10;
+/// Balance `withdraw_excess_balance` always leaves on top of the tracked liabilities.
+const EXCESS_BALANCE_BUFFER: Balance = 1_000_000_000_000_000_000_000_000;
+const MERKLE_TREE_DEPTH: usize = 32;
+const MAX_RECENT_EVENTS: u64 = 100;
+/// Gas for each `ft_metadata` call of `ping_tokens` and for its callback.
+const PING_GAS: Gas = 5_000_000_000_000;
+const PING_CALLBACK_GAS: Gas = 5_000_000_000_000;
+/// Gas for the `update_reference` call of a token.
+const UPDATE_REFERENCE_GAS: Gas = 10_000_000_000_000;
+const MAX_PING_PAGE_SIZE: u64 = 10;
+const PROBE_CALLBACK_GAS: Gas = 5_000_000_000_000;
+const MAX_PREPROVISION_BATCH: u64 = 5;
+const ORACLE_GAS: Gas = 10_000_000_000_000;
+const ORACLE_CALLBACK_GAS: Gas = 5_000_000_000_000;
+/// Gas for initializing a new project namespace and for its `create_token_account` call, on top
+/// of the init gas forwarded to the token. The call includes the namespace's callbacks, which
+/// return the deposit of a failed creation to the factory.
+const NAMESPACE_INIT_GAS: Gas = 5_000_000_000_000;
+const NAMESPACE_CALL_GAS: Gas = 25_000_000_000_000;
+/// Gas for minting the launch certificate, taken out of the gas of the finishing callback.
+const NFT_MINT_GAS: Gas = 5_000_000_000_000;
+const FT_TRANSFER_GAS: Gas = 10_000_000_000_000;
+/// Gas for the `storage_deposit` registering the owner of templates that need it.
+const OWNER_REGISTRATION_GAS: Gas = 10_000_000_000_000;
+const SOCIAL_GET_GAS: Gas = 10_000_000_000_000;
+/// Characters of a near.social profile name kept in the registry.
+const MAX_SOCIAL_NAME_LENGTH: usize = 64;
+const MAX_TOKEN_MANAGERS: usize = 10;
+const MAX_SIMILARITY_REFERENCES: u64 = 100;
+/// Characters of a normalized similarity reference, which bounds the cost of comparing it.
+const MAX_SIMILARITY_REFERENCE_LENGTH: usize = 64;
+const ONE_YOCTO: Balance = 1;
+
+#[ext_contract(ext_self)]
+trait ExtSelf {
+    fn on_create_token(&mut self, token_id: TokenId) -> bool;
+    fn on_account_created(&mut self, token_id: TokenId) -> PromiseOrValue<bool>;
+    fn on_namespace_created(&mut self, token_id: TokenId, project: String) -> PromiseOrValue<bool>;
+    fn on_token_deployed(&mut self, token_id: TokenId) -> bool;
+    fn on_token_pinged(&mut self, token_id: TokenId) -> bool;
+    fn on_account_probed(&mut self) -> bool;
+    fn on_account_preprovisioned(&mut self, token_id: TokenId) -> bool;
+    fn on_usd_price_fetched(&mut self, asset_id: String) -> bool;
+    fn on_reference_refreshed(&mut self, token_id: TokenId) -> bool;
+    fn on_token_account_probed(&mut self, token_id: TokenId) -> PromiseOrValue<bool>;
+    fn on_social_profile_fetched(&mut self, account_id: AccountId, social_db_id: AccountId)
+        -> bool;
+    fn on_rebind_probed(&mut self, token_id: TokenId, account_id: AccountId) -> bool;
+}
+
+#[ext_contract(ext_ft)]
+trait ExtFungibleToken {
+    fn ft_metadata(&self) -> FungibleTokenMetadata;
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+    fn update_reference(&mut self, reference: Option<String>, reference_hash: Option<Base64VecU8>);
+}
+
+#[ext_contract(ext_nft)]
+trait ExtNonFungibleToken {
+    fn nft_mint(
+        &mut self,
+        token_id: String,
+        receiver_id: AccountId,
+        token_metadata: CertificateMetadata,
+    );
+}
+
+#[ext_contract(ext_oracle)]
+trait ExtPriceOracle {
+    fn get_price_data(&self, asset_ids: Option<Vec<String>>) -> PriceData;
+}
+
+#[ext_contract(ext_social)]
+trait ExtSocialDb {
+    fn get(&self, keys: Vec<String>) -> serde_json::Value;
+}
+
+fn is_promise_success() -> bool {
+    assert_eq!(
+        env::promise_results_count(),
+        1,
+        "Contract expected a result on the callback"
+    );
+    matches!(env::promise_result(0), PromiseResult::Successful(_))
+}
+
+/// Whether the promise succeeded without resolving to `false`, which the namespace's
+/// `create_token_account` resolves to once it returned the deposit of a failed creation.
+/// Namespaces deployed before resolve to the empty result of the token's `new` instead.
+fn is_promise_success_and_not_false() -> bool {
+    assert_eq!(
+        env::promise_results_count(),
+        1,
+        "Contract expected a result on the callback"
+    );
+    match env::promise_result(0) {
+        PromiseResult::Successful(value) => value != b"false",
+        _ => false,
+    }
+}
+
+fn page_range(from_index: u64, limit: Option<u64>, len: u64) -> std::ops::Range<u64> {
+    let limit = std::cmp::min(limit.unwrap_or(DEFAULT_PAGE_SIZE), MAX_PAGE_SIZE);
+    from_index..std::cmp::min(from_index.saturating_add(limit), len)
+}
+
+/// Returns the bytes a storage balance entry of the longest account ID takes in the map.
+fn measure_storage_balance_bytes(storage_deposits: &mut UnorderedMap<AccountId, Balance>) -> u64 {
+    let initial_storage_usage = env::storage_usage();
+    let tmp_account_id = "a".repeat(64);
+    storage_deposits.insert(&tmp_account_id, &0);
+    let storage_balance_bytes = env::storage_usage() - initial_storage_usage;
+    storage_deposits.remove(&tmp_account_id);
+    storage_balance_bytes
+}
 
 pub fn is_valid_token_id(token_id: &TokenId) -> bool {
     for c in token_id.as_bytes() {
@@ -27,131 +293,704 @@ pub fn is_valid_token_id(token_id: &TokenId) -> bool {
     true
 }
 
+/// Progress of rewriting the token records after an upgrade, see `migrate_chunk`.
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Migration {
+    /// Index of the next token record to rewrite.
+    pub next_index: U64,
+    pub total: U64,
+}
+
+/// State of the first version of the factory, which the upgrade migrates from.
+#[derive(BorshDeserialize, BorshSerialize)]
+struct BaselineTokenFactory {
+    tokens: UnorderedMap<TokenId, BaselineTokenArgs>,
+    storage_deposits: LookupMap<AccountId, Balance>,
+    storage_balance_cost: Balance,
+}
+
+/// Token args of the first version, which were its whole token records.
+#[derive(BorshDeserialize, BorshSerialize)]
+struct BaselineTokenArgs {
+    owner_id: ValidAccountId,
+    total_supply: U128,
+    metadata: FungibleTokenMetadata,
+}
+
 #[derive(BorshSerialize, BorshStorageKey)]
+#[allow(dead_code)]
 enum StorageKey {
     Tokens,
+    // The storage balances of the baseline state, only read through the migrated state. The
+    // variant stays so the prefixes of the later keys don't shift.
     StorageDeposits,
+    Roles,
+    Proposals,
+    ReferralPayouts,
+    BannedAccounts,
+    CreatorStats,
+    TopCreators,
+    Deployments,
+    RegistryTree,
+    RegistryLeaves,
+    Quotes,
+    TokensBySymbol,
+    ReservedSymbols,
+    RecentEvents,
+    IconUploads,
+    Projects,
+    DeniedDomains,
+    TrustedIntegrators,
+    TokenCode,
+    Templates,
+    TemplateCode,
+    CreationQueue,
+    TokenNames,
+    BlockedNames,
+    TokensByTag,
+    PrelaunchPools,
+    TokensByOwner,
+    ArchivedTokens,
+    PreprovisionedAccounts,
+    SymbolSkeletons,
+    TemplateStats,
+    AdminLog,
+    SymbolCommits,
+    RecentDeposits,
+    HolderMilestones,
+    Campaigns,
+    TokensByCampaign,
+    PaymentTokens,
+    PaymentTokenBalances,
+    ReferenceRefreshes,
+    FeeDiscounts,
+    FeeDiscountUses,
+    StorageBalances,
+    SocialProfiles,
+    IconBlobs,
+    RetiredSymbols,
+    TokenManagers,
+    SimilarityReferences,
+    SimilarityFlags,
+    CreationReceipts,
+    TagTokens { tag_hash: Vec<u8> },
+    OwnerTokens { account_hash: Vec<u8> },
+    CampaignTokens { name_hash: Vec<u8> },
+    SymbolTokens { symbol_hash: Vec<u8> },
+    PrelaunchContributions,
+    IconChunks,
 }
 
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct TokenFactory {
-    pub tokens: UnorderedMap<TokenId, TokenArgs>,
-    pub storage_deposits: LookupMap<AccountId, Balance>,
-    pub storage_balance_cost: Balance,
-}
-
-#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
-#[serde(crate = "near_sdk::serde")]
-pub struct TokenArgs {
-    owner_id: ValidAccountId,
-    total_supply: U128,
-    metadata: FungibleTokenMetadata,
+    pub tokens: UnorderedMap<TokenId, TokenRecord>,
+    pub storage_deposits: UnorderedMap<AccountId, Balance>,
+    /// Bytes a storage balance entry takes. The cost is derived from it with the current
+    /// storage price, so registrations stay priced right if the price changes.
+    pub storage_balance_bytes: u64,
+    pub owner_id: AccountId,
+    pub config: Config,
+    pub roles: UnorderedMap<AccountId, Vec<Role>>,
+    pub proposals: UnorderedMap<TokenId, Proposal>,
+    pub fees_collected: Balance,
+    pub fees_withdrawn: Balance,
+    pub referral_payouts: LookupMap<AccountId, Balance>,
+    pub total_referral_payouts: Balance,
+    pub banned_accounts: UnorderedSet<AccountId>,
+    pub creator_stats: LookupMap<AccountId, CreatorStats>,
+    /// Creators with the most deployed tokens, sorted in descending order.
+    pub top_creators: LazyOption<Vec<(AccountId, u64)>>,
+    pub limits: Limits,
+    /// Deployments that are in flight or failed halfway through the split deployment.
+    pub deployments: UnorderedMap<TokenId, Deployment>,
+    pub registry_tree: LazyOption<MerkleTree>,
+    /// Leaf hashes of the registry tree in insertion order, for building membership proofs.
+    pub registry_leaves: Vector<Vec<u8>>,
+    /// The latest quote of every account, honored by `create_token` until it expires.
+    pub quotes: LookupMap<AccountId, Quote>,
+    pub quote_nonce: u64,
+    /// Number of ids handed out in the counter mode.
+    pub token_counter: u64,
+    /// Tokens by lowercase symbol, each symbol with a set under its own prefix. Tokens of
+    /// different projects can share a symbol.
+    pub tokens_by_symbol: LookupMap<String, UnorderedSet<TokenId>>,
+    /// Number of registered tokens per confusable skeleton of their symbol.
+    pub symbol_skeletons: LookupMap<String, u64>,
+    pub reserved_symbols: UnorderedSet<String>,
+    /// Ring buffer with the last `MAX_RECENT_EVENTS` events.
+    pub recent_events: Vector<RecordedEvent>,
+    pub event_count: u64,
+    pub creation_stats_count: u64,
+    pub creation_stats_total: CreationStats,
+    pub icon_uploads: LookupMap<(AccountId, String), IconUpload>,
+    pub projects: UnorderedMap<String, Project>,
+    pub denied_domains: UnorderedSet<String>,
+    pub trusted_integrators: UnorderedSet<AccountId>,
+    /// Code deployed to new tokens, replacing the built-in fungible token once set.
+    pub token_code: LazyOption<Vec<u8>>,
+    pub token_code_info: TokenCodeInfo,
+    pub templates: UnorderedMap<String, Template>,
+    pub template_code: LookupMap<String, Vec<u8>>,
+    pub creation_queue: UnorderedMap<TokenId, QueuedCreation>,
+    pub queue_nonce: u64,
+    /// Number of registered tokens per normalized name.
+    pub token_names: LookupMap<String, u64>,
+    pub blocked_names: UnorderedSet<String>,
+    /// Tokens by tag, each tag with a set under its own prefix.
+    pub tokens_by_tag: LookupMap<String, UnorderedSet<TokenId>>,
+    /// Tokens by the `owner_id` of their args, which differs from the creator for tokens
+    /// launched on behalf of another account. Each owner has a set under its own prefix.
+    pub tokens_by_owner: LookupMap<AccountId, UnorderedSet<TokenId>>,
+    /// Compact entries of the tokens moved out of the registry by `archive_tokens`.
+    pub archived_tokens: LookupMap<TokenId, ArchivedToken>,
+    pub archived_token_count: u64,
+    /// Deployments that finished and failed, counted by the callbacks. Unlike the registry
+    /// length, they don't go down when records are removed.
+    pub total_created: u64,
+    pub total_failed: u64,
+    /// Token accounts created ahead of time with the default code but no state, by whether
+    /// their code is deployed yet.
+    pub preprovisioned_accounts: UnorderedMap<TokenId, bool>,
+    pub preprovision_nonce: u64,
+    /// Sum of all storage balances. After the upgrade from the first version it also covers the
+    /// balances left in `legacy_storage_deposits`, estimated at the migration, see `migrate`.
+    pub total_storage_balances: Balance,
+    /// Sum of the escrows of proposals and deployments that haven't resolved yet.
+    pub pending_escrow: Balance,
+    pub prelaunch_pools: LookupMap<TokenId, PrelaunchPool>,
+    /// Sum of the contributions held by the prelaunch pools.
+    pub prelaunch_funds: Balance,
+    pub usd_price: Option<CachedUsdPrice>,
+    /// Deployed tokens per template, with the default code counted as `DEFAULT_TEMPLATE`.
+    pub template_stats: UnorderedMap<String, TemplateStats>,
+    /// Append-only log of the privileged calls.
+    pub admin_log: Vector<AdminLogEntry>,
+    /// Symbol commitments by their hash.
+    pub symbol_commits: LookupMap<Vec<u8>, SymbolCommit>,
+    /// Storage deposits within the grace period that nothing was charged from yet.
+    pub recent_deposits: LookupMap<AccountId, RecentDeposit>,
+    /// Highest holder milestone reported by each token.
+    pub holder_milestones: LookupMap<TokenId, HolderMilestone>,
+    pub campaigns: UnorderedMap<String, Campaign>,
+    /// Tokens by campaign, each campaign with a set under its own prefix.
+    pub tokens_by_campaign: LookupMap<String, UnorderedSet<TokenId>>,
+    /// Fungible tokens accepted by `ft_on_transfer` in place of NEAR, with their rates.
+    pub payment_tokens: UnorderedMap<AccountId, PaymentTokenRate>,
+    /// Amounts of the payment tokens received and not withdrawn by the owner yet.
+    pub payment_token_balances: LookupMap<AccountId, Balance>,
+    /// Metadata references scheduled by the creators, applied once their timelock passed.
+    pub reference_refreshes: LookupMap<TokenId, ReferenceRefresh>,
+    /// Promotional discounts on the creation fee set by the owner.
+    pub fee_discounts: UnorderedMap<String, FeeDiscount>,
+    /// Discounted creations of every account, for the promotions capped per account.
+    pub fee_discount_uses: LookupMap<(String, AccountId), u64>,
+    /// Storage balances of the map used before `storage_deposits` could be enumerated. They are
+    /// read from here until the balance changes or the owner moves it over with
+    /// `migrate_storage_deposits`.
+    pub legacy_storage_deposits: Option<LookupMap<AccountId, Balance>>,
+    /// near.social profiles linked by the creators, read from the configured SocialDB.
+    pub social_profiles: LookupMap<AccountId, SocialProfile>,
+    /// Number of chunks of the icons of the tokens created with `icon_blob`.
+    pub icon_blobs: LookupMap<TokenId, u64>,
+    /// Symbols that can never be used again, in lowercase.
+    pub retired_symbols: UnorderedSet<String>,
+    /// Last attestation of the factory account's code and access keys.
+    pub trust_attestation: Option<TrustAttestation>,
+    /// Set while token records still have to be rewritten by `migrate_chunk`.
+    pub migration: Option<Migration>,
+    /// Accounts the creator of a token authorized to act as the creator for it.
+    pub token_managers: LookupMap<TokenId, Vec<AccountId>>,
+    /// Normalized names of popular tokens new symbols and names are compared with.
+    pub similarity_references: UnorderedSet<String>,
+    /// Reference each token flagged by the `Flag` similarity check is similar to.
+    pub similarity_flags: LookupMap<TokenId, SimilarityFlag>,
+    /// NEAR amounts of every successful creation, kept after the token is archived.
+    pub creation_receipts: LookupMap<TokenId, CreationReceipt>,
+    /// Contribution of each supporter to the prelaunch pool of a token.
+    pub prelaunch_contributions: LookupMap<(TokenId, AccountId), Balance>,
+    /// `ICON_CHUNK_SIZE` chunks of the icon blobs by their token and index.
+    pub icon_chunks: LookupMap<(TokenId, u64), String>,
 }
 
 #[near_bindgen]
 impl TokenFactory {
     #[init]
-    pub fn new() -> Self {
-        let mut storage_deposits = LookupMap::new(StorageKey::StorageDeposits);
-
-        let initial_storage_usage = env::storage_usage();
-        let tmp_account_id = "a".repeat(64);
-        storage_deposits.insert(&tmp_account_id, &0);
-        let storage_balance_cost =
-            Balance::from(env::storage_usage() - initial_storage_usage) * STORAGE_PRICE_PER_BYTE;
-        storage_deposits.remove(&tmp_account_id);
+    pub fn new(owner_id: ValidAccountId) -> Self {
+        // Also checked by the build script, against its configurable threshold.
+        assert!(
+            FT_WASM_CODE.len() <= MAX_TOKEN_CODE_SIZE,
+            "Embedded token code has {} bytes, more than the {} bytes allowed",
+            FT_WASM_CODE.len(),
+            MAX_TOKEN_CODE_SIZE
+        );
+        let mut storage_deposits = UnorderedMap::new(StorageKey::StorageBalances);
+        let storage_balance_bytes = measure_storage_balance_bytes(&mut storage_deposits);
 
         Self {
             tokens: UnorderedMap::new(StorageKey::Tokens),
             storage_deposits,
-            storage_balance_cost,
+            storage_balance_bytes,
+            owner_id: owner_id.into(),
+            config: Config::default(),
+            roles: UnorderedMap::new(StorageKey::Roles),
+            proposals: UnorderedMap::new(StorageKey::Proposals),
+            fees_collected: 0,
+            fees_withdrawn: 0,
+            referral_payouts: LookupMap::new(StorageKey::ReferralPayouts),
+            total_referral_payouts: 0,
+            banned_accounts: UnorderedSet::new(StorageKey::BannedAccounts),
+            creator_stats: LookupMap::new(StorageKey::CreatorStats),
+            top_creators: LazyOption::new(StorageKey::TopCreators, Some(&vec![])),
+            limits: Limits::new(&env::current_account_id()),
+            deployments: UnorderedMap::new(StorageKey::Deployments),
+            registry_tree: LazyOption::new(StorageKey::RegistryTree, Some(&MerkleTree::new())),
+            registry_leaves: Vector::new(StorageKey::RegistryLeaves),
+            quotes: LookupMap::new(StorageKey::Quotes),
+            quote_nonce: 0,
+            token_counter: 0,
+            tokens_by_symbol: LookupMap::new(StorageKey::TokensBySymbol),
+            symbol_skeletons: LookupMap::new(StorageKey::SymbolSkeletons),
+            reserved_symbols: UnorderedSet::new(StorageKey::ReservedSymbols),
+            recent_events: Vector::new(StorageKey::RecentEvents),
+            event_count: 0,
+            creation_stats_count: 0,
+            creation_stats_total: CreationStats::default(),
+            icon_uploads: LookupMap::new(StorageKey::IconUploads),
+            projects: UnorderedMap::new(StorageKey::Projects),
+            denied_domains: UnorderedSet::new(StorageKey::DeniedDomains),
+            trusted_integrators: UnorderedSet::new(StorageKey::TrustedIntegrators),
+            token_code: LazyOption::new(StorageKey::TokenCode, None),
+            token_code_info: TokenCodeInfo::new(FT_WASM_CODE),
+            templates: UnorderedMap::new(StorageKey::Templates),
+            template_code: LookupMap::new(StorageKey::TemplateCode),
+            creation_queue: UnorderedMap::new(StorageKey::CreationQueue),
+            queue_nonce: 0,
+            token_names: LookupMap::new(StorageKey::TokenNames),
+            blocked_names: UnorderedSet::new(StorageKey::BlockedNames),
+            tokens_by_tag: LookupMap::new(StorageKey::TokensByTag),
+            tokens_by_owner: LookupMap::new(StorageKey::TokensByOwner),
+            archived_tokens: LookupMap::new(StorageKey::ArchivedTokens),
+            archived_token_count: 0,
+            total_created: 0,
+            total_failed: 0,
+            preprovisioned_accounts: UnorderedMap::new(StorageKey::PreprovisionedAccounts),
+            preprovision_nonce: 0,
+            total_storage_balances: 0,
+            pending_escrow: 0,
+            prelaunch_pools: LookupMap::new(StorageKey::PrelaunchPools),
+            prelaunch_funds: 0,
+            usd_price: None,
+            template_stats: UnorderedMap::new(StorageKey::TemplateStats),
+            admin_log: Vector::new(StorageKey::AdminLog),
+            symbol_commits: LookupMap::new(StorageKey::SymbolCommits),
+            recent_deposits: LookupMap::new(StorageKey::RecentDeposits),
+            holder_milestones: LookupMap::new(StorageKey::HolderMilestones),
+            campaigns: UnorderedMap::new(StorageKey::Campaigns),
+            tokens_by_campaign: LookupMap::new(StorageKey::TokensByCampaign),
+            payment_tokens: UnorderedMap::new(StorageKey::PaymentTokens),
+            payment_token_balances: LookupMap::new(StorageKey::PaymentTokenBalances),
+            reference_refreshes: LookupMap::new(StorageKey::ReferenceRefreshes),
+            fee_discounts: UnorderedMap::new(StorageKey::FeeDiscounts),
+            fee_discount_uses: LookupMap::new(StorageKey::FeeDiscountUses),
+            legacy_storage_deposits: None,
+            social_profiles: LookupMap::new(StorageKey::SocialProfiles),
+            icon_blobs: LookupMap::new(StorageKey::IconBlobs),
+            retired_symbols: UnorderedSet::new(StorageKey::RetiredSymbols),
+            trust_attestation: None,
+            migration: None,
+            token_managers: LookupMap::new(StorageKey::TokenManagers),
+            similarity_references: UnorderedSet::new(StorageKey::SimilarityReferences),
+            similarity_flags: LookupMap::new(StorageKey::SimilarityFlags),
+            creation_receipts: LookupMap::new(StorageKey::CreationReceipts),
+            prelaunch_contributions: LookupMap::new(StorageKey::PrelaunchContributions),
+            icon_chunks: LookupMap::new(StorageKey::IconChunks),
         }
     }
 
-    fn get_min_attached_balance(&self, args: &TokenArgs) -> u128 {
-        ((FT_WASM_CODE.len() + EXTRA_BYTES + args.try_to_vec().unwrap().len() * 2) as Balance
-            * STORAGE_PRICE_PER_BYTE)
-            .into()
+    /// Migrates the state of the first version, which only held the token args, the storage
+    /// balances in a LookupMap that can't be enumerated, and the registration cost. As it had
+    /// no owner, `owner_id` becomes the owner. The other state starts out as in `new`.
+    ///
+    /// The old balance map is kept as `legacy_storage_deposits`, as its accounts can't be listed
+    /// on chain. Balances are read from it until they change or the owner moves them over with
+    /// `migrate_storage_deposits`. Their sum can't be computed either, so everything the factory
+    /// holds above its storage staking is counted in `total_storage_balances`. The first version
+    /// passed the rest of every creation deposit on to the token, so that is an upper bound, and
+    /// the owner can't withdraw unmigrated balances as excess.
+    ///
+    /// The token records are rewritten afterwards by `migrate_chunk`. Until all of them are
+    /// migrated, the creations and the methods and views reading the token records are refused,
+    /// as the records not migrated yet can't be read.
+    #[init(ignore_state)]
+    pub fn migrate(owner_id: ValidAccountId) -> Self {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "Can only be called by the factory account"
+        );
+        let old_state: BaselineTokenFactory =
+            env::state_read().expect("Factory is not initialized");
+        let legacy_storage_balances = env::account_balance().saturating_sub(pricing::storage_cost(
+            env::storage_usage(),
+            STORAGE_PRICE_PER_BYTE,
+        ));
+        let mut factory = Self::new(owner_id);
+        // The records keep their prefix, and only their values are rewritten by `migrate_chunk`.
+        factory.tokens = UnorderedMap::try_from_slice(&old_state.tokens.try_to_vec().unwrap())
+            .expect("Unexpected state");
+        factory.legacy_storage_deposits = Some(old_state.storage_deposits);
+        factory.total_storage_balances = legacy_storage_balances;
+        factory.migration = Some(Migration {
+            next_index: 0.into(),
+            total: factory.tokens.len().into(),
+        });
+        factory
     }
 
-    pub fn get_required_deposit(&self, args: TokenArgs, account_id: ValidAccountId) -> U128 {
-        let args_deposit = self.get_min_attached_balance(&args);
-        if let Some(previous_balance) = self.storage_deposits.get(account_id.as_ref()) {
-            args_deposit.saturating_sub(previous_balance).into()
+    /// Rewrites up to `limit` token records of the first version, starting at `from_index`,
+    /// which has to be where the last chunk stopped, and adds them to the symbol, name and owner
+    /// indexes. Migrating all records in one call could exceed the gas limit for large
+    /// registries. The first version had no creators, so the owner of a token is recorded as its
+    /// creator. The new fields of the records and the index entries take a few bytes, which the
+    /// factory pays for. Returns the progress, or `None` once all records are migrated and the
+    /// creations are unblocked.
+    pub fn migrate_chunk(&mut self, from_index: u64, limit: u64) -> Option<Migration> {
+        self.assert_owner();
+        let mut migration = self.migration.clone().expect("No migration in progress");
+        assert_eq!(
+            from_index, migration.next_index.0,
+            "Migration continues at {}",
+            migration.next_index.0
+        );
+        let end = std::cmp::min(
+            from_index + std::cmp::min(limit, MAX_PAGE_SIZE),
+            migration.total.0,
+        );
+        let values_prefix = [StorageKey::Tokens.try_to_vec().unwrap(), b"v".to_vec()].concat();
+        for index in from_index..end {
+            let token_id = self
+                .tokens
+                .keys_as_vector()
+                .get(index)
+                .expect("Token record not found");
+            let key = [values_prefix.clone(), index.to_le_bytes().to_vec()].concat();
+            let value = env::storage_read(&key).expect("Token record not found");
+            let args = BaselineTokenArgs::try_from_slice(&value).expect("Unexpected token record");
+            let args = TokenArgs::new(args.owner_id, args.total_supply, args.metadata);
+            self.internal_add_to_symbol_index(&args.metadata.symbol, &token_id);
+            self.internal_add_to_name_index(&args.metadata.name);
+            self.internal_add_to_owner_index(args.owner_id.as_ref(), &token_id);
+            let record = TokenRecord {
+                creator_id: args.owner_id.clone().into(),
+                args,
+                frozen: false,
+                creation_stats: None,
+                last_seen_ok: None,
+                unresponsive: false,
+                full_access_key: None,
+                detached_account_id: None,
+                fee_on_transfer: false,
+                created_at: None,
+            };
+            env::storage_write(&key, &record.try_to_vec().unwrap());
+        }
+        migration.next_index = end.into();
+        if end == migration.total.0 {
+            env::log(format!("Migrated {} token records", end).as_bytes());
+            self.migration = None;
         } else {
-            (self.storage_balance_cost + args_deposit).into()
+            self.migration = Some(migration);
         }
+        self.migration.clone()
     }
 
-    #[payable]
-    pub fn storage_deposit(&mut self) {
-        let account_id = env::predecessor_account_id();
-        let deposit = env::attached_deposit();
-        if let Some(previous_balance) = self.storage_deposits.get(&account_id) {
-            self.storage_deposits
-                .insert(&account_id, &(previous_balance + deposit));
-        } else {
-            assert!(deposit >= self.storage_balance_cost, "Deposit is too low");
-            self.storage_deposits
-                .insert(&account_id, &(deposit - self.storage_balance_cost));
+    /// Moves the balances of the given accounts from the legacy storage balance map, skipping
+    /// accounts without one. The accounts are listed off chain, e.g. from the `storage_deposit`
+    /// calls seen by an indexer. Returns the number of balances moved.
+    pub fn migrate_storage_deposits(&mut self, account_ids: Vec<ValidAccountId>) -> u64 {
+        self.assert_owner();
+        assert!(
+            account_ids.len() as u64 <= MAX_PAGE_SIZE,
+            "Can't migrate more than {} accounts at once",
+            MAX_PAGE_SIZE
+        );
+        let legacy_storage_deposits = self
+            .legacy_storage_deposits
+            .as_ref()
+            .expect("No storage balances to migrate");
+        let balances: Vec<(AccountId, Balance)> = account_ids
+            .into_iter()
+            .filter_map(|account_id| {
+                legacy_storage_deposits
+                    .get(account_id.as_ref())
+                    .map(|balance| (account_id.into(), balance))
+            })
+            .collect();
+        for (account_id, balance) in &balances {
+            self.internal_set_storage_balance(account_id, *balance, "migration");
         }
+        balances.len() as u64
     }
 
-    pub fn get_number_of_tokens(&self) -> u64 {
-        self.tokens.len()
+    fn assert_owner(&self) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "Can only be called by the owner"
+        );
     }
 
-    pub fn get_tokens(&self, from_index: u64, limit: u64) -> Vec<TokenArgs> {
-        let tokens = self.tokens.values_as_vector();
-        (from_index..std::cmp::min(from_index + limit, tokens.len()))
-            .filter_map(|index| tokens.get(index))
-            .collect()
+    fn assert_role(&self, role: Role) {
+        let account_id = env::predecessor_account_id();
+        assert!(
+            account_id == self.owner_id || self.internal_has_role(&account_id, &role),
+            "Missing required role"
+        );
     }
 
-    pub fn get_token(&self, token_id: TokenId) -> Option<TokenArgs> {
-        self.tokens.get(&token_id)
+    fn assert_not_migrating(&self) {
+        assert!(
+            self.migration.is_none(),
+            "Factory is migrating, try again once migrate_chunk completed"
+        );
     }
 
-    #[payable]
-    pub fn create_token(&mut self, args: TokenArgs) -> Promise {
-        if env::attached_deposit() > 0 {
-            self.storage_deposit();
-        }
-        args.metadata.assert_valid();
-        let token_id = args.metadata.symbol.to_ascii_lowercase();
-        assert!(is_valid_token_id(&token_id), "Invalid Symbol");
-        let token_account_id = format!("{}.{}", token_id, env::current_account_id());
+    fn assert_not_banned(&self, account_id: &AccountId) {
         assert!(
-            env::is_valid_account_id(token_account_id.as_bytes()),
-            "Token Account ID is invalid"
+            !self.banned_accounts.contains(account_id),
+            "Account is banned from using the factory"
         );
+    }
 
-        let account_id = env::predecessor_account_id();
+    fn internal_has_role(&self, account_id: &AccountId, role: &Role) -> bool {
+        self.roles
+            .get(account_id)
+            .map_or(false, |roles| roles.contains(role))
+    }
+}
 
-        let required_balance = self.get_min_attached_balance(&args);
-        let user_balance = self.storage_deposits.get(&account_id).unwrap_or(0);
-        assert!(
-            user_balance >= required_balance,
-            "Not enough required balance"
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::test_utils::accounts;
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    /// Writes the state of the first version, with a token for each of the symbols and a
+    /// storage balance.
+    fn write_baseline_state(symbols: &[&str]) {
+        let mut tokens: UnorderedMap<TokenId, BaselineTokenArgs> =
+            UnorderedMap::new(StorageKey::Tokens);
+        for symbol in symbols {
+            tokens.insert(
+                &symbol.to_ascii_lowercase(),
+                &BaselineTokenArgs {
+                    owner_id: accounts(1),
+                    total_supply: U128(1_000_000),
+                    metadata: token_metadata(symbol),
+                },
+            );
+        }
+        let mut storage_deposits: LookupMap<AccountId, Balance> =
+            LookupMap::new(StorageKey::StorageDeposits);
+        storage_deposits.insert(accounts(2).as_ref(), &500);
+        env::state_write(&BaselineTokenFactory {
+            tokens,
+            storage_deposits,
+            storage_balance_cost: 1_000,
+        });
+    }
+
+    #[test]
+    fn test_embedded_code_fits_the_size_limits() {
+        let max_size: usize = env!("MAX_TOKEN_WASM_SIZE").parse().unwrap();
+        assert!(FT_WASM_CODE.len() <= max_size);
+        assert!(FT_WASM_CODE.len() <= MAX_TOKEN_CODE_SIZE);
+        assert!(FT_WASM_CODE.starts_with(WASM_MAGIC));
+        assert!(NAMESPACE_WASM_CODE.starts_with(WASM_MAGIC));
+        let factory = setup_factory();
+        assert_eq!(factory.token_code_info.code_size, FT_WASM_CODE.len() as u64);
+    }
+
+    #[test]
+    fn test_abi_describes_the_methods() {
+        let json = include_str!(concat!(env!("OUT_DIR"), "/token_factory_abi.json"));
+        // The frame holds the JSON in raw blocks after its 13 byte header.
+        let mut frame_content = vec![];
+        let mut offset = 13;
+        loop {
+            let header = u32::from_le_bytes([
+                CONTRACT_ABI[offset],
+                CONTRACT_ABI[offset + 1],
+                CONTRACT_ABI[offset + 2],
+                0,
+            ]);
+            let size = (header >> 3) as usize;
+            frame_content.extend_from_slice(&CONTRACT_ABI[offset + 3..offset + 3 + size]);
+            offset += 3 + size;
+            if header & 1 == 1 {
+                break;
+            }
+        }
+        assert_eq!(frame_content, json.as_bytes());
+
+        let abi: serde_json::Value = serde_json::from_str(json).unwrap();
+        let functions = abi["body"]["functions"].as_array().unwrap();
+        let function = |name: &str| {
+            functions
+                .iter()
+                .find(|function| function["name"] == name)
+                .unwrap_or_else(|| panic!("ABI has no {}", name))
+        };
+        assert_eq!(function("new")["modifiers"], serde_json::json!(["init"]));
+        let create_token = function("create_token");
+        assert_eq!(create_token["kind"], "call");
+        assert_eq!(create_token["modifiers"], serde_json::json!(["payable"]));
+        assert_eq!(
+            create_token["params"]["args"][0]["type_schema"]["$ref"],
+            "#/definitions/TokenArgs"
+        );
+        assert_eq!(function("get_tokens")["kind"], "view");
+        assert_eq!(
+            function("on_create_token")["modifiers"],
+            serde_json::json!(["private"])
+        );
+        assert_eq!(function("ft_on_transfer")["kind"], "call");
+        assert_eq!(
+            functions
+                .iter()
+                .any(|function| function["name"] == "dev_seed_tokens"),
+            cfg!(feature = "dev")
+        );
+        let definitions = &abi["body"]["root_schema"]["definitions"];
+        assert_eq!(
+            definitions["TokenArgs"]["required"],
+            serde_json::json!(["owner_id", "total_supply", "metadata"])
         );
-        self.storage_deposits
-            .insert(&account_id, &(user_balance - required_balance));
+    }
 
-        let initial_storage_usage = env::storage_usage();
+    #[test]
+    fn test_migrate_baseline_state() {
+        env::take_blockchain_interface();
+        testing_env!(context(accounts(0)).build());
+        write_baseline_state(&["ABC", "XYZ"]);
+        let mut factory = TokenFactory::migrate(accounts(3));
+        assert_eq!(factory.owner_id, accounts(3).as_ref().clone());
+        assert_eq!(factory.migration.as_ref().unwrap().total.0, 2);
+        let total_storage_balances = factory.total_storage_balances;
+        assert!(total_storage_balances >= 500);
 
-        assert!(
-            self.tokens.insert(&token_id, &args).is_none(),
-            "Token ID is already taken"
+        testing_env!(context(accounts(3)).build());
+        assert_eq!(factory.migrate_chunk(0, 1).unwrap().next_index.0, 1);
+        assert!(factory.migrate_chunk(1, 10).is_none());
+        let token = factory.tokens.get(&"xyz".to_string()).unwrap();
+        assert_eq!(token.args.metadata.symbol, "XYZ");
+        assert_eq!(token.args.total_supply.0, 1_000_000);
+        assert_eq!(token.creator_id, accounts(1).as_ref().clone());
+        assert_eq!(factory.get_tokens_by_symbol("abc".to_string()), vec!["abc"]);
+        assert_eq!(factory.get_tokens_by_owner(accounts(1), 0, None).len(), 2);
+
+        // The balance is read from the legacy map until it is moved over.
+        let balance = factory.storage_balance_of(accounts(2)).unwrap();
+        assert_eq!(balance.available.0, 500);
+        assert_eq!(
+            factory.migrate_storage_deposits(vec![accounts(2), accounts(4)]),
+            1
+        );
+        assert_eq!(
+            factory.storage_deposits.get(accounts(2).as_ref()),
+            Some(500)
+        );
+        assert!(factory
+            .legacy_storage_deposits
+            .as_ref()
+            .unwrap()
+            .get(accounts(2).as_ref())
+            .is_none());
+        // The moved balance was counted in the total since the migration.
+        assert_eq!(factory.total_storage_balances, total_storage_balances);
+        assert_eq!(factory.migrate_storage_deposits(vec![accounts(2)]), 0);
+    }
+
+    #[test]
+    fn test_legacy_balance_moves_with_its_first_change() {
+        env::take_blockchain_interface();
+        testing_env!(context(accounts(0)).build());
+        write_baseline_state(&["ABC", "XYZ"]);
+        let mut factory = TokenFactory::migrate(accounts(3));
+        let total_storage_balances = factory.total_storage_balances;
+        factory.internal_set_storage_balance(accounts(2).as_ref(), 200, "creation");
+        assert_eq!(
+            factory.get_storage_accounts(0, None),
+            vec![(accounts(2).as_ref().clone(), U128(200))]
         );
+        assert_eq!(factory.total_storage_balances, total_storage_balances - 300);
+        testing_env!(context(accounts(3)).build());
+        assert_eq!(factory.migrate_storage_deposits(vec![accounts(2)]), 0);
+    }
+
+    /// Migrates the first version with the given tokens, up to the chunks.
+    fn migrate_baseline_state(symbols: &[&str]) -> TokenFactory {
+        env::take_blockchain_interface();
+        testing_env!(context(accounts(0)).build());
+        write_baseline_state(symbols);
+        let factory = TokenFactory::migrate(accounts(3));
+        testing_env!(context(accounts(3)).build());
+        factory
+    }
+
+    #[test]
+    fn test_migrate_in_several_chunks() {
+        let symbols = ["ABC", "DEF", "GHI", "JKL", "MNO"];
+        let mut factory = migrate_baseline_state(&symbols);
+        assert_eq!(factory.migrate_chunk(0, 2).unwrap().next_index.0, 2);
+        assert_eq!(factory.migrate_chunk(2, 2).unwrap().next_index.0, 4);
+        assert!(factory.get_migration().is_some());
+        assert!(factory.migrate_chunk(4, 2).is_none());
+        assert!(factory.get_migration().is_none());
+        for symbol in &symbols {
+            let token_id = symbol.to_ascii_lowercase();
+            let token = factory.get_token(token_id.clone()).unwrap();
+            assert!(serde_json::to_string(&token).unwrap().contains(symbol));
+            assert_eq!(
+                factory.get_tokens_by_symbol(token_id.clone()),
+                vec![token_id]
+            );
+        }
+        assert_eq!(factory.get_tokens(0, None, None).len(), symbols.len());
+    }
+
+    #[test]
+    #[should_panic(expected = "Migration continues at 2")]
+    fn test_migrate_chunk_skipping_records() {
+        let mut factory = migrate_baseline_state(&["ABC", "DEF", "GHI"]);
+        factory.migrate_chunk(0, 2);
+        factory.migrate_chunk(1, 2);
+    }
 
-        let storage_balance_used =
-            Balance::from(env::storage_usage() - initial_storage_usage) * STORAGE_PRICE_PER_BYTE;
+    #[test]
+    #[should_panic(expected = "Factory is migrating, try again once migrate_chunk completed")]
+    fn test_token_views_wait_for_the_migration() {
+        let mut factory = migrate_baseline_state(&["ABC", "XYZ"]);
+        // The migrated record can be read, but the views wait for all of them.
+        factory.migrate_chunk(0, 1);
+        factory.get_token("abc".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Factory is migrating, try again once migrate_chunk completed")]
+    fn test_freeze_token_waits_for_the_migration() {
+        let mut factory = migrate_baseline_state(&["ABC", "XYZ"]);
+        factory.freeze_token("abc".to_string(), "spam".to_string());
+    }
 
-        Promise::new(token_account_id)
-            .create_account()
-            .transfer(required_balance - storage_balance_used)
-            .deploy_contract(FT_WASM_CODE.to_vec())
-            .function_call(b"new".to_vec(), serde_json::to_vec(&args).unwrap(), 0, GAS)
+    #[test]
+    #[should_panic(expected = "Factory is migrating, try again once migrate_chunk completed")]
+    fn test_token_managers_wait_for_the_migration() {
+        let mut factory = migrate_baseline_state(&["ABC", "XYZ"]);
+        testing_env!(context(accounts(1)).attached_deposit(1).build());
+        factory.add_token_manager("abc".to_string(), accounts(2));
     }
 }
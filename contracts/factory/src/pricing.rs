@@ -0,0 +1,274 @@
+//! Deposit arithmetic of the factory. The functions only depend on their arguments, so the
+//! price of a byte is passed in rather than read from the environment.
+use factory_types::MAX_BPS;
+use near_sdk::Balance;
+
+/// Bytes of the token account on top of its code: the account record, the access keys and
+/// the initial state.
+pub const EXTRA_BYTES: u64 = 10000;
+/// Estimated bytes of the registry bookkeeping around a token record, on top of its args: the
+/// symbol, name and owner indexes, the registry tree and the recorded events.
+pub const REGISTRY_EXTRA_BYTES: u64 = 2000;
+/// Estimated bytes of adding a token to the index of one of its tags or of its campaign.
+pub const INDEX_ENTRY_BYTES: u64 = 400;
+/// Bytes the protocol charges for every storage record on top of its key and value.
+pub const RECORD_EXTRA_BYTES: u64 = 40;
+
+pub fn storage_cost(bytes: u64, byte_cost: Balance) -> Balance {
+    Balance::from(bytes) * byte_cost
+}
+
+/// Cost of the code and the account records of a new account.
+pub fn code_storage_cost(code_len: u64, byte_cost: Balance) -> Balance {
+    storage_cost(code_len + EXTRA_BYTES, byte_cost)
+}
+
+/// Cost of the token state initialized from the args.
+pub fn args_storage_cost(args_len: u64, extra_init_len: u64, byte_cost: Balance) -> Balance {
+    storage_cost(args_len + extra_init_len, byte_cost)
+}
+
+/// Upper estimate of the registry storage for a token in `index_entries` tag and campaign
+/// indexes. The pending deployment keeps a copy of the args next to the token record until the
+/// deployment resolves.
+pub fn registry_storage_cost(
+    args_len: u64,
+    extra_init_len: u64,
+    index_entries: u64,
+    byte_cost: Balance,
+) -> Balance {
+    storage_cost(
+        args_len * 2 + extra_init_len + REGISTRY_EXTRA_BYTES + index_entries * INDEX_ENTRY_BYTES,
+        byte_cost,
+    )
+}
+
+/// Bytes of an `UnorderedMap` entry: the key index record, and the key and the value records of
+/// its two vectors, each of them prefixed by the map prefix and a one byte suffix.
+pub fn unordered_map_entry_bytes(prefix_len: u64, key_len: u64, value_len: u64) -> u64 {
+    let index_record = prefix_len + 1 + key_len + 8;
+    let vector_records = 2 * (prefix_len + 1 + 8) + key_len + value_len;
+    index_record + vector_records + 3 * RECORD_EXTRA_BYTES
+}
+
+/// Storage balance left after the storage usage moved from `initial_storage_usage` to
+/// `storage_usage`. Returns `None` if the balance doesn't cover the growth.
+pub fn settle_storage_balance(
+    balance: Balance,
+    initial_storage_usage: u64,
+    storage_usage: u64,
+    byte_cost: Balance,
+) -> Option<Balance> {
+    if storage_usage > initial_storage_usage {
+        balance.checked_sub(storage_cost(
+            storage_usage - initial_storage_usage,
+            byte_cost,
+        ))
+    } else {
+        Some(balance + storage_cost(initial_storage_usage - storage_usage, byte_cost))
+    }
+}
+
+/// Adds the account registration to a required deposit. Returns the registration cost and the
+/// new total. A registered account only has to top up its storage balance to the total.
+pub fn with_registration(
+    total: Balance,
+    registration_cost: Balance,
+    storage_balance: Option<Balance>,
+) -> (Balance, Balance) {
+    match storage_balance {
+        Some(storage_balance) => (0, total.saturating_sub(storage_balance)),
+        None => (registration_cost, registration_cost + total),
+    }
+}
+
+/// Part of the amount given in basis points.
+pub fn bps_share(amount: Balance, bps: u16) -> Balance {
+    amount * Balance::from(bps) / Balance::from(MAX_BPS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Price of a byte on mainnet, 10^19 yoctoNEAR.
+    const BYTE_COST: Balance = 10_000_000_000_000_000_000;
+    const NEAR: Balance = 1_000_000_000_000_000_000_000_000;
+
+    #[test]
+    fn test_storage_cost() {
+        assert_eq!(storage_cost(0, BYTE_COST), 0);
+        assert_eq!(storage_cost(1, BYTE_COST), BYTE_COST);
+        assert_eq!(storage_cost(100_000, BYTE_COST), NEAR);
+        assert_eq!(storage_cost(100_000, 0), 0);
+    }
+
+    #[test]
+    fn test_code_storage_cost() {
+        assert_eq!(code_storage_cost(0, BYTE_COST), NEAR / 10);
+        // A 200 kB token code and the account records need 2.1 NEAR.
+        assert_eq!(code_storage_cost(200_000, BYTE_COST), 21 * NEAR / 10);
+        assert_eq!(
+            code_storage_cost(3 * 1024 * 1024, BYTE_COST),
+            (3 * 1024 * 1024 + EXTRA_BYTES) as Balance * BYTE_COST
+        );
+    }
+
+    #[test]
+    fn test_args_storage_cost() {
+        assert_eq!(args_storage_cost(0, 0, BYTE_COST), 0);
+        assert_eq!(args_storage_cost(300, 0, BYTE_COST), 300 * BYTE_COST);
+        assert_eq!(args_storage_cost(300, 200, BYTE_COST), NEAR / 200);
+    }
+
+    #[test]
+    fn test_registry_storage_cost() {
+        assert_eq!(
+            registry_storage_cost(0, 0, 0, BYTE_COST),
+            REGISTRY_EXTRA_BYTES as Balance * BYTE_COST
+        );
+        assert_eq!(
+            registry_storage_cost(300, 100, 0, BYTE_COST),
+            2_700 * BYTE_COST
+        );
+        assert_eq!(
+            registry_storage_cost(300, 100, 2, BYTE_COST),
+            3_500 * BYTE_COST
+        );
+    }
+
+    #[test]
+    fn test_unordered_map_entry_bytes() {
+        // Index record of 1 + 1 + 8 + 8 bytes, key and value records of 10 + 8 and 10 + 100
+        // bytes, and the three records.
+        assert_eq!(unordered_map_entry_bytes(1, 8, 100), 266);
+        assert_eq!(unordered_map_entry_bytes(0, 0, 0), 147);
+    }
+
+    #[test]
+    fn test_settle_storage_balance() {
+        // Growth is paid from the balance, down to exactly zero.
+        assert_eq!(
+            settle_storage_balance(NEAR, 1_000, 1_000 + 100_000, BYTE_COST),
+            Some(0)
+        );
+        assert_eq!(
+            settle_storage_balance(NEAR, 1_000, 1_000 + 100_001, BYTE_COST),
+            None
+        );
+        // Released storage is refunded to the balance.
+        assert_eq!(
+            settle_storage_balance(0, 1_000 + 100_000, 1_000, BYTE_COST),
+            Some(NEAR)
+        );
+        assert_eq!(settle_storage_balance(7, 1_000, 1_000, BYTE_COST), Some(7));
+    }
+
+    #[test]
+    fn test_with_registration() {
+        assert_eq!(
+            with_registration(NEAR, NEAR / 100, None),
+            (NEAR / 100, NEAR + NEAR / 100)
+        );
+        assert_eq!(
+            with_registration(NEAR, NEAR / 100, Some(NEAR / 2)),
+            (0, NEAR / 2)
+        );
+        assert_eq!(with_registration(NEAR, NEAR / 100, Some(2 * NEAR)), (0, 0));
+        assert_eq!(with_registration(0, 0, None), (0, 0));
+    }
+
+    #[test]
+    fn test_bps_share() {
+        assert_eq!(bps_share(1_000_000, 0), 0);
+        assert_eq!(bps_share(1_000_000, 2_500), 250_000);
+        assert_eq!(bps_share(1_000_000, MAX_BPS), 1_000_000);
+        assert_eq!(bps_share(9_999, 1), 0);
+        assert_eq!(bps_share(10_000, 1), 1);
+    }
+
+    #[test]
+    fn test_bps_share_of_every_bps() {
+        let amount = 123_456_789 * NEAR;
+        let mut previous = 0;
+        for bps in 0..=MAX_BPS {
+            let share = bps_share(amount, bps);
+            let rest = bps_share(amount, MAX_BPS - bps);
+            assert!(share >= previous);
+            assert!(share + rest <= amount && amount - share - rest <= 1);
+            previous = share;
+        }
+        assert_eq!(previous, amount);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_storage_cost_is_linear(
+            a in 0..1u64 << 40,
+            b in 0..1u64 << 40,
+            byte_cost in 0..=10 * BYTE_COST,
+        ) {
+            prop_assert_eq!(
+                storage_cost(a + b, byte_cost),
+                storage_cost(a, byte_cost) + storage_cost(b, byte_cost)
+            );
+        }
+
+        #[test]
+        fn prop_registry_storage_covers_the_args(
+            args_len in 0..1u64 << 32,
+            extra_init_len in 0..1u64 << 32,
+            byte_cost in 0..=10 * BYTE_COST,
+        ) {
+            prop_assert!(
+                registry_storage_cost(args_len, extra_init_len, 0, byte_cost)
+                    >= args_storage_cost(args_len, extra_init_len, byte_cost)
+            );
+        }
+
+        #[test]
+        fn prop_settle_storage_balance_round_trips(
+            balance in 0..1_000 * NEAR,
+            initial_storage_usage in 0..1u64 << 32,
+            storage_usage in 0..1u64 << 32,
+            byte_cost in 0..=10 * BYTE_COST,
+        ) {
+            let settled =
+                settle_storage_balance(balance, initial_storage_usage, storage_usage, byte_cost);
+            let growth_cost =
+                storage_cost(storage_usage.saturating_sub(initial_storage_usage), byte_cost);
+            prop_assert_eq!(settled.is_none(), growth_cost > balance);
+            if let Some(settled) = settled {
+                let (old_usage, new_usage) = (storage_usage, initial_storage_usage);
+                let restored = settle_storage_balance(settled, old_usage, new_usage, byte_cost);
+                prop_assert_eq!(restored, Some(balance));
+            }
+        }
+
+        #[test]
+        fn prop_registration_deposit_covers_the_total(
+            total in 0..1_000 * NEAR,
+            registration_cost in 0..NEAR,
+            storage_balance in proptest::option::of(0..1_000 * NEAR),
+        ) {
+            let (registration, deposit) =
+                with_registration(total, registration_cost, storage_balance);
+            match storage_balance {
+                Some(storage_balance) => {
+                    prop_assert_eq!(registration, 0);
+                    prop_assert!(storage_balance + deposit >= total);
+                }
+                None => prop_assert_eq!(deposit, total + registration),
+            }
+        }
+
+        #[test]
+        fn prop_bps_shares_add_up(amount in 0..1u128 << 100, bps in 0..=MAX_BPS) {
+            let share = bps_share(amount, bps);
+            let rest = bps_share(amount, MAX_BPS - bps);
+            prop_assert!(share <= amount);
+            prop_assert!(share + rest <= amount && amount - share - rest <= 1);
+        }
+    }
+}
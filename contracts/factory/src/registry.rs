@@ -0,0 +1,1514 @@
+//! Registry of the created tokens and its indexes, symbol validation and token maintenance.
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct IconUpload {
+    pub data: String,
+    pub finalized: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct IconUploadInfo {
+    pub length: u64,
+    pub finalized: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct IconChunk {
+    pub data: String,
+    /// Number of chunks of the icon, which are concatenated to get the data URL.
+    pub chunks: u64,
+}
+
+/// What is left of a token record once it was archived. The token id stays taken.
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ArchivedToken {
+    pub token_account_id: AccountId,
+    pub creator_id: AccountId,
+    pub owner_id: AccountId,
+    pub symbol: String,
+    pub archived_at: U64,
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReferenceRefresh {
+    pub reference: Option<String>,
+    pub reference_hash: Option<Base64VecU8>,
+    pub ready_at: U64,
+}
+
+/// near.social profile of a creator as read from the SocialDB by the factory. The handle is the
+/// account the profile is stored under, which is always the creator itself.
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SocialProfile {
+    pub handle: AccountId,
+    /// Display name of the profile at the time it was linked.
+    pub name: Option<String>,
+    pub social_db_id: AccountId,
+    pub linked_at: U64,
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct HolderMilestone {
+    pub holders: U64,
+    pub reached_at: U64,
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TemplateStats {
+    pub tokens: u64,
+    /// Sum of the total supplies of the tokens, saturating at the maximum.
+    pub total_supply: U128,
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenCodeInfo {
+    pub code_hash: Base64VecU8,
+    pub code_size: u64,
+}
+
+impl TokenCodeInfo {
+    pub(crate) fn new(code: &[u8]) -> Self {
+        Self {
+            code_hash: env::sha256(code).into(),
+            code_size: code.len() as u64,
+        }
+    }
+}
+
+/// Settings shared by the tokens launched in a campaign, e.g. of a hackathon.
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CampaignSettings {
+    /// Discount on the creation fee in basis points.
+    pub fee_discount_bps: u16,
+    /// Template the campaign's tokens have to be created with, if any.
+    pub template: Option<String>,
+    /// Branding shown by UIs next to the campaign's tokens.
+    pub title: String,
+    pub icon: Option<String>,
+    pub reference: Option<String>,
+    /// Block timestamp after which no more tokens can be launched in the campaign.
+    pub ends_at: Option<U64>,
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Campaign {
+    pub settings: CampaignSettings,
+    pub tokens: u64,
+}
+
+impl Campaign {
+    pub(crate) fn is_active(&self) -> bool {
+        self.settings
+            .ends_at
+            .map_or(true, |ends_at| env::block_timestamp() <= ends_at.0)
+    }
+}
+
+/// Token code registered under a name, which creators select with the `template` of the args.
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Template {
+    pub code: TokenCodeInfo,
+    /// JSON schema of the init args the template's `new` method expects.
+    pub init_schema: String,
+    /// Disabled templates can't be selected anymore, but pending deployments still use them.
+    pub enabled: bool,
+    /// Bounds the template's `storage_balance_bounds` returns, as declared by the owner.
+    pub storage_balance_bounds: Option<TemplateStorageBounds>,
+    /// Deposit of a `storage_deposit` for the owner chained after `new`, for templates whose
+    /// `new` doesn't register the owner itself. It is part of the token deposit.
+    pub owner_registration: Option<U128>,
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TemplateStorageBounds {
+    pub min: U128,
+    pub max: Option<U128>,
+}
+
+/// Checks that the code is a WebAssembly module small enough to be deployed together with the
+/// other actions, matching `expected_hash` if given.
+pub(crate) fn assert_valid_token_code(
+    code: &[u8],
+    expected_hash: Option<Base64VecU8>,
+) -> TokenCodeInfo {
+    let info = TokenCodeInfo::new(code);
+    if let Some(expected_hash) = expected_hash {
+        assert_eq!(
+            expected_hash.0, info.code_hash.0,
+            "Code doesn't match the expected hash"
+        );
+    }
+    assert!(
+        code.starts_with(WASM_MAGIC),
+        "Code is not a WebAssembly module"
+    );
+    assert!(
+        code.len() <= MAX_TOKEN_CODE_SIZE,
+        "Code can't exceed {} bytes",
+        MAX_TOKEN_CODE_SIZE
+    );
+    info
+}
+
+/// Result of replacing the token code, with the code storage cost every creation is charged.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenCodeUpdate {
+    pub code: TokenCodeInfo,
+    pub previous_code_storage: U128,
+    pub code_storage: U128,
+}
+
+/// Limits derived from the account id every token account is a sub-account of, which is the
+/// factory account unless a token namespace is configured.
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Limits {
+    pub factory_account_id: AccountId,
+    pub max_symbol_length: u8,
+}
+
+impl Limits {
+    pub(crate) fn new(token_parent_id: &str) -> Self {
+        let max_symbol_length = MAX_ACCOUNT_ID_LEN
+            .checked_sub(token_parent_id.len() + 1)
+            .expect("Token parent account id is too long");
+        Self {
+            factory_account_id: env::current_account_id(),
+            max_symbol_length: max_symbol_length as u8,
+        }
+    }
+}
+
+/// Incremental Merkle tree over the deployed tokens, following the deposit contract approach:
+/// only the rightmost branch is stored, so appending a leaf and computing the root both take
+/// `MERKLE_TREE_DEPTH` hashes.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct MerkleTree {
+    branch: Vec<Vec<u8>>,
+    leaf_count: u64,
+}
+
+impl MerkleTree {
+    pub(crate) fn new() -> Self {
+        Self {
+            branch: vec![vec![0; 32]; MERKLE_TREE_DEPTH],
+            leaf_count: 0,
+        }
+    }
+
+    pub(crate) fn append(&mut self, leaf: Vec<u8>) {
+        self.leaf_count += 1;
+        let mut size = self.leaf_count;
+        let mut node = leaf;
+        for height in 0..MERKLE_TREE_DEPTH {
+            if size % 2 == 1 {
+                self.branch[height] = node;
+                return;
+            }
+            node = hash_pair(&self.branch[height], &node);
+            size /= 2;
+        }
+    }
+
+    fn root(&self) -> Vec<u8> {
+        let mut node = vec![0; 32];
+        let mut zero_hash = vec![0; 32];
+        let mut size = self.leaf_count;
+        for height in 0..MERKLE_TREE_DEPTH {
+            node = if size % 2 == 1 {
+                hash_pair(&self.branch[height], &node)
+            } else {
+                hash_pair(&node, &zero_hash)
+            };
+            zero_hash = hash_pair(&zero_hash, &zero_hash);
+            size /= 2;
+        }
+        node
+    }
+}
+
+fn hash_pair(left: &[u8], right: &[u8]) -> Vec<u8> {
+    env::sha256(&[left, right].concat())
+}
+
+/// Leaf of the registry tree: `sha256(borsh(token_id) ++ borsh(args))`.
+pub(crate) fn registry_leaf(token_id: &TokenId, args: &TokenArgs) -> Vec<u8> {
+    let mut data = token_id.try_to_vec().unwrap();
+    data.extend(args.try_to_vec().unwrap());
+    env::sha256(&data)
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CreatorStats {
+    pub tokens_created: u64,
+}
+
+/// Namespace grouping the tokens of a project under `project.factory`. The first creator that
+/// launches under a project owns it.
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Project {
+    pub owner_id: AccountId,
+    /// Whether the namespace account was created.
+    pub namespace_created: bool,
+    /// Set while a deployment creates the namespace account, so other deployments don't try to
+    /// create it as well.
+    pub namespace_pending: bool,
+}
+
+/// Adds the token to the set of `key` in a per-key index, creating the set under `set_prefix`
+/// on first use. Unlike a `Vec` value, the set isn't read and rewritten whole on every change,
+/// so the cost doesn't grow with the popularity of the key.
+pub(crate) fn index_add<K: BorshSerialize>(
+    index: &mut LookupMap<K, UnorderedSet<TokenId>>,
+    key: &K,
+    set_prefix: StorageKey,
+    token_id: &TokenId,
+) {
+    let mut set = index
+        .get(key)
+        .unwrap_or_else(|| UnorderedSet::new(set_prefix));
+    set.insert(token_id);
+    index.insert(key, &set);
+}
+
+/// Removes the token from the set of `key`, dropping the set once it is empty.
+pub(crate) fn index_remove<K: BorshSerialize>(
+    index: &mut LookupMap<K, UnorderedSet<TokenId>>,
+    key: &K,
+    token_id: &TokenId,
+) {
+    if let Some(mut set) = index.get(key) {
+        set.remove(token_id);
+        if set.is_empty() {
+            index.remove(key);
+        } else {
+            index.insert(key, &set);
+        }
+    }
+}
+
+/// Normalizes a token name for comparison, keeping only its lowercase alphanumeric characters.
+pub(crate) fn normalize_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Levenshtein distance of the character sequences, keeping a single row of the matrix.
+fn levenshtein_distance(a: &[char], b: &[char]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = diagonal + if a_char == b_char { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = std::cmp::min(substitution, std::cmp::min(row[j], row[j + 1]) + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Similarity of the character sequences in basis points: the share of the longer one that
+/// doesn't have to be edited to get the other.
+fn similarity_bps(a: &[char], b: &[char], distance: usize) -> u16 {
+    let longest = std::cmp::max(a.len(), b.len());
+    if longest == 0 {
+        return MAX_BPS;
+    }
+    ((longest - distance) * usize::from(MAX_BPS) / longest) as u16
+}
+
+/// Similarity reference a flagged token is similar to.
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SimilarityFlag {
+    pub similar_to: String,
+    pub similarity_bps: u16,
+}
+
+/// Non-ASCII characters that look like a lowercase ASCII letter.
+const CONFUSABLE_CHARACTERS: &[(char, char)] = &[
+    ('а', 'a'),
+    ('в', 'b'),
+    ('с', 'c'),
+    ('ԁ', 'd'),
+    ('е', 'e'),
+    ('һ', 'h'),
+    ('і', 'i'),
+    ('ј', 'j'),
+    ('к', 'k'),
+    ('м', 'm'),
+    ('о', 'o'),
+    ('р', 'p'),
+    ('ԛ', 'q'),
+    ('ѕ', 's'),
+    ('т', 't'),
+    ('у', 'y'),
+    ('ԝ', 'w'),
+    ('х', 'x'),
+    ('α', 'a'),
+    ('β', 'b'),
+    ('ε', 'e'),
+    ('ι', 'i'),
+    ('κ', 'k'),
+    ('ν', 'v'),
+    ('ο', 'o'),
+    ('ρ', 'p'),
+    ('τ', 't'),
+    ('υ', 'u'),
+    ('χ', 'x'),
+];
+
+/// ASCII sequences that look alike, replaced by a canonical one in skeletons.
+const CONFUSABLE_SEQUENCES: &[(&str, &str)] = &[
+    ("rn", "m"),
+    ("vv", "w"),
+    ("0", "o"),
+    ("1", "l"),
+    ("i", "l"),
+    ("5", "s"),
+    ("8", "b"),
+];
+
+/// Maps a fullwidth form or a listed lookalike to the lowercase ASCII character it imitates.
+fn confusable_ascii(c: char) -> Option<char> {
+    if ('\u{ff01}'..='\u{ff5e}').contains(&c) {
+        return std::char::from_u32(c as u32 - 0xfee0).map(|c| c.to_ascii_lowercase());
+    }
+    let lowercase = c.to_lowercase().next().unwrap_or(c);
+    CONFUSABLE_CHARACTERS
+        .iter()
+        .find(|(confusable, _)| *confusable == lowercase)
+        .map(|(_, ascii)| *ascii)
+}
+
+/// Returns the first character of the text that imitates an ASCII character.
+fn find_confusable_character(text: &str) -> Option<char> {
+    text.chars()
+        .find(|c| !c.is_ascii() && confusable_ascii(*c).is_some())
+}
+
+/// Reduces a symbol to a skeleton that is equal for lookalike symbols.
+pub(crate) fn confusable_skeleton(symbol: &str) -> String {
+    let mut skeleton: String = symbol
+        .chars()
+        .map(|c| confusable_ascii(c).unwrap_or_else(|| c.to_ascii_lowercase()))
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect();
+    for (sequence, replacement) in CONFUSABLE_SEQUENCES {
+        skeleton = skeleton.replace(sequence, replacement);
+    }
+    skeleton
+}
+
+/// Returns the lowercase hosts of the URLs found in the text.
+fn url_hosts(text: &str) -> Vec<String> {
+    text.match_indices("://")
+        .map(|(index, _)| {
+            text[index + 3..]
+                .chars()
+                .take_while(|c| c.is_ascii_alphanumeric() || *c == '.' || *c == '-')
+                .collect::<String>()
+                .to_ascii_lowercase()
+        })
+        .filter(|host| !host.is_empty())
+        .collect()
+}
+
+/// Splits a token id such as `token.project` into the token name and its project.
+pub(crate) fn split_project(token_id: &str) -> (&str, Option<&str>) {
+    let mut parts = token_id.splitn(2, '.');
+    (parts.next().unwrap(), parts.next())
+}
+
+#[near_bindgen]
+impl TokenFactory {
+    /// Whether the init schema of the template of the args declares the given init arg.
+    pub(crate) fn internal_template_has_init_arg(&self, args: &TokenArgs, name: &str) -> bool {
+        let template_name = args
+            .template
+            .clone()
+            .unwrap_or_else(|| DEFAULT_TEMPLATE.to_string());
+        self.get_template_schema(template_name)
+            .map_or(false, |schema| !schema["properties"][name].is_null())
+    }
+
+    pub(crate) fn get_template_code_info(&self, template: Option<&String>) -> TokenCodeInfo {
+        match template {
+            Some(template) => {
+                self.templates
+                    .get(template)
+                    .expect("Template not found")
+                    .code
+            }
+            None => self.token_code_info.clone(),
+        }
+    }
+
+    pub(crate) fn internal_get_token_code(&self, template: Option<&String>) -> Vec<u8> {
+        match template {
+            Some(template) => self
+                .template_code
+                .get(template)
+                .expect("Template not found"),
+            None => self
+                .token_code
+                .get()
+                .unwrap_or_else(|| FT_WASM_CODE.to_vec()),
+        }
+    }
+
+    pub(crate) fn get_token_account_id(&self, token_id: &TokenId) -> AccountId {
+        format!("{}.{}", token_id, self.internal_token_parent_id())
+    }
+
+    /// Account the token accounts are created under.
+    pub(crate) fn internal_token_parent_id(&self) -> AccountId {
+        self.config
+            .token_namespace_id
+            .as_ref()
+            .map_or_else(env::current_account_id, |namespace_id| {
+                namespace_id.to_string()
+            })
+    }
+
+    /// Returns the account the token is deployed to, which differs from the derived one for
+    /// tokens detached by `rebind_registry`.
+    pub(crate) fn internal_token_account_id(
+        &self,
+        token_id: &TokenId,
+        token: &TokenRecord,
+    ) -> AccountId {
+        token
+            .detached_account_id
+            .clone()
+            .unwrap_or_else(|| self.get_token_account_id(token_id))
+    }
+
+    pub(crate) fn internal_record_creation(&mut self, creator_id: &AccountId) {
+        let mut stats = self.creator_stats.get(creator_id).unwrap_or_default();
+        stats.tokens_created += 1;
+        self.creator_stats.insert(creator_id, &stats);
+
+        let mut top_creators = self.top_creators.get().unwrap();
+        top_creators.retain(|(account_id, _)| account_id != creator_id);
+        top_creators.push((creator_id.clone(), stats.tokens_created));
+        top_creators.sort_by(|a, b| b.1.cmp(&a.1));
+        top_creators.truncate(MAX_TOP_CREATORS);
+        self.top_creators.set(&top_creators);
+    }
+
+    /// Hook for tokens whose template counts its holders, see `ext_factory` of factory-types.
+    /// Only tokens that are still sub-accounts of the factory can report, and only their
+    /// highest milestone is kept. The milestones are self-reported: the factory can't verify
+    /// the holder count, so a token with custom code can claim any milestone. Neither bundled
+    /// template reports them.
+    pub fn on_holder_milestone(&mut self, holders: U64) {
+        self.assert_not_migrating();
+        let token_account_id = env::predecessor_account_id();
+        let token_id = token_account_id
+            .strip_suffix(&format!(".{}", self.internal_token_parent_id()))
+            .map(|token_id| token_id.to_string())
+            .filter(|token_id| {
+                self.tokens
+                    .get(token_id)
+                    .map_or(false, |token| token.detached_account_id.is_none())
+            })
+            .expect("Only registered tokens can report their holders");
+        assert!(
+            HOLDER_MILESTONES.contains(&holders.0),
+            "Holders have to be one of the milestones {:?}",
+            HOLDER_MILESTONES
+        );
+        if let Some(milestone) = self.holder_milestones.get(&token_id) {
+            if milestone.holders.0 >= holders.0 {
+                return;
+            }
+        }
+        self.holder_milestones.insert(
+            &token_id,
+            &HolderMilestone {
+                holders,
+                reached_at: env::block_timestamp().into(),
+            },
+        );
+        FactoryEvent::HolderMilestoneReached { token_id, holders }.emit();
+    }
+
+    /// Schedules a new metadata reference for a token whose template has `update_reference`,
+    /// replacing a scheduled one. Only the creator and its managers can call it, and the
+    /// reference can be applied with `apply_token_reference_refresh` after
+    /// `reference_refresh_delay_sec`, so holders see a rebranding coming. The storage is charged
+    /// to the creator's storage balance.
+    pub fn refresh_token_reference(
+        &mut self,
+        token_id: TokenId,
+        new_reference: Option<String>,
+        new_hash: Option<Base64VecU8>,
+    ) -> U64 {
+        self.assert_not_migrating();
+        let account_id = env::predecessor_account_id();
+        let token = self.tokens.get(&token_id).expect("Token not found");
+        self.assert_token_manager(&token_id, &token.creator_id, &account_id);
+        assert!(!token.frozen, "Token is frozen");
+        assert_eq!(
+            new_reference.is_some(),
+            new_hash.is_some(),
+            "Reference and its hash have to be set together"
+        );
+        if let Some(new_hash) = &new_hash {
+            assert_eq!(new_hash.0.len(), 32, "Reference hash has to be 32 bytes");
+        }
+        let ready_at: U64 = (env::block_timestamp()
+            + self.config.reference_refresh_delay_sec * 1_000_000_000)
+            .into();
+        let initial_storage_usage = env::storage_usage();
+        self.reference_refreshes.insert(
+            &token_id,
+            &ReferenceRefresh {
+                reference: new_reference.clone(),
+                reference_hash: new_hash,
+                ready_at,
+            },
+        );
+        self.internal_settle_storage(&token.creator_id, initial_storage_usage);
+        FactoryEvent::TokenReferenceRefreshScheduled {
+            token_id,
+            reference: new_reference,
+            ready_at,
+        }
+        .emit();
+        ready_at
+    }
+
+    /// Applies the scheduled reference to the token once the timelock passed. Only the creator
+    /// and its managers can call it.
+    pub fn apply_token_reference_refresh(&mut self, token_id: TokenId) -> Promise {
+        self.assert_not_migrating();
+        let account_id = env::predecessor_account_id();
+        let token = self.tokens.get(&token_id).expect("Token not found");
+        self.assert_token_manager(&token_id, &token.creator_id, &account_id);
+        let refresh = self
+            .reference_refreshes
+            .get(&token_id)
+            .expect("No reference refresh scheduled");
+        assert!(
+            env::block_timestamp() >= refresh.ready_at.0,
+            "Reference refresh is timelocked until {}",
+            refresh.ready_at.0
+        );
+        ext_ft::update_reference(
+            refresh.reference,
+            refresh.reference_hash,
+            &self.internal_token_account_id(&token_id, &token),
+            0,
+            UPDATE_REFERENCE_GAS,
+        )
+        .then(ext_self::on_reference_refreshed(
+            token_id,
+            &env::current_account_id(),
+            0,
+            self.config.callback_gas,
+        ))
+    }
+
+    /// Drops the scheduled reference refresh of a token, e.g. one whose code has no
+    /// `update_reference`, and returns its storage to the creator's storage balance. Only the
+    /// creator and its managers can call it.
+    pub fn cancel_token_reference_refresh(&mut self, token_id: TokenId) {
+        self.assert_not_migrating();
+        let account_id = env::predecessor_account_id();
+        let token = self.tokens.get(&token_id).expect("Token not found");
+        self.assert_token_manager(&token_id, &token.creator_id, &account_id);
+        let initial_storage_usage = env::storage_usage();
+        self.reference_refreshes
+            .remove(&token_id)
+            .expect("No reference refresh scheduled");
+        self.internal_settle_storage(&token.creator_id, initial_storage_usage);
+        env::log(format!("Cancelled the reference refresh of {}", token_id).as_bytes());
+    }
+
+    /// Mirrors the applied reference in the registry. A failed refresh stays scheduled, so it
+    /// can be retried or cancelled with `cancel_token_reference_refresh`.
+    #[private]
+    pub fn on_reference_refreshed(&mut self, token_id: TokenId) -> bool {
+        let success = is_promise_success();
+        let refresh = match self.reference_refreshes.get(&token_id) {
+            Some(refresh) => refresh,
+            None => return false,
+        };
+        let reference = refresh.reference.clone();
+        if let (true, Some(mut token)) = (success, self.tokens.get(&token_id)) {
+            let initial_storage_usage = env::storage_usage();
+            self.reference_refreshes.remove(&token_id);
+            token.args.metadata.reference = refresh.reference;
+            token.args.metadata.reference_hash = refresh.reference_hash;
+            self.tokens.insert(&token_id, &token);
+            self.internal_settle_callback_storage(&token.creator_id, initial_storage_usage);
+        }
+        self.internal_emit(FactoryEvent::TokenReferenceRefreshed {
+            token_id,
+            reference,
+            success,
+        });
+        success
+    }
+
+    /// Replaces the localized names and descriptions of a token. Only the creator and its
+    /// managers can call it. The storage is charged to the creator's storage balance.
+    pub fn set_token_localizations(
+        &mut self,
+        token_id: TokenId,
+        localizations: BTreeMap<String, Localization>,
+    ) {
+        self.assert_not_migrating();
+        let account_id = env::predecessor_account_id();
+        let mut token = self.tokens.get(&token_id).expect("Token not found");
+        self.assert_token_manager(&token_id, &token.creator_id, &account_id);
+        assert!(!token.frozen, "Token is frozen");
+        assert_valid_localizations(&localizations);
+        let initial_storage_usage = env::storage_usage();
+        token.args.localizations = localizations;
+        self.tokens.insert(&token_id, &token);
+        self.internal_settle_storage(&token.creator_id, initial_storage_usage);
+    }
+
+    /// Authorizes the account to act as the creator of the token, e.g. to refresh its reference
+    /// or to retry its deployment. Only the creator can call it. The storage is charged to the
+    /// creator's storage balance.
+    pub fn add_token_manager(&mut self, token_id: TokenId, account_id: ValidAccountId) {
+        self.assert_not_migrating();
+        let creator_id = env::predecessor_account_id();
+        let token = self.tokens.get(&token_id).expect("Token not found");
+        assert_eq!(
+            token.creator_id, creator_id,
+            "Only the creator can add managers"
+        );
+        assert!(!token.frozen, "Token is frozen");
+        let account_id: AccountId = account_id.into();
+        assert_ne!(account_id, creator_id, "Creator can't be a manager");
+        let mut managers = self.token_managers.get(&token_id).unwrap_or_default();
+        assert!(
+            !managers.contains(&account_id),
+            "Account is already a manager"
+        );
+        assert!(
+            managers.len() < MAX_TOKEN_MANAGERS,
+            "Token can't have more than {} managers",
+            MAX_TOKEN_MANAGERS
+        );
+        let initial_storage_usage = env::storage_usage();
+        managers.push(account_id.clone());
+        self.token_managers.insert(&token_id, &managers);
+        self.internal_settle_storage(&creator_id, initial_storage_usage);
+        self.internal_emit(FactoryEvent::TokenManagerAdded {
+            token_id,
+            account_id,
+        });
+    }
+
+    /// Revokes a manager of the token. Only the creator can call it.
+    pub fn remove_token_manager(&mut self, token_id: TokenId, account_id: ValidAccountId) {
+        self.assert_not_migrating();
+        let creator_id = env::predecessor_account_id();
+        let token = self.tokens.get(&token_id).expect("Token not found");
+        assert_eq!(
+            token.creator_id, creator_id,
+            "Only the creator can remove managers"
+        );
+        let account_id: AccountId = account_id.into();
+        let mut managers = self.token_managers.get(&token_id).unwrap_or_default();
+        let index = managers
+            .iter()
+            .position(|manager_id| manager_id == &account_id)
+            .expect("Account is not a manager");
+        let initial_storage_usage = env::storage_usage();
+        managers.swap_remove(index);
+        if managers.is_empty() {
+            self.token_managers.remove(&token_id);
+        } else {
+            self.token_managers.insert(&token_id, &managers);
+        }
+        self.internal_settle_storage(&creator_id, initial_storage_usage);
+        self.internal_emit(FactoryEvent::TokenManagerRemoved {
+            token_id,
+            account_id,
+        });
+    }
+
+    /// Whether the account is the creator of the token or one of its managers.
+    pub(crate) fn internal_is_token_manager(
+        &self,
+        token_id: &TokenId,
+        creator_id: &AccountId,
+        account_id: &AccountId,
+    ) -> bool {
+        account_id == creator_id
+            || self
+                .token_managers
+                .get(token_id)
+                .map_or(false, |managers| managers.contains(account_id))
+    }
+
+    fn assert_token_manager(
+        &self,
+        token_id: &TokenId,
+        creator_id: &AccountId,
+        account_id: &AccountId,
+    ) {
+        assert!(
+            self.internal_is_token_manager(token_id, creator_id, account_id),
+            "Only the creator or a manager can manage the token"
+        );
+    }
+
+    /// Links the predecessor's near.social profile, so wallets can show which profile launched
+    /// its tokens. The factory reads the profile from the configured SocialDB itself, so the
+    /// link proves the profile is stored under the creator's account. Calling it again refreshes
+    /// the linked name. The storage is charged to the storage balance.
+    pub fn link_social_profile(&mut self) -> Promise {
+        let account_id = env::predecessor_account_id();
+        self.assert_not_banned(&account_id);
+        let social_db_id: AccountId = self
+            .config
+            .social_db_id
+            .clone()
+            .expect("Social profiles are disabled")
+            .into();
+        ext_social::get(
+            vec![format!("{}/profile/name", account_id)],
+            &social_db_id,
+            0,
+            SOCIAL_GET_GAS,
+        )
+        .then(ext_self::on_social_profile_fetched(
+            account_id,
+            social_db_id,
+            &env::current_account_id(),
+            0,
+            self.config.callback_gas,
+        ))
+    }
+
+    /// Stores the profile if the SocialDB has one for the account. The SocialDB it was read from
+    /// is passed along, as the config may have changed while the lookup was in flight.
+    #[private]
+    pub fn on_social_profile_fetched(
+        &mut self,
+        account_id: AccountId,
+        social_db_id: AccountId,
+    ) -> bool {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "Contract expected a result on the callback"
+        );
+        let profile = match env::promise_result(0) {
+            PromiseResult::Successful(data) => serde_json::from_slice::<serde_json::Value>(&data)
+                .ok()
+                .and_then(|data| data.get(&account_id)?.get("profile").cloned()),
+            _ => None,
+        };
+        let profile = match profile {
+            Some(profile) => profile,
+            None => {
+                env::log(format!("No near.social profile found for {}", account_id).as_bytes());
+                return false;
+            }
+        };
+        let name = profile["name"].as_str().map(|name| {
+            name.chars()
+                .take(MAX_SOCIAL_NAME_LENGTH)
+                .collect::<String>()
+        });
+        let initial_storage_usage = env::storage_usage();
+        self.social_profiles.insert(
+            &account_id,
+            &SocialProfile {
+                handle: account_id.clone(),
+                name: name.clone(),
+                social_db_id,
+                linked_at: env::block_timestamp().into(),
+            },
+        );
+        self.internal_settle_callback_storage(&account_id, initial_storage_usage);
+        self.internal_emit(FactoryEvent::SocialProfileLinked { account_id, name });
+        true
+    }
+
+    /// Removes the predecessor's linked profile and refunds its storage to the storage balance.
+    pub fn unlink_social_profile(&mut self) {
+        let account_id = env::predecessor_account_id();
+        let initial_storage_usage = env::storage_usage();
+        self.social_profiles
+            .remove(&account_id)
+            .expect("No social profile linked");
+        self.internal_settle_storage(&account_id, initial_storage_usage);
+    }
+
+    /// Registers the project on first use, or checks that the account owns it.
+    pub(crate) fn internal_use_project(&mut self, account_id: &AccountId, project: &str) {
+        assert!(
+            self.config.token_namespace_id.is_none(),
+            "Projects are not supported with a token namespace"
+        );
+        assert!(
+            !project.contains('.')
+                && env::is_valid_account_id(
+                    format!("{}.{}", project, env::current_account_id()).as_bytes()
+                ),
+            "Project name is invalid"
+        );
+        let project = project.to_string();
+        assert!(
+            self.tokens.get(&project).is_none() && self.proposals.get(&project).is_none(),
+            "Project name is taken by a token"
+        );
+        let info = self.projects.get(&project).unwrap_or(Project {
+            owner_id: account_id.clone(),
+            namespace_created: false,
+            namespace_pending: false,
+        });
+        assert_eq!(
+            &info.owner_id, account_id,
+            "Only the project owner can launch tokens under it"
+        );
+        self.projects.insert(&project, &info);
+    }
+
+    /// Checks that a page of deployed tokens still answer `ft_metadata`. Can be called by
+    /// anyone, e.g. by a cron service, and returns the number of tokens pinged. Tokens that are
+    /// still being deployed are skipped.
+    pub fn ping_tokens(&mut self, from_index: u64, limit: Option<u64>) -> u64 {
+        self.assert_not_migrating();
+        let limit = std::cmp::min(limit.unwrap_or(MAX_PING_PAGE_SIZE), MAX_PING_PAGE_SIZE);
+        let token_ids = self.tokens.keys_as_vector();
+        let mut pinged = 0;
+        for index in page_range(from_index, Some(limit), token_ids.len()) {
+            let token_id = token_ids.get(index).unwrap();
+            if self.deployments.get(&token_id).is_some() {
+                continue;
+            }
+            assert!(
+                env::prepaid_gas() - env::used_gas() >= PING_GAS + PING_CALLBACK_GAS,
+                "Not enough gas attached to ping {} tokens",
+                limit
+            );
+            let token_account_id =
+                self.internal_token_account_id(&token_id, &self.tokens.get(&token_id).unwrap());
+            ext_ft::ft_metadata(&token_account_id, 0, PING_GAS).then(ext_self::on_token_pinged(
+                token_id,
+                &env::current_account_id(),
+                0,
+                PING_CALLBACK_GAS,
+            ));
+            pinged += 1;
+        }
+        pinged
+    }
+
+    #[private]
+    pub fn on_token_pinged(&mut self, token_id: TokenId) -> bool {
+        let success = is_promise_success();
+        if let Some(mut token) = self.tokens.get(&token_id) {
+            if success {
+                token.last_seen_ok = Some(env::block_timestamp().into());
+            }
+            token.unresponsive = !success;
+            self.tokens.insert(&token_id, &token);
+        }
+        success
+    }
+
+    /// Checks on chain that the account `symbol.factory` doesn't exist yet, e.g. because it was
+    /// created with a key of the factory account. Resolves to whether the account is available.
+    pub fn probe_account(&self, symbol: String) -> Promise {
+        let token_account_id = self.get_token_account_id(&symbol.to_ascii_lowercase());
+        assert!(
+            env::is_valid_account_id(token_account_id.as_bytes()),
+            "Token Account ID is invalid"
+        );
+        self.internal_probe_account(token_account_id)
+    }
+
+    /// A transfer of nothing only fails if the receiving account doesn't exist.
+    fn internal_probe_account(&self, account_id: AccountId) -> Promise {
+        Promise::new(account_id)
+            .transfer(0)
+            .then(ext_self::on_account_probed(
+                &env::current_account_id(),
+                0,
+                PROBE_CALLBACK_GAS,
+            ))
+    }
+
+    #[private]
+    pub fn on_account_probed(&mut self) -> bool {
+        !is_promise_success()
+    }
+
+    pub(crate) fn internal_validate_symbol(&self, symbol: &str) -> SymbolValidation {
+        let token_id = symbol.to_ascii_lowercase();
+        if token_id.is_empty() {
+            return SymbolValidation::Empty;
+        }
+        for (index, character) in token_id.chars().enumerate() {
+            if !matches!(character, '0'..='9' | 'a'..='z') {
+                return SymbolValidation::InvalidCharacter {
+                    index: index as u32,
+                    character,
+                };
+            }
+        }
+        if token_id.len() > self.limits.max_symbol_length as usize {
+            return SymbolValidation::TooLong {
+                max_length: self.limits.max_symbol_length,
+            };
+        }
+        if self.retired_symbols.contains(&token_id) {
+            return SymbolValidation::Retired;
+        }
+        if self.reserved_symbols.contains(&token_id) {
+            return SymbolValidation::Reserved;
+        }
+        if !self.internal_is_token_id_free(&token_id) {
+            return SymbolValidation::Taken;
+        }
+        SymbolValidation::Valid {
+            token_account_id: self.get_token_account_id(&token_id),
+            token_id,
+        }
+    }
+
+    /// Checks that no token, proposal, pending deployment, project, archived token or
+    /// preprovisioned account uses the id. The pending
+    /// deployment is recorded synchronously and only removed by its callback, so it locks the
+    /// id while the deployment receipts are in flight.
+    pub(crate) fn internal_is_token_id_free(&self, token_id: &TokenId) -> bool {
+        self.assert_not_migrating();
+        // Project namespaces share the factory sub-accounts with the tokens.
+        self.tokens.get(token_id).is_none()
+            && self.proposals.get(token_id).is_none()
+            && self.deployments.get(token_id).is_none()
+            && self.creation_queue.get(token_id).is_none()
+            && self.projects.get(token_id).is_none()
+            && self.archived_tokens.get(token_id).is_none()
+            && self.preprovisioned_accounts.get(token_id).is_none()
+    }
+
+    pub(crate) fn internal_check_metadata_domains(
+        &self,
+        metadata: &FungibleTokenMetadata,
+    ) -> Option<String> {
+        let mut texts = vec![];
+        if let Some(reference) = &metadata.reference {
+            texts.push(reference.clone());
+        }
+        if let Some(icon) = &metadata.icon {
+            // Base64 encoded SVG icons can still load external resources.
+            if let Some(data) = icon.strip_prefix("data:image/svg+xml;base64,") {
+                if let Ok(data) = serde_json::from_value::<Base64VecU8>(data.into()) {
+                    texts.push(String::from_utf8_lossy(&data.0).into_owned());
+                }
+            }
+            texts.push(icon.clone());
+        }
+        texts
+            .iter()
+            .flat_map(|text| url_hosts(text))
+            .find_map(|host| self.internal_find_denied_domain(&host))
+    }
+
+    /// Returns the denied domain the host is or is a subdomain of.
+    fn internal_find_denied_domain(&self, host: &str) -> Option<String> {
+        std::iter::once(0)
+            .chain(host.match_indices('.').map(|(index, _)| index + 1))
+            .map(|index| host[index..].to_string())
+            .find(|domain| self.denied_domains.contains(domain))
+    }
+
+    /// Appends a chunk to the predecessor's icon upload, so icons too large for a single
+    /// transaction can be staged across several. The storage is charged to the storage balance.
+    pub fn upload_icon_chunk(&mut self, upload_id: String, chunk: String) {
+        let account_id = env::predecessor_account_id();
+        self.assert_not_banned(&account_id);
+        let key = (account_id.clone(), upload_id);
+        let mut upload = self.icon_uploads.get(&key).unwrap_or(IconUpload {
+            data: String::new(),
+            finalized: false,
+        });
+        assert!(!upload.finalized, "Icon upload is already finalized");
+        upload.data.push_str(&chunk);
+        assert!(
+            upload.data.len() <= MAX_ICON_LENGTH,
+            "Icon can't be longer than {} bytes",
+            MAX_ICON_LENGTH
+        );
+        let initial_storage_usage = env::storage_usage();
+        self.icon_uploads.insert(&key, &upload);
+        self.internal_settle_storage(&account_id, initial_storage_usage);
+    }
+
+    /// Marks the upload as complete, so it can be referenced by `icon_upload_id` in the args.
+    pub fn finalize_icon(&mut self, upload_id: String) {
+        let key = (env::predecessor_account_id(), upload_id);
+        let mut upload = self.icon_uploads.get(&key).expect("Icon upload not found");
+        assert!(
+            upload.data.starts_with("data:image/"),
+            "Icon has to be a data URL"
+        );
+        upload.finalized = true;
+        self.icon_uploads.insert(&key, &upload);
+    }
+
+    /// Removes the upload and returns its storage to the storage balance.
+    pub fn delete_icon_upload(&mut self, upload_id: String) {
+        let account_id = env::predecessor_account_id();
+        let initial_storage_usage = env::storage_usage();
+        self.icon_uploads
+            .remove(&(account_id.clone(), upload_id))
+            .expect("Icon upload not found");
+        self.internal_settle_storage(&account_id, initial_storage_usage);
+    }
+
+    /// Replaces the icon with the referenced upload and returns the key of the upload. An icon
+    /// blob is referenced by its hash in the metadata, next to a reference to the `get_icon` view
+    /// of the factory, since the metadata standard only accepts a hash with a reference.
+    pub(crate) fn internal_resolve_icon(
+        &self,
+        account_id: &AccountId,
+        args: &mut TokenArgs,
+    ) -> Option<(AccountId, String)> {
+        let upload_id = match args.icon_upload_id.take() {
+            Some(upload_id) => upload_id,
+            None => {
+                assert!(!args.icon_blob, "Icon blobs need an icon_upload_id");
+                return None;
+            }
+        };
+        let key = (account_id.clone(), upload_id);
+        let upload = self.icon_uploads.get(&key).expect("Icon upload not found");
+        assert!(upload.finalized, "Icon upload is not finalized");
+        if args.icon_blob {
+            assert!(
+                args.metadata.reference.is_none() && args.metadata.reference_hash.is_none(),
+                "The reference_hash of an icon blob is the hash of the icon"
+            );
+            args.metadata.icon = None;
+            args.metadata.reference =
+                Some(format!("near://{}/get_icon", env::current_account_id()));
+            args.metadata.reference_hash = Some(env::sha256(upload.data.as_bytes()).into());
+        } else {
+            args.metadata.icon = Some(upload.data);
+        }
+        Some(key)
+    }
+
+    /// Resolves the icon upload of the args and removes it, returning its storage to the storage
+    /// balance. Returns the icon if it has to be kept as a blob.
+    pub(crate) fn internal_take_icon_upload(
+        &mut self,
+        account_id: &AccountId,
+        args: &mut TokenArgs,
+    ) -> Option<String> {
+        let upload_key = self.internal_resolve_icon(account_id, args)?;
+        let initial_storage_usage = env::storage_usage();
+        let upload = self.icon_uploads.remove(&upload_key).unwrap();
+        self.internal_settle_storage(account_id, initial_storage_usage);
+        if args.icon_blob {
+            Some(upload.data)
+        } else {
+            None
+        }
+    }
+
+    /// Stores the icon of the token in chunks that each fit into a view call.
+    pub(crate) fn internal_store_icon_blob(&mut self, token_id: &TokenId, icon: &str) {
+        let mut chunks = 0;
+        let mut rest = icon;
+        while !rest.is_empty() {
+            let mut end = std::cmp::min(ICON_CHUNK_SIZE, rest.len());
+            while !rest.is_char_boundary(end) {
+                end -= 1;
+            }
+            let (chunk, tail) = rest.split_at(end);
+            self.icon_chunks
+                .insert(&(token_id.clone(), chunks), &chunk.to_string());
+            chunks += 1;
+            rest = tail;
+        }
+        self.icon_blobs.insert(token_id, &chunks);
+    }
+
+    pub(crate) fn internal_remove_icon_blob(&mut self, token_id: &TokenId) {
+        if let Some(chunks) = self.icon_blobs.remove(token_id) {
+            for chunk in 0..chunks {
+                self.icon_chunks.remove(&(token_id.clone(), chunk));
+            }
+        }
+    }
+
+    pub fn get_registry_merkle_root(&self) -> RegistryRoot {
+        let tree = self.registry_tree.get().unwrap();
+        RegistryRoot {
+            root: tree.root().into(),
+            leaf_count: tree.leaf_count,
+        }
+    }
+
+    pub fn get_registry_leaves(&self, from_index: u64, limit: Option<u64>) -> Vec<Base64VecU8> {
+        page_range(from_index, limit, self.registry_leaves.len())
+            .filter_map(|index| self.registry_leaves.get(index))
+            .map(|leaf| leaf.into())
+            .collect()
+    }
+
+    pub(crate) fn internal_record_template_creation(&mut self, args: &TokenArgs) {
+        let template = args
+            .template
+            .clone()
+            .unwrap_or_else(|| DEFAULT_TEMPLATE.to_string());
+        let mut stats = self.template_stats.get(&template).unwrap_or(TemplateStats {
+            tokens: 0,
+            total_supply: 0.into(),
+        });
+        stats.tokens += 1;
+        stats.total_supply = stats
+            .total_supply
+            .0
+            .saturating_add(args.total_supply.0)
+            .into();
+        self.template_stats.insert(&template, &stats);
+    }
+
+    /// Refuses metadata that imitates other tokens, according to `confusable_check`.
+    pub(crate) fn internal_assert_not_confusable(&self, metadata: &FungibleTokenMetadata) {
+        if self.config.confusable_check == ConfusableCheck::Off {
+            return;
+        }
+        if let Some(c) = find_confusable_character(&metadata.symbol) {
+            panic!("Symbol contains the confusable character {}", c);
+        }
+        if let Some(c) = find_confusable_character(&metadata.name) {
+            panic!("Name contains the confusable character {}", c);
+        }
+        if self.config.confusable_check == ConfusableCheck::Skeleton {
+            // Tokens of different projects can share the exact same symbol.
+            let symbol = metadata.symbol.to_ascii_lowercase();
+            assert!(
+                self.tokens_by_symbol.get(&symbol).is_some()
+                    || self
+                        .symbol_skeletons
+                        .get(&confusable_skeleton(&symbol))
+                        .is_none(),
+                "Symbol is confusable with the symbol of a registered token"
+            );
+        }
+    }
+
+    /// Returns the similarity reference closest to the normalized symbol or name, if any of them
+    /// reaches `min_similarity_bps`.
+    pub(crate) fn internal_find_similar(
+        &self,
+        metadata: &FungibleTokenMetadata,
+    ) -> Option<SimilarityFlag> {
+        let min_similarity_bps = self.config.min_similarity_bps;
+        let candidates: Vec<Vec<char>> = vec![
+            normalize_name(&metadata.symbol).chars().collect(),
+            normalize_name(&metadata.name).chars().collect(),
+        ];
+        let mut closest: Option<SimilarityFlag> = None;
+        for reference in self.similarity_references.iter() {
+            let reference_chars: Vec<char> = reference.chars().collect();
+            for candidate in &candidates {
+                // Even without substitutions, the length difference has to be edited.
+                let length_difference = if candidate.len() > reference_chars.len() {
+                    candidate.len() - reference_chars.len()
+                } else {
+                    reference_chars.len() - candidate.len()
+                };
+                if similarity_bps(candidate, &reference_chars, length_difference)
+                    < min_similarity_bps
+                {
+                    continue;
+                }
+                let distance = levenshtein_distance(candidate, &reference_chars);
+                let similarity = similarity_bps(candidate, &reference_chars, distance);
+                if similarity >= min_similarity_bps
+                    && closest
+                        .as_ref()
+                        .map_or(true, |closest| similarity > closest.similarity_bps)
+                {
+                    closest = Some(SimilarityFlag {
+                        similar_to: reference.clone(),
+                        similarity_bps: similarity,
+                    });
+                }
+            }
+        }
+        closest
+    }
+
+    pub(crate) fn internal_assert_not_similar(&self, metadata: &FungibleTokenMetadata) {
+        if self.config.similarity_check != SimilarityCheck::Block {
+            return;
+        }
+        if let Some(flag) = self.internal_find_similar(metadata) {
+            panic!(
+                "Token is too similar to {} ({} basis points)",
+                flag.similar_to, flag.similarity_bps
+            );
+        }
+    }
+
+    pub(crate) fn internal_add_to_symbol_index(&mut self, symbol: &str, token_id: &TokenId) {
+        let skeleton = confusable_skeleton(symbol);
+        let count = self.symbol_skeletons.get(&skeleton).unwrap_or(0);
+        self.symbol_skeletons.insert(&skeleton, &(count + 1));
+        let symbol = symbol.to_ascii_lowercase();
+        let set_prefix = StorageKey::SymbolTokens {
+            symbol_hash: env::sha256(symbol.as_bytes()),
+        };
+        index_add(&mut self.tokens_by_symbol, &symbol, set_prefix, token_id);
+    }
+
+    pub(crate) fn internal_remove_from_symbol_index(&mut self, symbol: &str, token_id: &TokenId) {
+        let skeleton = confusable_skeleton(symbol);
+        match self.symbol_skeletons.get(&skeleton).unwrap_or(0) {
+            0 | 1 => self.symbol_skeletons.remove(&skeleton),
+            count => self.symbol_skeletons.insert(&skeleton, &(count - 1)),
+        };
+        index_remove(
+            &mut self.tokens_by_symbol,
+            &symbol.to_ascii_lowercase(),
+            token_id,
+        );
+    }
+
+    pub(crate) fn internal_add_to_tag_index(&mut self, tags: &[String], token_id: &TokenId) {
+        for tag in tags {
+            let set_prefix = StorageKey::TagTokens {
+                tag_hash: env::sha256(tag.as_bytes()),
+            };
+            index_add(&mut self.tokens_by_tag, tag, set_prefix, token_id);
+        }
+    }
+
+    pub(crate) fn internal_remove_from_tag_index(&mut self, tags: &[String], token_id: &TokenId) {
+        for tag in tags {
+            index_remove(&mut self.tokens_by_tag, tag, token_id);
+        }
+    }
+
+    pub(crate) fn internal_add_to_campaign(
+        &mut self,
+        campaign: Option<&String>,
+        token_id: &TokenId,
+    ) {
+        if let Some(name) = campaign {
+            let set_prefix = StorageKey::CampaignTokens {
+                name_hash: env::sha256(name.as_bytes()),
+            };
+            index_add(&mut self.tokens_by_campaign, name, set_prefix, token_id);
+            if let Some(mut campaign) = self.campaigns.get(name) {
+                campaign.tokens += 1;
+                self.campaigns.insert(name, &campaign);
+            }
+        }
+    }
+
+    pub(crate) fn internal_remove_from_campaign(
+        &mut self,
+        campaign: Option<&String>,
+        token_id: &TokenId,
+    ) {
+        if let Some(name) = campaign {
+            index_remove(&mut self.tokens_by_campaign, name, token_id);
+            if let Some(mut campaign) = self.campaigns.get(name) {
+                campaign.tokens -= 1;
+                self.campaigns.insert(name, &campaign);
+            }
+        }
+    }
+
+    pub(crate) fn internal_add_to_owner_index(&mut self, owner_id: &AccountId, token_id: &TokenId) {
+        let set_prefix = StorageKey::OwnerTokens {
+            account_hash: env::sha256(owner_id.as_bytes()),
+        };
+        index_add(&mut self.tokens_by_owner, owner_id, set_prefix, token_id);
+    }
+
+    pub(crate) fn internal_remove_from_owner_index(
+        &mut self,
+        owner_id: &AccountId,
+        token_id: &TokenId,
+    ) {
+        index_remove(&mut self.tokens_by_owner, owner_id, token_id);
+    }
+
+    pub(crate) fn internal_add_to_name_index(&mut self, name: &str) {
+        let name = normalize_name(name);
+        let count = self.token_names.get(&name).unwrap_or(0);
+        self.token_names.insert(&name, &(count + 1));
+    }
+
+    pub(crate) fn internal_remove_from_name_index(&mut self, name: &str) {
+        let name = normalize_name(name);
+        match self.token_names.get(&name).unwrap_or(0) {
+            0 | 1 => self.token_names.remove(&name),
+            count => self.token_names.insert(&name, &(count - 1)),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::test_utils::accounts;
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    /// Reports the holders from the account at the timestamp.
+    fn report_holders(factory: &mut TokenFactory, account_id: &str, holders: u64, timestamp: u64) {
+        testing_env!(context(ValidAccountId::try_from(account_id).unwrap())
+            .block_timestamp(timestamp)
+            .build());
+        factory.on_holder_milestone(holders.into());
+    }
+
+    #[test]
+    fn test_levenshtein_distance_and_similarity() {
+        let (a, b) = (chars("kitten"), chars("sitting"));
+        assert_eq!(levenshtein_distance(&a, &b), 3);
+        assert_eq!(levenshtein_distance(&b, &a), 3);
+        assert_eq!(levenshtein_distance(&a, &a), 0);
+        assert_eq!(levenshtein_distance(&a, &[]), 6);
+        // 4 of the 7 characters of the longer one are kept.
+        assert_eq!(similarity_bps(&a, &b, 3), 5_714);
+        assert_eq!(similarity_bps(&[], &[], 0), MAX_BPS);
+    }
+
+    #[test]
+    fn test_normalize_name() {
+        assert_eq!(normalize_name("Tether USD!"), "tetherusd");
+        assert_eq!(normalize_name("  "), "");
+    }
+
+    #[test]
+    fn test_confusable_skeleton() {
+        assert_eq!(confusable_skeleton("USDT"), "usdt");
+        assert_eq!(confusable_skeleton("u5dt"), "usdt");
+        // Cyrillic `т` and fullwidth forms.
+        assert_eq!(confusable_skeleton("usdт"), "usdt");
+        assert_eq!(confusable_skeleton("ＵＳＤＴ"), "usdt");
+        assert_eq!(confusable_skeleton("rnoon"), confusable_skeleton("moon"));
+        assert_ne!(confusable_skeleton("usdc"), "usdt");
+    }
+
+    #[test]
+    fn test_find_confusable_character() {
+        assert_eq!(find_confusable_character("Usdа"), Some('а'));
+        assert_eq!(find_confusable_character("USDT"), None);
+        assert_eq!(find_confusable_character("日本"), None);
+    }
+
+    #[test]
+    fn test_url_hosts() {
+        assert_eq!(
+            url_hosts("See https://Evil.com/claim and ipfs://a-b.io, or ://"),
+            vec!["evil.com", "a-b.io"]
+        );
+        assert!(url_hosts("no links").is_empty());
+    }
+
+    #[test]
+    fn test_split_project() {
+        assert_eq!(split_project("abc.launch"), ("abc", Some("launch")));
+        assert_eq!(split_project("abc"), ("abc", None));
+    }
+
+    #[test]
+    fn test_holder_milestones_only_move_up() {
+        let mut factory = setup_factory();
+        let token_id = seed_token(&mut factory, token_args(accounts(1), "ABC"));
+        let token_account_id = factory.get_token_account_id(&token_id);
+        report_holders(&mut factory, &token_account_id, 100, 1);
+        // A lower milestone reported later, e.g. after holders left, is ignored.
+        report_holders(&mut factory, &token_account_id, 10, 2);
+        let milestone = factory.get_holder_milestone(token_id.clone()).unwrap();
+        assert_eq!((milestone.holders.0, milestone.reached_at.0), (100, 1));
+        report_holders(&mut factory, &token_account_id, 100, 3);
+        assert_eq!(
+            factory
+                .get_holder_milestone(token_id.clone())
+                .unwrap()
+                .reached_at
+                .0,
+            1
+        );
+        report_holders(&mut factory, &token_account_id, 1_000, 4);
+        let milestone = factory.get_holder_milestone(token_id).unwrap();
+        assert_eq!((milestone.holders.0, milestone.reached_at.0), (1_000, 4));
+    }
+
+    #[test]
+    #[should_panic(expected = "Holders have to be one of the milestones")]
+    fn test_holder_count_between_milestones() {
+        let mut factory = setup_factory();
+        let token_id = seed_token(&mut factory, token_args(accounts(1), "ABC"));
+        let token_account_id = factory.get_token_account_id(&token_id);
+        report_holders(&mut factory, &token_account_id, 123, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only registered tokens can report their holders")]
+    fn test_holder_milestone_from_another_account() {
+        let mut factory = setup_factory();
+        seed_token(&mut factory, token_args(accounts(1), "ABC"));
+        // The token id alone isn't enough, the report has to come from the token account.
+        report_holders(&mut factory, "abc", 100, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only registered tokens can report their holders")]
+    fn test_holder_milestone_from_an_unregistered_sub_account() {
+        let mut factory = setup_factory();
+        seed_token(&mut factory, token_args(accounts(1), "ABC"));
+        let token_account_id = factory.get_token_account_id(&"xyz".to_string());
+        report_holders(&mut factory, &token_account_id, 100, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only registered tokens can report their holders")]
+    fn test_holder_milestone_from_a_detached_token() {
+        let mut factory = setup_factory();
+        let token_id = seed_token(&mut factory, token_args(accounts(1), "ABC"));
+        let mut token = factory.tokens.get(&token_id).unwrap();
+        token.detached_account_id = Some(accounts(2).into());
+        factory.tokens.insert(&token_id, &token);
+        let token_account_id = factory.get_token_account_id(&token_id);
+        report_holders(&mut factory, &token_account_id, 100, 1);
+    }
+}
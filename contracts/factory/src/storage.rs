@@ -0,0 +1,1018 @@
+//! Storage balances of the accounts, required deposits and creation fees.
+use crate::*;
+
+/// Rate a payment token is credited at, `yocto_near` for every `token_amount` of its smallest
+/// units.
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PaymentTokenRate {
+    pub yocto_near: U128,
+    pub token_amount: U128,
+}
+
+impl PaymentTokenRate {
+    fn to_yocto_near(&self, amount: Balance) -> Balance {
+        amount
+            .checked_mul(self.yocto_near.0)
+            .expect("Amount is too large")
+            / self.token_amount.0
+    }
+}
+
+/// Promotional discount on the creation fee between two timestamps, in nanoseconds.
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeDiscount {
+    pub starts_at: U64,
+    pub ends_at: U64,
+    pub discount_bps: u16,
+    /// Number of discounted creations every account gets, unlimited when unset.
+    pub max_per_account: Option<u64>,
+}
+
+impl FeeDiscount {
+    fn is_active(&self) -> bool {
+        let now = env::block_timestamp();
+        self.starts_at.0 <= now && now < self.ends_at.0
+    }
+}
+
+/// Price as returned by the oracle, `multiplier / 10^decimals` USD per yoctoNEAR.
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OraclePrice {
+    pub multiplier: U128,
+    pub decimals: u8,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AssetOptionalPrice {
+    pub asset_id: String,
+    pub price: Option<OraclePrice>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceData {
+    pub timestamp: U64,
+    pub prices: Vec<AssetOptionalPrice>,
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CachedUsdPrice {
+    pub asset_id: String,
+    pub price: OraclePrice,
+    /// Oracle timestamp of the price, in nanoseconds.
+    pub timestamp: U64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DepositBreakdown {
+    pub account_registration: U128,
+    pub code_storage: U128,
+    pub args_storage: U128,
+    /// Estimate of the factory's own storage for the token, charged at its exact size.
+    pub registry_storage: U128,
+    /// Storage of the project namespace account, when it is launched into a new project.
+    pub namespace_storage: U128,
+    /// Deposit for minting the launch certificate, if the factory mints one.
+    pub launch_certificate: U128,
+    /// Deposit of the owner registration chained after the init, for templates that need one.
+    pub owner_registration: U128,
+    pub fee: U128,
+    pub total: U128,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageDepositBounds {
+    pub min_deposit: U128,
+    pub max_balance: Option<U128>,
+}
+
+/// Deposits made by `storage_deposit` since the first one of the grace period.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct RecentDeposit {
+    pub amount: Balance,
+    /// Whether the first deposit registered the account, so reclaiming unregisters it again.
+    pub registered: bool,
+    pub deposited_at: u64,
+}
+
+#[near_bindgen]
+impl TokenFactory {
+    pub(crate) fn get_min_attached_balance(
+        &self,
+        args: &TokenArgs,
+        extra_init_json: Option<&String>,
+    ) -> u128 {
+        self.get_code_storage_cost(args.template.as_ref())
+            + self.get_args_storage_cost(args, extra_init_json)
+            + self.get_owner_registration_deposit(args.template.as_ref())
+    }
+
+    pub(crate) fn get_owner_registration_deposit(&self, template: Option<&String>) -> Balance {
+        template
+            .and_then(|template| self.templates.get(template))
+            .and_then(|template| template.owner_registration)
+            .map_or(0, |owner_registration| owner_registration.0)
+    }
+
+    pub(crate) fn get_code_storage_cost(&self, template: Option<&String>) -> Balance {
+        pricing::code_storage_cost(
+            self.get_template_code_info(template).code_size,
+            STORAGE_PRICE_PER_BYTE,
+        )
+    }
+
+    pub(crate) fn get_args_storage_cost(
+        &self,
+        args: &TokenArgs,
+        extra_init_json: Option<&String>,
+    ) -> Balance {
+        pricing::args_storage_cost(
+            args.try_to_vec().unwrap().len() as u64,
+            extra_init_json.map_or(0, |extra_init_json| extra_init_json.len() as u64),
+            STORAGE_PRICE_PER_BYTE,
+        )
+    }
+
+    fn get_registry_storage_cost(
+        &self,
+        args: &TokenArgs,
+        extra_init_json: Option<&String>,
+    ) -> Balance {
+        pricing::registry_storage_cost(
+            args.try_to_vec().unwrap().len() as u64,
+            extra_init_json.map_or(0, |extra_init_json| extra_init_json.len() as u64),
+            (args.tags.len() + args.campaign.iter().count()) as u64,
+            STORAGE_PRICE_PER_BYTE,
+        )
+    }
+
+    /// Creation fee the account pays for the token, with the discounts of its campaign and of
+    /// the best running promotion applied one after the other.
+    pub(crate) fn internal_get_creation_fee(
+        &self,
+        args: &TokenArgs,
+        account_id: &AccountId,
+    ) -> Balance {
+        let fee = self.get_creation_fee().0;
+        let fee = match args
+            .campaign
+            .as_ref()
+            .and_then(|campaign| self.campaigns.get(campaign))
+        {
+            Some(campaign) => fee - pricing::bps_share(fee, campaign.settings.fee_discount_bps),
+            None => fee,
+        };
+        match self.internal_get_fee_discount(account_id) {
+            Some((_, discount)) => fee - pricing::bps_share(fee, discount.discount_bps),
+            None => fee,
+        }
+    }
+
+    /// Returns the running promotion with the largest discount the account hasn't used up.
+    fn internal_get_fee_discount(&self, account_id: &AccountId) -> Option<(String, FeeDiscount)> {
+        self.fee_discounts
+            .iter()
+            .filter(|(name, discount)| {
+                discount.is_active()
+                    && discount.max_per_account.map_or(true, |max_per_account| {
+                        self.fee_discount_uses
+                            .get(&(name.clone(), account_id.clone()))
+                            .unwrap_or(0)
+                            < max_per_account
+                    })
+            })
+            .max_by_key(|(_, discount)| discount.discount_bps)
+    }
+
+    /// Counts a creation of the account against the cap of the promotion discounting it.
+    /// Returns the promotion if it is capped, so the use can be released again.
+    pub(crate) fn internal_use_fee_discount(&mut self, account_id: &AccountId) -> Option<String> {
+        let (name, discount) = self.internal_get_fee_discount(account_id)?;
+        discount.max_per_account?;
+        let key = (name, account_id.clone());
+        let uses = self.fee_discount_uses.get(&key).unwrap_or(0);
+        self.fee_discount_uses.insert(&key, &(uses + 1));
+        Some(key.0)
+    }
+
+    /// Releases the use counted by `internal_use_fee_discount` for a creation that was rejected
+    /// or failed, whose fee was refunded.
+    pub(crate) fn internal_release_fee_discount(
+        &mut self,
+        account_id: &AccountId,
+        name: Option<&String>,
+    ) {
+        let key = match name {
+            Some(name) => (name.clone(), account_id.clone()),
+            None => return,
+        };
+        match self.fee_discount_uses.get(&key) {
+            Some(uses) if uses > 1 => {
+                self.fee_discount_uses.insert(&key, &(uses - 1));
+            }
+            Some(_) => {
+                self.fee_discount_uses.remove(&key);
+            }
+            None => {}
+        }
+    }
+
+    fn get_storage_balance_cost(&self) -> Balance {
+        pricing::storage_cost(self.storage_balance_bytes, STORAGE_PRICE_PER_BYTE)
+    }
+
+    fn get_namespace_storage_cost(&self) -> Balance {
+        pricing::code_storage_cost(NAMESPACE_WASM_CODE.len() as u64, STORAGE_PRICE_PER_BYTE)
+    }
+
+    /// Deposit for the namespace account of the project, if it doesn't exist yet.
+    pub(crate) fn get_namespace_deposit(&self, project: Option<&String>) -> Balance {
+        let project = match project {
+            Some(project) => project,
+            None => return 0,
+        };
+        if self
+            .projects
+            .get(project)
+            .map_or(false, |info| info.namespace_created)
+        {
+            0
+        } else {
+            self.get_namespace_storage_cost()
+        }
+    }
+
+    pub(crate) fn get_available_fees(&self) -> Balance {
+        self.fees_collected - self.total_referral_payouts - self.fees_withdrawn
+    }
+
+    /// Returns the creation fee in yoctoNEAR, including the USD part converted with the cached
+    /// oracle price. Fails while the price is missing or older than the configured age.
+    pub fn get_creation_fee(&self) -> U128 {
+        let usd_fee = match &self.config.usd_fee {
+            Some(usd_fee) => usd_fee,
+            None => return self.config.creation_fee,
+        };
+        let cached = self
+            .usd_price
+            .as_ref()
+            .filter(|cached| cached.asset_id == usd_fee.asset_id)
+            .expect("USD price is missing, call refresh_usd_price");
+        assert!(
+            env::block_timestamp().saturating_sub(cached.timestamp.0)
+                <= usd_fee.max_price_age_sec * 1_000_000_000,
+            "USD price is stale, call refresh_usd_price"
+        );
+        let fee = Balance::from(usd_fee.fee_usd_cents)
+            .checked_mul(10u128.pow(u32::from(cached.price.decimals)))
+            .expect("USD fee overflows")
+            / (cached.price.multiplier.0 * 100);
+        (self.config.creation_fee.0 + fee).into()
+    }
+
+    /// Fetches the NEAR price from the configured oracle and caches it for converting the USD
+    /// fee. Can be called by anyone.
+    pub fn refresh_usd_price(&self) -> Promise {
+        let usd_fee = self.config.usd_fee.as_ref().expect("USD fee is not set");
+        ext_oracle::get_price_data(
+            Some(vec![usd_fee.asset_id.clone()]),
+            usd_fee.oracle_id.as_ref(),
+            0,
+            ORACLE_GAS,
+        )
+        .then(ext_self::on_usd_price_fetched(
+            usd_fee.asset_id.clone(),
+            &env::current_account_id(),
+            0,
+            ORACLE_CALLBACK_GAS,
+        ))
+    }
+
+    #[private]
+    pub fn on_usd_price_fetched(&mut self, asset_id: String) -> bool {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "Contract expected a result on the callback"
+        );
+        let price_data = match env::promise_result(0) {
+            PromiseResult::Successful(data) => serde_json::from_slice::<PriceData>(&data).ok(),
+            _ => None,
+        };
+        let cached = price_data.and_then(|price_data| {
+            let timestamp = price_data.timestamp;
+            price_data
+                .prices
+                .into_iter()
+                .find(|price| price.asset_id == asset_id)
+                .and_then(|price| price.price)
+                .filter(|price| price.multiplier.0 > 0)
+                .map(|price| CachedUsdPrice {
+                    asset_id,
+                    price,
+                    timestamp,
+                })
+        });
+        match cached {
+            Some(cached) => {
+                self.usd_price = Some(cached);
+                true
+            }
+            None => {
+                env::log(b"Oracle didn't return a price");
+                false
+            }
+        }
+    }
+
+    pub(crate) fn internal_collect_fee(&mut self, fee: Balance, referrer_id: Option<AccountId>) {
+        if fee == 0 {
+            return;
+        }
+        self.fees_collected += fee;
+        if let Some(referrer_id) = referrer_id {
+            let payout = pricing::bps_share(fee, self.config.referral_fee_bps);
+            if payout > 0 {
+                let total_payout = self.referral_payouts.get(&referrer_id).unwrap_or(0) + payout;
+                self.referral_payouts.insert(&referrer_id, &total_payout);
+                self.total_referral_payouts += payout;
+                Promise::new(referrer_id).transfer(payout);
+            }
+        }
+    }
+
+    /// Returns the deposit the account has to attach to create the token, broken into its
+    /// components. The `total` accounts for the storage balance the account already has.
+    pub fn get_required_deposit(
+        &self,
+        args: TokenArgs,
+        account_id: ValidAccountId,
+        extra_init_json: Option<String>,
+        project: Option<String>,
+    ) -> DepositBreakdown {
+        let deposit = self.internal_get_token_deposit(
+            &args,
+            account_id.as_ref(),
+            extra_init_json.as_ref(),
+            project.as_ref(),
+        );
+        self.internal_add_registration(deposit, account_id.as_ref())
+    }
+
+    /// Returns the bytes the `tokens` entry of the token takes once the account created it with
+    /// the args and the key, including the creation stats added when the deployment succeeds.
+    /// The search indexes and the pending deployment are not included.
+    pub fn estimate_record_bytes(
+        &self,
+        args: TokenArgs,
+        account_id: ValidAccountId,
+        public_key: Option<Base58PublicKey>,
+    ) -> U64 {
+        let token_id = match self.config.token_id_mode {
+            TokenIdMode::Symbol => args.metadata.symbol.to_ascii_lowercase(),
+            TokenIdMode::Counter => format!("t{}", self.token_counter + 1),
+        };
+        let fee_on_transfer = args.has_transfer_fee();
+        let record = TokenRecord {
+            args,
+            creator_id: account_id.into(),
+            frozen: false,
+            creation_stats: Some(CreationStats::default()),
+            last_seen_ok: None,
+            unresponsive: false,
+            full_access_key: public_key,
+            detached_account_id: None,
+            fee_on_transfer,
+            created_at: Some(env::block_timestamp().into()),
+        };
+        pricing::unordered_map_entry_bytes(
+            StorageKey::Tokens.try_to_vec().unwrap().len() as u64,
+            token_id.try_to_vec().unwrap().len() as u64,
+            record.try_to_vec().unwrap().len() as u64,
+        )
+        .into()
+    }
+
+    /// Same as `get_required_deposit` for launching several tokens in a row, with the account
+    /// registration and the existing storage balance only counted once.
+    pub fn get_required_deposit_batch(
+        &self,
+        args_list: Vec<TokenArgs>,
+        account_id: ValidAccountId,
+    ) -> DepositBreakdown {
+        assert!(
+            args_list.len() as u64 <= MAX_PAGE_SIZE,
+            "Can't price more than {} tokens at once",
+            MAX_PAGE_SIZE
+        );
+        let empty = DepositBreakdown {
+            account_registration: 0.into(),
+            code_storage: 0.into(),
+            args_storage: 0.into(),
+            registry_storage: 0.into(),
+            namespace_storage: 0.into(),
+            launch_certificate: 0.into(),
+            owner_registration: 0.into(),
+            fee: 0.into(),
+            total: 0.into(),
+        };
+        let deposit = args_list
+            .iter()
+            .map(|args| self.internal_get_token_deposit(args, account_id.as_ref(), None, None))
+            .fold(empty, |total, deposit| DepositBreakdown {
+                account_registration: 0.into(),
+                code_storage: (total.code_storage.0 + deposit.code_storage.0).into(),
+                args_storage: (total.args_storage.0 + deposit.args_storage.0).into(),
+                registry_storage: (total.registry_storage.0 + deposit.registry_storage.0).into(),
+                namespace_storage: (total.namespace_storage.0 + deposit.namespace_storage.0).into(),
+                launch_certificate: (total.launch_certificate.0 + deposit.launch_certificate.0)
+                    .into(),
+                owner_registration: (total.owner_registration.0 + deposit.owner_registration.0)
+                    .into(),
+                fee: (total.fee.0 + deposit.fee.0).into(),
+                total: (total.total.0 + deposit.total.0).into(),
+            });
+        self.internal_add_registration(deposit, account_id.as_ref())
+    }
+
+    /// Deposit a single token needs, without the account registration.
+    fn internal_get_token_deposit(
+        &self,
+        args: &TokenArgs,
+        account_id: &AccountId,
+        extra_init_json: Option<&String>,
+        project: Option<&String>,
+    ) -> DepositBreakdown {
+        let code_storage = self.get_code_storage_cost(args.template.as_ref());
+        let args_storage = self.get_args_storage_cost(args, extra_init_json);
+        let registry_storage = self.get_registry_storage_cost(args, extra_init_json);
+        let namespace_storage = self.get_namespace_deposit(project);
+        let launch_certificate = self
+            .config
+            .launch_certificate
+            .as_ref()
+            .map_or(0, |certificate| certificate.mint_deposit.0);
+        let fee = self.internal_get_creation_fee(args, account_id);
+        let owner_registration = self.get_owner_registration_deposit(args.template.as_ref());
+        DepositBreakdown {
+            account_registration: 0.into(),
+            code_storage: code_storage.into(),
+            args_storage: args_storage.into(),
+            registry_storage: registry_storage.into(),
+            namespace_storage: namespace_storage.into(),
+            launch_certificate: launch_certificate.into(),
+            owner_registration: owner_registration.into(),
+            fee: fee.into(),
+            total: (code_storage
+                + args_storage
+                + registry_storage
+                + namespace_storage
+                + launch_certificate
+                + owner_registration
+                + fee)
+                .into(),
+        }
+    }
+
+    /// Adds the registration of an unregistered account to the deposit, or takes off the
+    /// existing storage balance of a registered one.
+    fn internal_add_registration(
+        &self,
+        deposit: DepositBreakdown,
+        account_id: &AccountId,
+    ) -> DepositBreakdown {
+        let (account_registration, total) = pricing::with_registration(
+            deposit.total.0,
+            self.get_storage_balance_cost(),
+            self.internal_storage_balance(account_id),
+        );
+        DepositBreakdown {
+            account_registration: account_registration.into(),
+            total: total.into(),
+            ..deposit
+        }
+    }
+
+    #[payable]
+    /// Returns the resulting storage balance. Its `total` includes the registration cost, and
+    /// only the `available` part can be spent on creating tokens.
+    pub fn storage_deposit(&mut self) -> StorageBalance {
+        let account_id = env::predecessor_account_id();
+        self.assert_not_banned(&account_id);
+        let deposit = env::attached_deposit();
+        let registered = self.internal_storage_deposit(&account_id, deposit, "deposit");
+        if self.config.deposit_grace_period_sec.is_some() && deposit > 0 {
+            let recent_deposit = match self.recent_deposits.get(&account_id) {
+                Some(mut recent_deposit)
+                    if !self.internal_is_grace_period_over(&recent_deposit) =>
+                {
+                    recent_deposit.amount += deposit;
+                    recent_deposit
+                }
+                _ => RecentDeposit {
+                    amount: deposit,
+                    registered,
+                    deposited_at: env::block_timestamp(),
+                },
+            };
+            self.recent_deposits.insert(&account_id, &recent_deposit);
+        }
+        self.internal_storage_balance_of(&account_id).unwrap()
+    }
+
+    /// Credits a deposit to the storage balance, registering the account if needed. Returns
+    /// whether the deposit registered it.
+    fn internal_storage_deposit(
+        &mut self,
+        account_id: &AccountId,
+        deposit: Balance,
+        reason: &str,
+    ) -> bool {
+        assert!(
+            deposit >= self.config.min_storage_deposit.0,
+            "Deposit is below the minimum of {}",
+            self.config.min_storage_deposit.0
+        );
+        let balance = if let Some(previous_balance) = self.internal_storage_balance(account_id) {
+            previous_balance + deposit
+        } else {
+            assert!(
+                deposit >= self.get_storage_balance_cost(),
+                "Deposit is too low"
+            );
+            deposit - self.get_storage_balance_cost()
+        };
+        if let Some(max_storage_balance) = self.config.max_storage_balance {
+            assert!(
+                balance <= max_storage_balance.0,
+                "Storage balance can't exceed the maximum of {}",
+                max_storage_balance.0
+            );
+        }
+        let registered = self.internal_storage_balance(account_id).is_none();
+        self.internal_set_storage_balance(account_id, balance, reason);
+        registered
+    }
+
+    /// Reverses the storage deposits of the predecessor made within the grace period, as long
+    /// as nothing was charged from the storage balance since. When the first of them registered
+    /// the account, it is unregistered and the registration cost is refunded as well.
+    pub fn reclaim_recent_deposit(&mut self) -> Promise {
+        let account_id = env::predecessor_account_id();
+        let recent_deposit = self
+            .recent_deposits
+            .remove(&account_id)
+            .expect("No recent deposit to reclaim, or it was already used");
+        assert!(
+            !self.internal_is_grace_period_over(&recent_deposit),
+            "Grace period of the deposit is over"
+        );
+        let balance = self.internal_storage_balance(&account_id).unwrap_or(0);
+        if recent_deposit.registered {
+            assert_eq!(
+                balance + self.get_storage_balance_cost(),
+                recent_deposit.amount,
+                "Storage balance was changed since the deposit"
+            );
+            self.internal_remove_storage_balance(&account_id, "reclaim");
+        } else {
+            assert!(
+                balance >= recent_deposit.amount,
+                "Storage balance was changed since the deposit"
+            );
+            self.internal_set_storage_balance(
+                &account_id,
+                balance - recent_deposit.amount,
+                "reclaim",
+            );
+        }
+        Promise::new(account_id).transfer(recent_deposit.amount)
+    }
+
+    fn internal_is_grace_period_over(&self, recent_deposit: &RecentDeposit) -> bool {
+        let grace_period_sec = self.config.deposit_grace_period_sec.unwrap_or(0);
+        env::block_timestamp() > recent_deposit.deposited_at + grace_period_sec * 1_000_000_000
+    }
+
+    /// Credits the deposit attached to a creation call to the storage balance.
+    pub(crate) fn internal_credit_creation_deposit(&mut self) {
+        let deposit = env::attached_deposit();
+        if deposit == 0 {
+            return;
+        }
+        assert!(
+            !self.config.separate_deposits,
+            "Creation calls don't accept a deposit, use storage_deposit first"
+        );
+        if let Some(max_creation_deposit) = self.config.max_creation_deposit {
+            assert!(
+                deposit <= max_creation_deposit.0,
+                "Attached deposit exceeds the maximum of {} for creations, use storage_deposit",
+                max_creation_deposit.0
+            );
+        }
+        self.storage_deposit();
+    }
+
+    pub fn storage_balance_of(&self, account_id: ValidAccountId) -> Option<StorageBalance> {
+        self.internal_storage_balance_of(account_id.as_ref())
+    }
+
+    /// Lists the storage balances of the registered accounts, for auditing the balances the
+    /// factory holds. Balances left in `legacy_storage_deposits` aren't listed.
+    pub fn get_storage_accounts(
+        &self,
+        from_index: u64,
+        limit: Option<u64>,
+    ) -> Vec<(AccountId, U128)> {
+        let keys = self.storage_deposits.keys_as_vector();
+        let values = self.storage_deposits.values_as_vector();
+        page_range(from_index, limit, keys.len())
+            .map(|index| (keys.get(index).unwrap(), values.get(index).unwrap().into()))
+            .collect()
+    }
+
+    fn internal_storage_balance_of(&self, account_id: &AccountId) -> Option<StorageBalance> {
+        self.internal_storage_balance(account_id)
+            .map(|balance| StorageBalance {
+                total: (self.get_storage_balance_cost() + balance).into(),
+                available: balance.into(),
+            })
+    }
+
+    pub fn get_storage_deposit_bounds(&self) -> StorageDepositBounds {
+        StorageDepositBounds {
+            min_deposit: self.config.min_storage_deposit,
+            max_balance: self.config.max_storage_balance,
+        }
+    }
+
+    /// Storage balance of a registered account, read from `legacy_storage_deposits` while it
+    /// wasn't moved over after the upgrade from the first version.
+    pub(crate) fn internal_storage_balance(&self, account_id: &AccountId) -> Option<Balance> {
+        self.storage_deposits.get(account_id).or_else(|| {
+            self.legacy_storage_deposits
+                .as_ref()
+                .and_then(|legacy_storage_deposits| legacy_storage_deposits.get(account_id))
+        })
+    }
+
+    /// Updates the storage balance of an account, keeping `total_storage_balances` in sync, and
+    /// logs the change with its reason so accounts can audit their balance. A legacy balance is
+    /// moved over to `storage_deposits` by its first change.
+    pub(crate) fn internal_set_storage_balance(
+        &mut self,
+        account_id: &AccountId,
+        balance: Balance,
+        reason: &str,
+    ) {
+        let legacy_balance = self
+            .legacy_storage_deposits
+            .as_mut()
+            .and_then(|legacy_storage_deposits| legacy_storage_deposits.remove(account_id));
+        // Legacy balances are part of the total since the migration.
+        let previous_balance = self
+            .storage_deposits
+            .insert(account_id, &balance)
+            .or(legacy_balance)
+            .unwrap_or(0);
+        self.total_storage_balances = self.total_storage_balances + balance - previous_balance;
+        if balance < previous_balance {
+            // Once anything is charged, the recent deposit paid for it and can't be reclaimed.
+            self.recent_deposits.remove(account_id);
+        }
+        if balance != previous_balance || legacy_balance.is_some() {
+            // Only logged, as recording them would flood the recent events and change the
+            // storage usage while it is being settled.
+            FactoryEvent::StorageBalanceChanged {
+                account_id: account_id.clone(),
+                old_balance: previous_balance.into(),
+                new_balance: balance.into(),
+                reason: reason.to_string(),
+            }
+            .emit();
+        }
+    }
+
+    /// Unregisters the account, dropping its storage balance from the total like
+    /// `internal_set_storage_balance` does for a change. Returns the removed balance.
+    pub(crate) fn internal_remove_storage_balance(
+        &mut self,
+        account_id: &AccountId,
+        reason: &str,
+    ) -> Balance {
+        let legacy_balance = self
+            .legacy_storage_deposits
+            .as_mut()
+            .and_then(|legacy_storage_deposits| legacy_storage_deposits.remove(account_id));
+        let previous_balance = self
+            .storage_deposits
+            .remove(account_id)
+            .or(legacy_balance)
+            .expect("Account is not registered");
+        self.total_storage_balances -= previous_balance;
+        self.recent_deposits.remove(account_id);
+        FactoryEvent::StorageBalanceChanged {
+            account_id: account_id.clone(),
+            old_balance: previous_balance.into(),
+            new_balance: 0.into(),
+            reason: reason.to_string(),
+        }
+        .emit();
+        previous_balance
+    }
+
+    /// Charges the storage growth since `initial_storage_usage` to the account's storage
+    /// balance, or refunds the released storage.
+    pub(crate) fn internal_settle_storage(
+        &mut self,
+        account_id: &AccountId,
+        initial_storage_usage: u64,
+    ) {
+        let storage_usage = env::storage_usage();
+        let balance = self
+            .internal_storage_balance(account_id)
+            .expect("Account has to be registered with storage_deposit");
+        let new_balance = pricing::settle_storage_balance(
+            balance,
+            initial_storage_usage,
+            storage_usage,
+            STORAGE_PRICE_PER_BYTE,
+        );
+        self.internal_log_diagnostic(
+            "storage_settlement",
+            serde_json::json!({
+                "account_id": account_id,
+                "initial_storage_usage": U64(initial_storage_usage),
+                "storage_usage": U64(storage_usage),
+                "balance_before": U128(balance),
+                "balance_after": new_balance.map(U128),
+            }),
+        );
+        let balance = new_balance.expect("Not enough storage balance");
+        self.internal_set_storage_balance(account_id, balance, "storage");
+    }
+
+    /// Same as `internal_settle_storage` for callbacks, which must not panic. A shortfall is
+    /// charged as far as the storage balance goes.
+    pub(crate) fn internal_settle_callback_storage(
+        &mut self,
+        account_id: &AccountId,
+        initial_storage_usage: u64,
+    ) {
+        let storage_usage = env::storage_usage();
+        let balance = self.internal_storage_balance(account_id).unwrap_or(0);
+        let balance = pricing::settle_storage_balance(
+            balance,
+            initial_storage_usage,
+            storage_usage,
+            STORAGE_PRICE_PER_BYTE,
+        )
+        .unwrap_or(0);
+        self.internal_set_storage_balance(account_id, balance, "storage");
+    }
+}
+
+/// Payment tokens transferred with an empty `msg` are credited to the sender's storage balance
+/// at their rate, like a `storage_deposit`. Tokens that aren't accepted are refunded. The first
+/// payment in a token also pays for its balance entry, and is refunded if it doesn't cover it.
+#[near_bindgen]
+impl FungibleTokenReceiver for TokenFactory {
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: ValidAccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        let token_id = env::predecessor_account_id();
+        let rate = match self.payment_tokens.get(&token_id) {
+            Some(rate) if msg.is_empty() => rate,
+            _ => return PromiseOrValue::Value(amount),
+        };
+        self.assert_not_banned(sender_id.as_ref());
+        let deposit = rate.to_yocto_near(amount.0);
+        self.internal_storage_deposit(sender_id.as_ref(), deposit, "ft_payment");
+        let initial_storage_usage = env::storage_usage();
+        let balance = self.payment_token_balances.get(&token_id).unwrap_or(0);
+        self.payment_token_balances
+            .insert(&token_id, &(balance + amount.0));
+        self.internal_settle_storage(sender_id.as_ref(), initial_storage_usage);
+        PromiseOrValue::Value(0.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::test_utils::{accounts, get_created_receipts};
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    const GRACE_PERIOD_SEC: u64 = 60;
+    const CREATION_DEPOSIT: Balance = 10_000_000_000_000_000_000_000_000;
+
+    fn deposit(factory: &mut TokenFactory, account_id: ValidAccountId, amount: Balance) {
+        testing_env!(context(account_id).attached_deposit(amount).build());
+        factory.storage_deposit();
+    }
+
+    #[test]
+    fn test_storage_deposit_registers_then_tops_up() {
+        let mut factory = setup_factory();
+        let registration_cost = factory.get_storage_balance_cost();
+        deposit(&mut factory, accounts(1), registration_cost + 100);
+        let balance = factory.storage_balance_of(accounts(1)).unwrap();
+        assert_eq!(balance.available.0, 100);
+        assert_eq!(balance.total.0, registration_cost + 100);
+
+        deposit(&mut factory, accounts(1), 50);
+        let balance = factory.storage_balance_of(accounts(1)).unwrap();
+        assert_eq!(balance.available.0, 150);
+        assert_eq!(
+            factory.get_storage_accounts(0, None),
+            vec![(accounts(1).as_ref().clone(), U128(150))]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Deposit is too low")]
+    fn test_storage_deposit_below_the_registration() {
+        let mut factory = setup_factory();
+        let registration_cost = factory.get_storage_balance_cost();
+        deposit(&mut factory, accounts(1), registration_cost - 1);
+    }
+
+    #[test]
+    fn test_required_deposit_adds_the_registration() {
+        let mut factory = setup_factory();
+        let args = token_args(accounts(1), "ABC");
+        let unregistered = factory.get_required_deposit(args.clone(), accounts(1), None, None);
+        assert_eq!(
+            unregistered.account_registration.0,
+            factory.get_storage_balance_cost()
+        );
+
+        deposit(&mut factory, accounts(1), unregistered.total.0);
+        let registered = factory.get_required_deposit(args, accounts(1), None, None);
+        assert_eq!(registered.account_registration.0, 0);
+        assert_eq!(registered.total.0, 0);
+    }
+
+    #[test]
+    fn test_record_bytes_estimate_matches_the_created_record() {
+        let mut factory = setup_factory();
+        let args = token_args(accounts(1), "ABC");
+        let public_key =
+            Base58PublicKey::try_from("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp")
+                .unwrap();
+        let estimate = factory
+            .estimate_record_bytes(args.clone(), accounts(1), Some(public_key.clone()))
+            .0;
+
+        testing_env!(context(accounts(1))
+            .attached_deposit(CREATION_DEPOSIT)
+            .build());
+        drop(factory.create_token(args, Some(public_key), None, None, None, None, None));
+        testing_env!(
+            context(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        assert!(factory.on_create_token("abc".to_string()));
+
+        // The finished record, with its creation stats, is inserted again on its own.
+        let token_id = "abc".to_string();
+        let record = factory.tokens.remove(&token_id).unwrap();
+        assert!(record.creation_stats.is_some());
+        let initial_storage_usage = env::storage_usage();
+        factory.tokens.insert(&token_id, &record);
+        assert_eq!(env::storage_usage() - initial_storage_usage, estimate);
+    }
+
+    /// Reclaims the recent deposits of `accounts(1)` at `timestamp`, checking that `amount` is
+    /// transferred back. The receipt is matched in its JSON form, as its fields aren't public
+    /// and `serde_json::Value` can't hold the u128 deposit.
+    fn reclaim(factory: &mut TokenFactory, timestamp: u64, amount: Balance) {
+        testing_env!(context(accounts(1)).block_timestamp(timestamp).build());
+        drop(factory.reclaim_recent_deposit());
+        let receipts = get_created_receipts();
+        assert_eq!(receipts.len(), 1);
+        let receipt = serde_json::to_string(&receipts[0]).unwrap();
+        assert!(receipt.contains(&format!(r#""receiver_id":"{}""#, accounts(1))));
+        assert!(receipt.contains(&format!(r#"{{"Transfer":{{"deposit":{}}}}}"#, amount)));
+    }
+
+    #[test]
+    fn test_reclaim_unregisters_the_account() {
+        let mut factory = setup_factory();
+        factory.config.deposit_grace_period_sec = Some(GRACE_PERIOD_SEC);
+        let total_storage_balances = factory.total_storage_balances;
+        let amount = factory.get_storage_balance_cost() + 100;
+        deposit(&mut factory, accounts(1), amount);
+        assert_eq!(factory.total_storage_balances, total_storage_balances + 100);
+
+        reclaim(&mut factory, 0, amount);
+        assert!(factory.storage_balance_of(accounts(1)).is_none());
+        assert!(factory.get_storage_accounts(0, None).is_empty());
+        assert_eq!(factory.total_storage_balances, total_storage_balances);
+        assert!(factory.recent_deposits.get(accounts(1).as_ref()).is_none());
+    }
+
+    #[test]
+    fn test_reclaim_keeps_an_earlier_registration() {
+        let mut factory = setup_factory();
+        factory.config.deposit_grace_period_sec = Some(GRACE_PERIOD_SEC);
+        let total_storage_balances = factory.total_storage_balances;
+        let registration_cost = factory.get_storage_balance_cost();
+        deposit(&mut factory, accounts(1), registration_cost + 100);
+
+        // The registration can't be reclaimed anymore, only the top up.
+        let timestamp = (GRACE_PERIOD_SEC + 1) * 1_000_000_000;
+        testing_env!(context(accounts(1))
+            .attached_deposit(50)
+            .block_timestamp(timestamp)
+            .build());
+        factory.storage_deposit();
+        reclaim(&mut factory, timestamp, 50);
+        let balance = factory.storage_balance_of(accounts(1)).unwrap();
+        assert_eq!(balance.available.0, 100);
+        assert_eq!(factory.total_storage_balances, total_storage_balances + 100);
+    }
+
+    /// Rate of 1 yoctoNEAR for every unit of the payment token.
+    fn whitelist_payment_token(factory: &mut TokenFactory, token_id: ValidAccountId) {
+        testing_env!(context(accounts(0)).build());
+        factory.set_payment_token(
+            token_id,
+            Some(PaymentTokenRate {
+                yocto_near: 1.into(),
+                token_amount: 1.into(),
+            }),
+        );
+    }
+
+    fn pay(
+        factory: &mut TokenFactory,
+        token_id: ValidAccountId,
+        amount: Balance,
+        msg: &str,
+    ) -> Balance {
+        testing_env!(context(token_id).build());
+        match factory.ft_on_transfer(accounts(1), amount.into(), msg.to_string()) {
+            PromiseOrValue::Value(unused) => unused.0,
+            PromiseOrValue::Promise(_) => panic!("Payment resolved to a promise"),
+        }
+    }
+
+    #[test]
+    fn test_whitelisted_payment_token_is_credited() {
+        let mut factory = setup_factory();
+        whitelist_payment_token(&mut factory, accounts(3));
+        assert_eq!(pay(&mut factory, accounts(3), CREATION_DEPOSIT, ""), 0);
+        assert_eq!(
+            factory.get_payment_token_balance(accounts(3)).0,
+            CREATION_DEPOSIT
+        );
+        // The balance entry of the payment token was charged to the first payment.
+        let total = factory.storage_balance_of(accounts(1)).unwrap().total.0;
+        assert!(total < CREATION_DEPOSIT);
+        assert!(CREATION_DEPOSIT - total < 200 * STORAGE_PRICE_PER_BYTE);
+
+        assert_eq!(pay(&mut factory, accounts(3), CREATION_DEPOSIT, ""), 0);
+        assert_eq!(
+            factory.storage_balance_of(accounts(1)).unwrap().total.0,
+            total + CREATION_DEPOSIT
+        );
+        assert_eq!(
+            factory.get_payment_token_balance(accounts(3)).0,
+            2 * CREATION_DEPOSIT
+        );
+    }
+
+    #[test]
+    fn test_unknown_payment_token_is_refunded() {
+        let mut factory = setup_factory();
+        whitelist_payment_token(&mut factory, accounts(3));
+        assert_eq!(
+            pay(&mut factory, accounts(4), CREATION_DEPOSIT, ""),
+            CREATION_DEPOSIT
+        );
+        // Only transfers without a `msg` pay for storage.
+        assert_eq!(
+            pay(&mut factory, accounts(3), CREATION_DEPOSIT, "create"),
+            CREATION_DEPOSIT
+        );
+        assert!(factory.storage_balance_of(accounts(1)).is_none());
+        assert_eq!(factory.get_payment_token_balance(accounts(4)).0, 0);
+        assert_eq!(factory.get_payment_token_balance(accounts(3)).0, 0);
+    }
+}
@@ -0,0 +1,76 @@
+//! Helpers shared by the unit tests of the factory modules.
+use crate::*;
+use near_contract_standards::fungible_token::metadata::FT_METADATA_SPEC;
+use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+use near_sdk::{testing_env, MockedBlockchain};
+
+/// Context of a call to the factory at `accounts(0)`, signed and sent by `predecessor`.
+pub(crate) fn context(predecessor: ValidAccountId) -> VMContextBuilder {
+    let mut builder = VMContextBuilder::new();
+    builder
+        .current_account_id(accounts(0))
+        .signer_account_id(predecessor.clone())
+        .predecessor_account_id(predecessor);
+    builder
+}
+
+/// Factory at `accounts(0)` owned by itself, with the default config. The storage of the
+/// previous test on the thread is dropped first, since `testing_env!` carries it over.
+pub(crate) fn setup_factory() -> TokenFactory {
+    env::take_blockchain_interface();
+    testing_env!(context(accounts(0)).build());
+    TokenFactory::new(accounts(0))
+}
+
+pub(crate) fn token_metadata(symbol: &str) -> FungibleTokenMetadata {
+    FungibleTokenMetadata {
+        spec: FT_METADATA_SPEC.to_string(),
+        name: format!("{} Token", symbol),
+        symbol: symbol.to_string(),
+        icon: None,
+        reference: None,
+        reference_hash: None,
+        decimals: 18,
+    }
+}
+
+pub(crate) fn token_args(owner_id: ValidAccountId, symbol: &str) -> TokenArgs {
+    TokenArgs::new(owner_id, U128(1_000_000), token_metadata(symbol))
+}
+
+/// Adds the token to the registry and its indexes without deploying it, like `dev_seed_tokens`.
+pub(crate) fn seed_token(factory: &mut TokenFactory, args: TokenArgs) -> TokenId {
+    let token_id = args.metadata.symbol.to_ascii_lowercase();
+    factory.internal_add_to_symbol_index(&args.metadata.symbol, &token_id);
+    factory.internal_add_to_name_index(&args.metadata.name);
+    factory.internal_add_to_tag_index(&args.tags, &token_id);
+    factory.internal_add_to_owner_index(args.owner_id.as_ref(), &token_id);
+    factory.internal_add_to_campaign(args.campaign.as_ref(), &token_id);
+    let fee_on_transfer = args.has_transfer_fee();
+    factory.tokens.insert(
+        &token_id,
+        &TokenRecord {
+            args,
+            creator_id: factory.owner_id.clone(),
+            frozen: false,
+            creation_stats: None,
+            last_seen_ok: None,
+            unresponsive: false,
+            full_access_key: None,
+            detached_account_id: None,
+            fee_on_transfer,
+            created_at: Some(env::block_timestamp().into()),
+        },
+    );
+    token_id
+}
+
+/// Whether one of the receipts created so far transfers `amount` to `receiver_id`. The receipts
+/// are matched in their JSON form, as `serde_json::Value` can't hold the u128 deposits.
+pub(crate) fn transfers(receiver_id: ValidAccountId, amount: Balance) -> bool {
+    get_created_receipts().iter().any(|receipt| {
+        let receipt = serde_json::to_string(receipt).unwrap();
+        receipt.contains(&format!(r#""receiver_id":"{}""#, receiver_id))
+            && receipt.contains(&format!(r#"{{"Transfer":{{"deposit":{}}}}}"#, amount))
+    })
+}
@@ -0,0 +1,1013 @@
+//! Read-only methods of the registry and the configuration.
+use crate::*;
+
+/// Measures the records added to a page against `MAX_PAGE_BYTES`.
+#[derive(Default)]
+struct PageBudget {
+    bytes: u64,
+}
+
+impl PageBudget {
+    /// Whether the record still fits into the page. The first record always does, so a page
+    /// never ends up empty before the end of the list.
+    fn fits(&mut self, token: &TokenRecord) -> bool {
+        let first = self.bytes == 0;
+        self.bytes += token.try_to_vec().unwrap().len() as u64;
+        first || self.bytes <= MAX_PAGE_BYTES
+    }
+}
+
+/// Formats a raw token amount as a decimal string with the given decimals, without trailing
+/// zeros in the fraction, e.g. `1500000` with 6 decimals as `1.5`.
+fn format_amount(amount: Balance, decimals: u8) -> String {
+    let digits = amount.to_string();
+    let decimals = usize::from(decimals);
+    if decimals == 0 {
+        return digits;
+    }
+    let digits = format!("{:0>width$}", digits, width = decimals + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - decimals);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        whole.to_string()
+    } else {
+        format!("{}.{}", whole, fraction)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenSecurityInfo {
+    pub token_account_id: AccountId,
+    pub full_access_key: Option<Base58PublicKey>,
+    /// Whether a key can still replace the token code, rather than the account being keyless.
+    pub key_controlled: bool,
+    pub fee_on_transfer: bool,
+}
+
+/// Monotonic counters of the token deployments, next to the number of registered tokens.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenCounters {
+    pub total_created: U64,
+    pub total_failed: U64,
+    /// Tokens removed from the registry by `archive_tokens`.
+    pub total_retired: U64,
+    pub registered: U64,
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TrustAttestation {
+    pub attester_id: AccountId,
+    /// SHA-256 hash of the code deployed to the factory account.
+    pub code_hash: Base64VecU8,
+    /// SHA-256 hash of the published release build of the factory.
+    pub release_code_hash: Base64VecU8,
+    pub full_access_keys: u32,
+    pub attested_at: U64,
+}
+
+/// Signals for grading how far creators have to trust the factory operator.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TrustReport {
+    pub owner_id: AccountId,
+    /// Whether the attested code is the published release, unknown without an attestation.
+    pub code_matches_release: Option<bool>,
+    /// Whether someone holding a key can replace the factory code, unknown without an
+    /// attestation.
+    pub has_full_access_keys: Option<bool>,
+    /// The attestation the answers are based on. An upgrade of the factory code leaves it in
+    /// place, so it has to be judged by its age.
+    pub attestation: Option<TrustAttestation>,
+    /// Accounts holding admin roles next to the owner, with their roles.
+    pub roles: Vec<(AccountId, Vec<Role>)>,
+    /// Accounts allowed to create tokens for other accounts.
+    pub trusted_integrators: u64,
+    pub approval_required: bool,
+    pub deprecated: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum EscrowStage {
+    /// Waiting for a reviewer to approve or reject the token.
+    Review,
+    /// Waiting in the creation queue for `process_queue`.
+    Queued,
+    /// Waiting for the probe of the token account in the strict mode.
+    Probing,
+    CreatingAccount,
+    Deploying,
+    /// The code deployment failed and waits for `retry_deployment`.
+    DeployFailed,
+}
+
+/// Balance held by the factory for a token that isn't deployed yet. Pending creations don't
+/// expire: a rejected or failed creation refunds the escrow to the payer's storage balance,
+/// and a failed code deployment waits for `retry_deployment`.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Escrow {
+    pub stage: EscrowStage,
+    pub creator_id: AccountId,
+    pub payer_id: AccountId,
+    /// Balance taken from the payer's storage balance.
+    pub amount: U128,
+    /// Part of the amount collected as the creation fee once the token is deployed.
+    pub fee: U128,
+}
+
+/// Page of tokens as columns of primitive values, one entry per token in each, for analytics
+/// that don't need the nested metadata.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokensFlat {
+    pub token_id: Vec<TokenId>,
+    pub account: Vec<AccountId>,
+    pub owner: Vec<AccountId>,
+    pub supply: Vec<U128>,
+    pub decimals: Vec<u8>,
+    pub created_at: Vec<Option<U64>>,
+}
+
+/// Token in the shape of an entry of the de facto token list format.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenListEntry {
+    pub address: AccountId,
+    pub symbol: String,
+    pub decimals: u8,
+    pub name: String,
+    #[serde(rename = "logoURI", skip_serializing_if = "Option::is_none")]
+    pub logo_uri: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AggregateCreationStats {
+    pub tokens: u64,
+    pub total: CreationStats,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DecimalsBounds {
+    pub min_decimals: u8,
+    pub max_decimals: u8,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeStats {
+    pub fees_collected: U128,
+    pub fees_withdrawn: U128,
+    pub referral_payouts: U128,
+    pub available: U128,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RegistryRoot {
+    pub root: Base64VecU8,
+    pub leaf_count: u64,
+}
+
+#[near_bindgen]
+impl TokenFactory {
+    pub fn get_token_code_info(&self) -> TokenCodeInfo {
+        self.token_code_info.clone()
+    }
+
+    pub fn get_campaign(&self, name: String) -> Option<Campaign> {
+        self.campaigns.get(&name)
+    }
+
+    pub fn get_campaigns(&self, from_index: u64, limit: Option<u64>) -> Vec<(String, Campaign)> {
+        let keys = self.campaigns.keys_as_vector();
+        let values = self.campaigns.values_as_vector();
+        page_range(from_index, limit, keys.len())
+            .map(|index| (keys.get(index).unwrap(), values.get(index).unwrap()))
+            .collect()
+    }
+
+    pub fn get_tokens_by_campaign(
+        &self,
+        name: String,
+        from_index: u64,
+        limit: Option<u64>,
+    ) -> Vec<VersionedTokenRecord> {
+        self.internal_get_indexed_tokens(self.tokens_by_campaign.get(&name), from_index, limit)
+    }
+
+    pub fn get_templates(&self, from_index: u64, limit: Option<u64>) -> Vec<(String, Template)> {
+        let keys = self.templates.keys_as_vector();
+        let values = self.templates.values_as_vector();
+        page_range(from_index, limit, keys.len())
+            .map(|index| (keys.get(index).unwrap(), values.get(index).unwrap()))
+            .collect()
+    }
+
+    /// Returns the JSON schema of the init args the template expects, so frontends can render
+    /// the launch form of the template.
+    pub fn get_template_schema(&self, template_name: String) -> Option<serde_json::Value> {
+        let init_schema = if template_name == DEFAULT_TEMPLATE {
+            DEFAULT_TEMPLATE_SCHEMA.to_string()
+        } else {
+            self.templates.get(&template_name)?.init_schema
+        };
+        serde_json::from_str(&init_schema).ok()
+    }
+
+    /// Returns the JSON schema of the manifest taken by `create_from_manifest`.
+    pub fn get_launch_manifest_schema(&self) -> serde_json::Value {
+        serde_json::from_str(LAUNCH_MANIFEST_SCHEMA).unwrap()
+    }
+
+    pub fn get_limits(&self) -> Limits {
+        self.limits.clone()
+    }
+
+    pub fn get_owner(&self) -> AccountId {
+        self.owner_id.clone()
+    }
+
+    pub fn get_config(&self) -> Config {
+        self.config.clone()
+    }
+
+    pub fn get_fee_stats(&self) -> FeeStats {
+        FeeStats {
+            fees_collected: self.fees_collected.into(),
+            fees_withdrawn: self.fees_withdrawn.into(),
+            referral_payouts: self.total_referral_payouts.into(),
+            available: self.get_available_fees().into(),
+        }
+    }
+
+    pub fn get_referral_payouts(&self, account_id: ValidAccountId) -> U128 {
+        self.referral_payouts
+            .get(account_id.as_ref())
+            .unwrap_or(0)
+            .into()
+    }
+
+    /// Returns the balance the owner can withdraw with `withdraw_excess_balance`.
+    pub fn get_excess_balance(&self) -> U128 {
+        let locked = pricing::storage_cost(env::storage_usage(), STORAGE_PRICE_PER_BYTE)
+            + self.total_storage_balances
+            + self.pending_escrow
+            + self.prelaunch_funds
+            + self.get_available_fees()
+            + EXCESS_BALANCE_BUFFER;
+        env::account_balance().saturating_sub(locked).into()
+    }
+
+    pub fn get_usd_price(&self) -> Option<CachedUsdPrice> {
+        self.usd_price.clone()
+    }
+
+    pub fn get_roles(&self, account_id: ValidAccountId) -> Vec<Role> {
+        self.roles.get(account_id.as_ref()).unwrap_or_default()
+    }
+
+    pub fn has_role(&self, account_id: ValidAccountId, role: Role) -> bool {
+        self.internal_has_role(account_id.as_ref(), &role)
+    }
+
+    /// Returns the progress of the token record migration, or `None` if none is running.
+    pub fn get_migration(&self) -> Option<Migration> {
+        self.migration.clone()
+    }
+
+    /// Aggregates what wallets need to grade the trustworthiness of launches through the
+    /// factory. The code hash and the access keys can't be read on chain, so they are taken from
+    /// the last `attest_trust`. At most `MAX_PAGE_SIZE` role holders are listed.
+    pub fn get_trust_report(&self) -> TrustReport {
+        let attestation = self.trust_attestation.clone();
+        TrustReport {
+            owner_id: self.owner_id.clone(),
+            code_matches_release: attestation
+                .as_ref()
+                .map(|attestation| attestation.code_hash.0 == attestation.release_code_hash.0),
+            has_full_access_keys: attestation
+                .as_ref()
+                .map(|attestation| attestation.full_access_keys > 0),
+            attestation,
+            roles: self.roles.iter().take(MAX_PAGE_SIZE as usize).collect(),
+            trusted_integrators: self.trusted_integrators.len(),
+            approval_required: self.config.approval_required,
+            deprecated: self.config.deprecation.is_some(),
+        }
+    }
+
+    pub fn is_banned(&self, account_id: ValidAccountId) -> bool {
+        self.banned_accounts.contains(account_id.as_ref())
+    }
+
+    pub fn get_banned_accounts(&self, from_index: u64, limit: Option<u64>) -> Vec<AccountId> {
+        let accounts = self.banned_accounts.as_vector();
+        page_range(from_index, limit, accounts.len())
+            .filter_map(|index| accounts.get(index))
+            .collect()
+    }
+
+    pub fn get_creator_stats(&self, account_id: ValidAccountId) -> CreatorStats {
+        self.creator_stats
+            .get(account_id.as_ref())
+            .unwrap_or_default()
+    }
+
+    pub fn get_top_creators(&self, limit: Option<u64>) -> Vec<(AccountId, u64)> {
+        let limit = limit.map_or(MAX_TOP_CREATORS, |limit| limit as usize);
+        let mut top_creators = self.top_creators.get().unwrap();
+        top_creators.truncate(limit);
+        top_creators
+    }
+
+    pub fn get_payment_tokens(&self) -> Vec<(AccountId, PaymentTokenRate)> {
+        self.payment_tokens.to_vec()
+    }
+
+    pub fn get_fee_discounts(&self) -> Vec<(String, FeeDiscount)> {
+        self.fee_discounts.to_vec()
+    }
+
+    pub fn get_payment_token_balance(&self, token_id: ValidAccountId) -> U128 {
+        self.payment_token_balances
+            .get(token_id.as_ref())
+            .unwrap_or(0)
+            .into()
+    }
+
+    /// Returns the range of decimals accepted for new tokens.
+    pub fn get_decimals_bounds(&self) -> DecimalsBounds {
+        DecimalsBounds {
+            min_decimals: self.config.min_decimals,
+            max_decimals: self.config.max_decimals,
+        }
+    }
+
+    pub fn get_number_of_tokens(&self) -> u64 {
+        self.tokens.len()
+    }
+
+    /// Frozen tokens are skipped unless `include_frozen` is set, so a page can contain fewer
+    /// tokens than the `limit`. A page also ends early once it holds `MAX_PAGE_BYTES`.
+    pub fn get_tokens(
+        &self,
+        from_index: u64,
+        limit: Option<u64>,
+        include_frozen: Option<bool>,
+    ) -> Vec<VersionedTokenRecord> {
+        self.assert_not_migrating();
+        let include_frozen = include_frozen.unwrap_or(false);
+        let tokens = self.tokens.values_as_vector();
+        let mut budget = PageBudget::default();
+        page_range(from_index, limit, tokens.len())
+            .filter_map(|index| tokens.get(index))
+            .filter(|token| include_frozen || !token.frozen)
+            .take_while(|token| budget.fits(token))
+            .map(VersionedTokenRecord::from)
+            .collect()
+    }
+
+    /// Same page as `get_tokens`, flattened into columns.
+    pub fn get_tokens_flat(
+        &self,
+        from_index: u64,
+        limit: Option<u64>,
+        include_frozen: Option<bool>,
+    ) -> TokensFlat {
+        self.assert_not_migrating();
+        let include_frozen = include_frozen.unwrap_or(false);
+        let keys = self.tokens.keys_as_vector();
+        let values = self.tokens.values_as_vector();
+        let mut flat = TokensFlat::default();
+        for index in page_range(from_index, limit, keys.len()) {
+            let (token_id, token) = match (keys.get(index), values.get(index)) {
+                (Some(token_id), Some(token)) => (token_id, token),
+                _ => continue,
+            };
+            if token.frozen && !include_frozen {
+                continue;
+            }
+            flat.account
+                .push(self.internal_token_account_id(&token_id, &token));
+            flat.token_id.push(token_id);
+            flat.owner.push(token.args.owner_id.into());
+            flat.supply.push(token.args.total_supply);
+            flat.decimals.push(token.args.metadata.decimals);
+            flat.created_at.push(token.created_at);
+        }
+        flat
+    }
+
+    pub fn get_token(&self, token_id: TokenId) -> Option<VersionedTokenRecord> {
+        self.assert_not_migrating();
+        self.tokens.get(&token_id).map(VersionedTokenRecord::from)
+    }
+
+    /// Returns the total supply the token was created with, in its smallest units.
+    pub fn get_raw_supply(&self, token_id: TokenId) -> Option<U128> {
+        self.assert_not_migrating();
+        self.tokens
+            .get(&token_id)
+            .map(|token| token.args.total_supply)
+    }
+
+    /// Returns the total supply the token was created with, adjusted by its decimals as a
+    /// decimal string. The conversion is done on the integer digits, so it is exact for any
+    /// supply and decimals, unlike a conversion through floating point numbers.
+    pub fn get_human_supply(&self, token_id: TokenId) -> Option<String> {
+        self.assert_not_migrating();
+        let token = self.tokens.get(&token_id)?;
+        Some(format_amount(
+            token.args.total_supply.0,
+            token.args.metadata.decimals,
+        ))
+    }
+
+    /// Returns the highest holder milestone the token reported about itself. It is not verified
+    /// by the factory, see `on_holder_milestone`.
+    pub fn get_holder_milestone(&self, token_id: TokenId) -> Option<HolderMilestone> {
+        self.holder_milestones.get(&token_id)
+    }
+
+    /// Returns the localization of the token for the language tag, falling back to the primary
+    /// language of a regional tag, e.g. from `pt-br` to `pt`.
+    pub fn get_token_localization(
+        &self,
+        token_id: TokenId,
+        language: String,
+    ) -> Option<Localization> {
+        self.assert_not_migrating();
+        let mut localizations = self.tokens.get(&token_id)?.args.localizations;
+        let language = language.to_ascii_lowercase();
+        let primary_language = language.split('-').next().unwrap().to_string();
+        localizations
+            .remove(&language)
+            .or_else(|| localizations.remove(&primary_language))
+    }
+
+    /// Returns the accounts the creator authorized to manage the token.
+    pub fn get_token_managers(&self, token_id: TokenId) -> Vec<AccountId> {
+        self.token_managers.get(&token_id).unwrap_or_default()
+    }
+
+    pub fn get_social_profile(&self, account_id: ValidAccountId) -> Option<SocialProfile> {
+        self.social_profiles.get(account_id.as_ref())
+    }
+
+    /// Returns the linked near.social profile of the creator of the token, for showing
+    /// "launched by @handle".
+    pub fn get_token_creator_profile(&self, token_id: TokenId) -> Option<SocialProfile> {
+        self.assert_not_migrating();
+        let token = self.tokens.get(&token_id)?;
+        self.social_profiles.get(&token.creator_id)
+    }
+
+    pub fn get_reference_refresh(&self, token_id: TokenId) -> Option<ReferenceRefresh> {
+        self.reference_refreshes.get(&token_id)
+    }
+
+    pub fn get_token_security_info(&self, token_id: TokenId) -> Option<TokenSecurityInfo> {
+        self.assert_not_migrating();
+        let token = self.tokens.get(&token_id)?;
+        Some(TokenSecurityInfo {
+            token_account_id: self.internal_token_account_id(&token_id, &token),
+            key_controlled: token.full_access_key.is_some(),
+            full_access_key: token.full_access_key,
+            fee_on_transfer: token.fee_on_transfer,
+        })
+    }
+
+    pub fn get_tokenlist_entry(&self, token_id: TokenId) -> Option<TokenListEntry> {
+        self.assert_not_migrating();
+        let token = self.tokens.get(&token_id)?;
+        let address = self.internal_token_account_id(&token_id, &token);
+        let metadata = token.args.metadata;
+        Some(TokenListEntry {
+            address,
+            symbol: metadata.symbol,
+            decimals: metadata.decimals,
+            name: metadata.name,
+            logo_uri: metadata.icon,
+        })
+    }
+
+    /// Resolves several tokens at once, in the order of the given ids. At most `MAX_PAGE_SIZE`
+    /// ids can be passed, and the records have to fit into `MAX_PAGE_BYTES`, unless a single
+    /// one is requested.
+    pub fn get_tokens_by_ids(&self, token_ids: Vec<TokenId>) -> Vec<Option<VersionedTokenRecord>> {
+        self.assert_not_migrating();
+        assert!(
+            token_ids.len() as u64 <= MAX_PAGE_SIZE,
+            "Can't get more than {} tokens at once",
+            MAX_PAGE_SIZE
+        );
+        let mut budget = PageBudget::default();
+        token_ids
+            .iter()
+            .map(|token_id| {
+                self.tokens.get(token_id).map(|token| {
+                    assert!(
+                        budget.fits(&token),
+                        "Tokens don't fit into {} bytes, request fewer at once",
+                        MAX_PAGE_BYTES
+                    );
+                    VersionedTokenRecord::from(token)
+                })
+            })
+            .collect()
+    }
+
+    pub fn get_project(&self, project: String) -> Option<Project> {
+        self.projects.get(&project)
+    }
+
+    pub fn get_projects(&self, from_index: u64, limit: Option<u64>) -> Vec<(String, Project)> {
+        let keys = self.projects.keys_as_vector();
+        let values = self.projects.values_as_vector();
+        page_range(from_index, limit, keys.len())
+            .map(|index| (keys.get(index).unwrap(), values.get(index).unwrap()))
+            .collect()
+    }
+
+    /// Whether the account `symbol.factory` is free as far as the registry knows, counting
+    /// tokens, pending creations and projects. Use `probe_account` to also catch accounts
+    /// created outside of the factory.
+    pub fn is_account_available(&self, symbol: String) -> bool {
+        self.internal_is_token_id_free(&symbol.to_ascii_lowercase())
+    }
+
+    /// Checks whether a token with the given symbol can be created and which account it would
+    /// get. In the counter mode the symbol is not unique and the next counter id is returned.
+    pub fn validate_symbol(&self, symbol: String) -> SymbolValidation {
+        match self.config.token_id_mode {
+            TokenIdMode::Symbol => self.internal_validate_symbol(&symbol),
+            TokenIdMode::Counter => {
+                let token_id = format!("t{}", self.token_counter + 1);
+                SymbolValidation::Valid {
+                    token_account_id: self.get_token_account_id(&token_id),
+                    token_id,
+                }
+            }
+        }
+    }
+
+    /// Returns the account id a token with the given id is deployed to.
+    pub fn derive_token_account_id(&self, token_id: TokenId) -> AccountId {
+        self.get_token_account_id(&token_id)
+    }
+
+    pub fn get_reserved_symbols(&self, from_index: u64, limit: Option<u64>) -> Vec<String> {
+        let symbols = self.reserved_symbols.as_vector();
+        page_range(from_index, limit, symbols.len())
+            .filter_map(|index| symbols.get(index))
+            .collect()
+    }
+
+    pub fn get_retired_symbols(&self, from_index: u64, limit: Option<u64>) -> Vec<String> {
+        let symbols = self.retired_symbols.as_vector();
+        page_range(from_index, limit, symbols.len())
+            .filter_map(|index| symbols.get(index))
+            .collect()
+    }
+
+    pub fn get_similarity_references(&self, from_index: u64, limit: Option<u64>) -> Vec<String> {
+        let references = self.similarity_references.as_vector();
+        page_range(from_index, limit, references.len())
+            .filter_map(|index| references.get(index))
+            .collect()
+    }
+
+    /// Returns the NEAR amounts the creation of the token consumed, for reconciling launch costs.
+    pub fn get_creation_receipt(&self, token_id: TokenId) -> Option<CreationReceipt> {
+        self.creation_receipts.get(&token_id)
+    }
+
+    /// Returns the similarity reference the token was flagged as similar to at its creation.
+    pub fn get_token_similarity(&self, token_id: TokenId) -> Option<SimilarityFlag> {
+        self.similarity_flags.get(&token_id)
+    }
+
+    pub fn is_symbol_retired(&self, symbol: String) -> bool {
+        self.retired_symbols.contains(&symbol.to_ascii_lowercase())
+    }
+
+    pub fn get_denied_domains(&self, from_index: u64, limit: Option<u64>) -> Vec<String> {
+        let domains = self.denied_domains.as_vector();
+        page_range(from_index, limit, domains.len())
+            .filter_map(|index| domains.get(index))
+            .collect()
+    }
+
+    /// Returns the first denied domain the `reference` or the icon of the metadata point to.
+    pub fn check_metadata_domains(&self, metadata: FungibleTokenMetadata) -> Option<String> {
+        self.internal_check_metadata_domains(&metadata)
+    }
+
+    pub fn get_blocked_names(&self, from_index: u64, limit: Option<u64>) -> Vec<String> {
+        let names = self.blocked_names.as_vector();
+        page_range(from_index, limit, names.len())
+            .filter_map(|index| names.get(index))
+            .collect()
+    }
+
+    /// Checks whether a token can be created with the given name. The name is compared after
+    /// normalization, so e.g. "Wrapped NEAR" and "wrapped-near" are the same name.
+    pub fn is_name_available(&self, name: String) -> bool {
+        let name = normalize_name(&name);
+        !(self.blocked_names.contains(&name)
+            || (self.config.unique_names && self.token_names.get(&name).is_some()))
+    }
+
+    pub fn get_tokens_by_tag(
+        &self,
+        tag: String,
+        from_index: u64,
+        limit: Option<u64>,
+    ) -> Vec<VersionedTokenRecord> {
+        self.internal_get_indexed_tokens(self.tokens_by_tag.get(&tag), from_index, limit)
+    }
+
+    /// Page of the tokens in the set of a per-key index, which ends early once it holds
+    /// `MAX_PAGE_BYTES`.
+    fn internal_get_indexed_tokens(
+        &self,
+        token_ids: Option<UnorderedSet<TokenId>>,
+        from_index: u64,
+        limit: Option<u64>,
+    ) -> Vec<VersionedTokenRecord> {
+        self.assert_not_migrating();
+        let token_ids = match token_ids {
+            Some(token_ids) => token_ids,
+            None => return vec![],
+        };
+        let token_ids = token_ids.as_vector();
+        let mut budget = PageBudget::default();
+        page_range(from_index, limit, token_ids.len())
+            .filter_map(|index| token_ids.get(index))
+            .filter_map(|token_id| self.tokens.get(&token_id))
+            .take_while(|token| budget.fits(token))
+            .map(VersionedTokenRecord::from)
+            .collect()
+    }
+
+    pub fn get_tokens_by_owner(
+        &self,
+        owner_id: ValidAccountId,
+        from_index: u64,
+        limit: Option<u64>,
+    ) -> Vec<VersionedTokenRecord> {
+        self.internal_get_indexed_tokens(
+            self.tokens_by_owner.get(owner_id.as_ref()),
+            from_index,
+            limit,
+        )
+    }
+
+    pub fn get_archived_token(&self, token_id: TokenId) -> Option<ArchivedToken> {
+        self.archived_tokens.get(&token_id)
+    }
+
+    pub fn get_number_of_archived_tokens(&self) -> u64 {
+        self.archived_token_count
+    }
+
+    pub fn get_token_counters(&self) -> TokenCounters {
+        TokenCounters {
+            total_created: self.total_created.into(),
+            total_failed: self.total_failed.into(),
+            total_retired: self.archived_token_count.into(),
+            registered: self.tokens.len().into(),
+        }
+    }
+
+    /// Returns ids of the tokens registered with the given symbol, compared case-insensitively.
+    /// At most `MAX_PAGE_SIZE` ids are returned.
+    pub fn get_tokens_by_symbol(&self, symbol: String) -> Vec<TokenId> {
+        self.assert_not_migrating();
+        self.tokens_by_symbol
+            .get(&symbol.to_ascii_lowercase())
+            .map_or_else(Vec::new, |token_ids| {
+                token_ids.iter().take(MAX_PAGE_SIZE as usize).collect()
+            })
+    }
+
+    pub fn get_number_of_proposals(&self) -> u64 {
+        self.proposals.len()
+    }
+
+    pub fn get_proposals(&self, from_index: u64, limit: Option<u64>) -> Vec<(TokenId, Proposal)> {
+        let keys = self.proposals.keys_as_vector();
+        let values = self.proposals.values_as_vector();
+        page_range(from_index, limit, keys.len())
+            .filter_map(|index| keys.get(index).zip(values.get(index)))
+            .collect()
+    }
+
+    pub fn get_proposal(&self, token_id: TokenId) -> Option<Proposal> {
+        self.proposals.get(&token_id)
+    }
+
+    pub fn get_icon_upload(
+        &self,
+        account_id: ValidAccountId,
+        upload_id: String,
+    ) -> Option<IconUploadInfo> {
+        self.icon_uploads
+            .get(&(account_id.into(), upload_id))
+            .map(|upload| IconUploadInfo {
+                length: upload.data.len() as u64,
+                finalized: upload.finalized,
+            })
+    }
+
+    /// Returns a chunk of the icon blob of the token. Its SHA-256 hash over all chunks is the
+    /// `reference_hash` of the token metadata.
+    pub fn get_icon(&self, token_id: TokenId, chunk: u64) -> Option<IconChunk> {
+        let chunks = self.icon_blobs.get(&token_id)?;
+        Some(IconChunk {
+            data: self.icon_chunks.get(&(token_id, chunk))?,
+            chunks,
+        })
+    }
+
+    /// Hash to pass to `commit_symbol`, which binds the symbol to the committing account.
+    pub fn get_symbol_commit_hash(
+        &self,
+        account_id: ValidAccountId,
+        symbol: String,
+        salt: String,
+    ) -> Base64VecU8 {
+        env::sha256(format!("{}:{}:{}", account_id.as_ref(), symbol, salt).as_bytes()).into()
+    }
+
+    pub fn get_symbol_commit(&self, hash: Base64VecU8) -> Option<SymbolCommit> {
+        self.symbol_commits.get(&hash.0)
+    }
+
+    pub fn get_trusted_integrators(&self) -> Vec<AccountId> {
+        self.trusted_integrators.to_vec()
+    }
+
+    pub fn get_preprovisioned_accounts(
+        &self,
+        from_index: u64,
+        limit: Option<u64>,
+    ) -> Vec<(TokenId, bool)> {
+        let keys = self.preprovisioned_accounts.keys_as_vector();
+        let values = self.preprovisioned_accounts.values_as_vector();
+        page_range(from_index, limit, keys.len())
+            .map(|index| (keys.get(index).unwrap(), values.get(index).unwrap()))
+            .collect()
+    }
+
+    pub fn get_creation_queue(
+        &self,
+        from_index: u64,
+        limit: Option<u64>,
+    ) -> Vec<(TokenId, QueuedCreation)> {
+        let keys = self.creation_queue.keys_as_vector();
+        let values = self.creation_queue.values_as_vector();
+        page_range(from_index, limit, keys.len())
+            .map(|index| (keys.get(index).unwrap(), values.get(index).unwrap()))
+            .collect()
+    }
+
+    pub fn get_prelaunch_pool(&self, token_id: TokenId) -> Option<PrelaunchPool> {
+        self.prelaunch_pools.get(&token_id)
+    }
+
+    /// Returns the contribution of the supporter to the prelaunch pool of the token, which is
+    /// kept once the pool is released.
+    pub fn get_prelaunch_contribution(
+        &self,
+        token_id: TokenId,
+        account_id: ValidAccountId,
+    ) -> Option<U128> {
+        self.prelaunch_contributions
+            .get(&(token_id, account_id.into()))
+            .map(|contribution| contribution.into())
+    }
+
+    pub fn get_deployment(&self, token_id: TokenId) -> Option<Deployment> {
+        self.deployments.get(&token_id)
+    }
+
+    /// Returns the balance locked for a pending creation, wherever it currently waits.
+    pub fn get_escrow(&self, token_id: TokenId) -> Option<Escrow> {
+        let (stage, proposal) = if let Some(proposal) = self.proposals.get(&token_id) {
+            (EscrowStage::Review, proposal)
+        } else if let Some(queued) = self.creation_queue.get(&token_id) {
+            (EscrowStage::Queued, queued.proposal)
+        } else {
+            let deployment = self.deployments.get(&token_id)?;
+            let stage = match deployment.status {
+                DeploymentStatus::CreatingAccount => EscrowStage::CreatingAccount,
+                DeploymentStatus::Deploying => EscrowStage::Deploying,
+                DeploymentStatus::DeployFailed => EscrowStage::DeployFailed,
+                DeploymentStatus::Probing => EscrowStage::Probing,
+            };
+            (stage, deployment.proposal)
+        };
+        Some(Escrow {
+            stage,
+            creator_id: proposal.creator_id,
+            payer_id: proposal.payer_id,
+            amount: proposal.escrow,
+            fee: proposal.fee,
+        })
+    }
+
+    /// Returns the resources consumed by all successful creations so far.
+    pub fn get_creation_stats(&self) -> AggregateCreationStats {
+        AggregateCreationStats {
+            tokens: self.creation_stats_count,
+            total: self.creation_stats_total.clone(),
+        }
+    }
+
+    /// Returns the number of deployed tokens and their summed supply for every template used.
+    pub fn get_template_stats(&self) -> Vec<(String, TemplateStats)> {
+        self.template_stats.to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use factory_types::{
+        MAX_LOCALIZATIONS, MAX_LOCALIZED_DESCRIPTION_LENGTH, MAX_LOCALIZED_NAME_LENGTH, MAX_TAGS,
+        MAX_TAG_LENGTH, MAX_WHITELIST_ACCOUNTS,
+    };
+    use near_sdk::test_utils::accounts;
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    /// Gas limit of view calls on the RPC nodes.
+    const VIEW_GAS: Gas = 200_000_000_000_000;
+
+    /// Context of a view call by `accounts(2)`. It isn't flagged as a view, since the mocked
+    /// blockchain doesn't report the used gas of views.
+    fn view_context() {
+        testing_env!(context(accounts(2)).prepaid_gas(VIEW_GAS).build());
+    }
+
+    /// Args of the largest record the factory accepts: an uploaded icon of `MAX_ICON_LENGTH`
+    /// bytes, the most and the longest tags and localizations, and a full whitelist.
+    fn worst_case_args(symbol: &str) -> TokenArgs {
+        let mut args = token_args(accounts(1), symbol);
+        args.metadata.icon = Some("i".repeat(MAX_ICON_LENGTH));
+        args.tags = (0..MAX_TAGS)
+            .map(|index| format!("{:t>width$}", index, width = MAX_TAG_LENGTH))
+            .collect();
+        args.localizations = (0..MAX_LOCALIZATIONS)
+            .map(|index| {
+                let localization = Localization {
+                    name: "n".repeat(MAX_LOCALIZED_NAME_LENGTH),
+                    description: Some("d".repeat(MAX_LOCALIZED_DESCRIPTION_LENGTH)),
+                };
+                (format!("{:0>8}", index), localization)
+            })
+            .collect();
+        args.whitelist = Some(SaleWhitelist::Accounts(
+            (0..MAX_WHITELIST_ACCOUNTS)
+                .map(|index| ValidAccountId::try_from(format!("{:0>64}", index)).unwrap())
+                .collect(),
+        ));
+        args
+    }
+
+    fn assert_versioned(records: Vec<VersionedTokenRecord>, symbols: &[&str]) {
+        let value = serde_json::to_value(&records).unwrap();
+        let records = value.as_array().unwrap();
+        assert_eq!(records.len(), symbols.len());
+        for (record, symbol) in records.iter().zip(symbols) {
+            assert_eq!(record["version"], 1);
+            assert_eq!(record.as_object().unwrap().len(), 2);
+            // The token args are flattened into the record.
+            assert_eq!(record["record"]["metadata"]["symbol"], *symbol);
+            assert_eq!(record["record"]["owner_id"], accounts(1).as_ref().as_str());
+            assert_eq!(
+                record["record"]["creator_id"],
+                accounts(0).as_ref().as_str()
+            );
+            assert!(record["record"].get("args").is_none());
+        }
+    }
+
+    #[test]
+    fn test_indexed_views_return_versioned_records() {
+        let mut factory = setup_factory();
+        let mut args = token_args(accounts(1), "ABC");
+        args.tags = vec!["defi".to_string()];
+        args.campaign = Some("launch".to_string());
+        seed_token(&mut factory, args);
+        let mut args = token_args(accounts(1), "XYZ");
+        args.tags = vec!["defi".to_string()];
+        args.campaign = Some("launch".to_string());
+        seed_token(&mut factory, args);
+
+        assert_versioned(
+            factory.get_tokens_by_owner(accounts(1), 0, None),
+            &["ABC", "XYZ"],
+        );
+        assert_versioned(
+            factory.get_tokens_by_tag("defi".to_string(), 0, None),
+            &["ABC", "XYZ"],
+        );
+        assert_versioned(
+            factory.get_tokens_by_campaign("launch".to_string(), 1, None),
+            &["XYZ"],
+        );
+        assert!(factory
+            .get_tokens_by_tag("nft".to_string(), 0, None)
+            .is_empty());
+
+        // The whole record as the UIs and indexers parse it, so a renamed or dropped field shows.
+        let expected = concat!(
+            r#"[{"version":1,"record":{"owner_id":"bob","total_supply":"1000000","#,
+            r#""metadata":{"spec":"ft-1.0.0","name":"XYZ Token","symbol":"XYZ","icon":null,"#,
+            r#""reference":null,"reference_hash":null,"decimals":18},"tags":["defi"],"#,
+            r#""campaign":"launch","creator_id":"alice","frozen":false,"creation_stats":null,"#,
+            r#""last_seen_ok":null,"unresponsive":false,"full_access_key":null,"#,
+            r#""detached_account_id":null,"fee_on_transfer":false,"created_at":"0"}}]"#,
+        );
+        assert_eq!(
+            serde_json::to_string(&factory.get_tokens_by_campaign("launch".to_string(), 1, None))
+                .unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_pages_of_worst_case_records_fit_a_view_call() {
+        let mut factory = setup_factory();
+        for index in 0..MAX_PAGE_SIZE {
+            // Writing all the records at once takes more gas than a call has.
+            testing_env!(context(accounts(0)).build());
+            seed_token(&mut factory, worst_case_args(&format!("T{}", index)));
+        }
+        let token = factory.tokens.get(&"t0".to_string()).unwrap();
+        let record_bytes = token.try_to_vec().unwrap().len() as u64;
+        // A full page of these records is what the byte budget guards against.
+        assert!(record_bytes * MAX_PAGE_SIZE > MAX_PAGE_BYTES);
+        let records_per_page = std::cmp::max(1, MAX_PAGE_BYTES / record_bytes);
+
+        // Paging by the number of returned records visits every token once.
+        let mut from_index = 0;
+        loop {
+            view_context();
+            let page = factory.get_tokens(from_index, Some(MAX_PAGE_SIZE), None);
+            if page.is_empty() {
+                break;
+            }
+            assert!(env::used_gas() < VIEW_GAS);
+            assert_eq!(
+                page.len() as u64,
+                std::cmp::min(records_per_page, MAX_PAGE_SIZE - from_index)
+            );
+            from_index += page.len() as u64;
+        }
+        assert_eq!(from_index, MAX_PAGE_SIZE);
+
+        view_context();
+        let page = factory.get_tokens_by_owner(accounts(1), 0, Some(MAX_PAGE_SIZE));
+        assert_eq!(page.len() as u64, records_per_page);
+        assert!(env::used_gas() < VIEW_GAS);
+    }
+
+    #[test]
+    fn test_page_of_small_records_is_full() {
+        let mut factory = setup_factory();
+        for index in 0..MAX_PAGE_SIZE + 1 {
+            seed_token(
+                &mut factory,
+                token_args(accounts(1), &format!("T{}", index)),
+            );
+        }
+        view_context();
+        let page = factory.get_tokens(0, Some(u64::MAX), None);
+        assert_eq!(page.len() as u64, MAX_PAGE_SIZE);
+        assert!(env::used_gas() < VIEW_GAS);
+        let page = factory.get_tokens_by_owner(accounts(1), 1, Some(u64::MAX));
+        assert_eq!(page.len() as u64, MAX_PAGE_SIZE);
+    }
+
+    #[test]
+    #[should_panic(expected = "request fewer at once")]
+    fn test_tokens_by_ids_past_the_page_bytes() {
+        let mut factory = setup_factory();
+        let token_ids = vec![
+            seed_token(&mut factory, worst_case_args("ABC")),
+            seed_token(&mut factory, worst_case_args("XYZ")),
+        ];
+        view_context();
+        factory.get_tokens_by_ids(token_ids);
+    }
+}
@@ -0,0 +1,168 @@
+//! A contract, e.g. a DAO, creating a token through the factory in a cross-contract call, with
+//! the deposit attached to its own call.
+use near_workspaces::network::Sandbox;
+use near_workspaces::types::NearToken;
+use near_workspaces::{Account, Contract, Worker};
+use serde_json::{json, Value};
+
+const FACTORY_WASM: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../factory/res/token_factory.wasm"
+);
+const CALLER_WASM: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../test-caller/res/test_caller.wasm"
+);
+const CREATION_DEPOSIT: NearToken = NearToken::from_near(10);
+
+struct Setup {
+    worker: Worker<Sandbox>,
+    factory: Contract,
+    caller: Contract,
+    alice: Account,
+}
+
+fn read_wasm(path: &str) -> Vec<u8> {
+    std::fs::read(path).unwrap_or_else(|_| panic!("{path} not found, run ../build.sh first"))
+}
+
+/// Factory at `factory.test.near` and the calling contract at `dao.test.near`, with `alice`
+/// signing the calls to the contract.
+async fn setup() -> anyhow::Result<Setup> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let factory = root
+        .create_subaccount("factory")
+        .initial_balance(NearToken::from_near(50))
+        .transact()
+        .await?
+        .into_result()?
+        .deploy(&read_wasm(FACTORY_WASM))
+        .await?
+        .into_result()?;
+    factory
+        .call("new")
+        .args_json(json!({ "owner_id": factory.id() }))
+        .transact()
+        .await?
+        .into_result()?;
+    let caller = root
+        .create_subaccount("dao")
+        .initial_balance(NearToken::from_near(20))
+        .transact()
+        .await?
+        .into_result()?
+        .deploy(&read_wasm(CALLER_WASM))
+        .await?
+        .into_result()?;
+    let alice = root
+        .create_subaccount("alice")
+        .initial_balance(NearToken::from_near(20))
+        .transact()
+        .await?
+        .into_result()?;
+    Ok(Setup {
+        worker,
+        factory,
+        caller,
+        alice,
+    })
+}
+
+/// Has `alice` call the contract, which creates the `ABC` token owned by her, and returns what
+/// the promise chain resolved to.
+async fn create_token(setup: &Setup) -> anyhow::Result<bool> {
+    let args = json!({
+        "args": {
+            "owner_id": setup.alice.id(),
+            "total_supply": "1000000",
+            "metadata": {
+                "spec": "ft-1.0.0",
+                "name": "ABC Token",
+                "symbol": "ABC",
+                "decimals": 18,
+            },
+        },
+    });
+    let result = setup
+        .alice
+        .call(setup.caller.id(), "create_token")
+        .args_json(json!({ "factory_id": setup.factory.id(), "args": args.to_string() }))
+        .deposit(CREATION_DEPOSIT)
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    Ok(result.json()?)
+}
+
+async fn storage_balance_total(setup: &Setup, account: &Account) -> anyhow::Result<Option<u128>> {
+    let balance: Option<Value> = setup
+        .factory
+        .view("storage_balance_of")
+        .args_json(json!({ "account_id": account.id() }))
+        .await?
+        .json()?;
+    Ok(balance.map(|balance| balance["total"].as_str().unwrap().parse().unwrap()))
+}
+
+#[tokio::test]
+async fn test_contract_caller_creates_a_token() -> anyhow::Result<()> {
+    let setup = setup().await?;
+    assert!(create_token(&setup).await?);
+
+    let token: Value = setup
+        .factory
+        .view("get_token")
+        .args_json(json!({ "token_id": "abc" }))
+        .await?
+        .json()?;
+    // The contract is the creator and payer, not the account signing the transaction.
+    assert_eq!(token["record"]["creator_id"], setup.caller.id().as_str());
+    let token_account_id = format!("abc.{}", setup.factory.id());
+    let supply: String = setup
+        .worker
+        .view(&token_account_id.parse()?, "ft_balance_of")
+        .args_json(json!({ "account_id": setup.alice.id() }))
+        .await?
+        .json()?;
+    assert_eq!(supply, "1000000");
+    assert!(storage_balance_total(&setup, setup.caller.as_account())
+        .await?
+        .is_some());
+    assert_eq!(storage_balance_total(&setup, &setup.alice).await?, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_failed_creation_refunds_the_contract_caller() -> anyhow::Result<()> {
+    let setup = setup().await?;
+    // The token account already exists, so creating it fails after the factory took the escrow.
+    setup
+        .factory
+        .as_account()
+        .create_subaccount("abc")
+        .initial_balance(NearToken::from_near(1))
+        .transact()
+        .await?
+        .into_result()?;
+    assert!(!create_token(&setup).await?);
+
+    let token: Option<Value> = setup
+        .factory
+        .view("get_token")
+        .args_json(json!({ "token_id": "abc" }))
+        .await?
+        .json()?;
+    assert!(token.is_none());
+    // The escrow went back to the storage balance of the contract that paid it, leaving it with
+    // about the whole deposit. The signer of the transaction isn't registered.
+    let total = storage_balance_total(&setup, setup.caller.as_account())
+        .await?
+        .unwrap();
+    let deposit = CREATION_DEPOSIT.as_yoctonear();
+    assert!(total <= deposit);
+    assert!(deposit - total < NearToken::from_millinear(100).as_yoctonear());
+    assert_eq!(storage_balance_total(&setup, &setup.alice).await?, None);
+    Ok(())
+}
@@ -0,0 +1,197 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::{
+    env, ext_contract, near_bindgen, AccountId, Gas, PanicOnDefault, Promise, PromiseOrValue,
+    PromiseResult,
+};
+
+near_sdk::setup_alloc!();
+
+/// Gas for each of the callbacks of `create_token_account`, which the factory attaches on top
+/// of the gas of the call.
+const CALLBACK_GAS: Gas = 5_000_000_000_000;
+
+#[ext_contract(ext_self)]
+trait ExtSelf {
+    fn on_token_account_created(&mut self, deposit: U128) -> PromiseOrValue<bool>;
+    fn return_deposit(&mut self, deposit: U128) -> bool;
+}
+
+fn is_promise_success() -> bool {
+    assert_eq!(
+        env::promise_results_count(),
+        1,
+        "Contract expected a result on the callback"
+    );
+    matches!(env::promise_result(0), PromiseResult::Successful(_))
+}
+
+/// Namespace account of a project, e.g. `project.factory`. An account can only create its own
+/// sub-accounts, so the factory deploys this contract to create the tokens of the project.
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct TokenNamespace {
+    factory_id: AccountId,
+}
+
+#[near_bindgen]
+impl TokenNamespace {
+    /// Initializes the namespace with the predecessor as its factory.
+    #[init]
+    pub fn new() -> Self {
+        Self {
+            factory_id: env::predecessor_account_id(),
+        }
+    }
+
+    /// Initializes the namespace for `factory_id`, for namespace accounts the factory doesn't
+    /// create itself, e.g. a top level account whose key holder deploys this contract.
+    #[init]
+    pub fn new_with_factory(factory_id: AccountId) -> Self {
+        assert!(
+            env::is_valid_account_id(factory_id.as_bytes()),
+            "Factory account id is invalid"
+        );
+        Self { factory_id }
+    }
+
+    /// Creates the `name` sub-account with the attached deposit, deploys the given code to it
+    /// and calls its `new` method with `init_args`, deleting the `public_key` again afterwards
+    /// if `delete_key_after_init` is set. Can only be called by the factory. The arguments are
+    /// Borsh serialized, so the code doesn't have to be encoded.
+    ///
+    /// Resolves to whether the token account was created. The deposit of a failed creation is
+    /// refunded to the namespace, which returns it to the factory.
+    #[payable]
+    pub fn create_token_account(
+        &mut self,
+        #[serializer(borsh)] name: String,
+        #[serializer(borsh)] public_key: Option<Vec<u8>>,
+        #[serializer(borsh)] code: Vec<u8>,
+        #[serializer(borsh)] init_args: Vec<u8>,
+        #[serializer(borsh)] init_gas: Gas,
+        #[serializer(borsh)] delete_key_after_init: bool,
+    ) -> Promise {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.factory_id,
+            "Only the factory can create token accounts"
+        );
+        let mut promise = Promise::new(format!("{}.{}", name, env::current_account_id()))
+            .create_account()
+            .transfer(env::attached_deposit());
+        if let Some(public_key) = public_key.clone() {
+            promise = promise.add_full_access_key(public_key);
+        }
+        promise =
+            promise
+                .deploy_contract(code)
+                .function_call(b"new".to_vec(), init_args, 0, init_gas);
+        if let (Some(public_key), true) = (public_key, delete_key_after_init) {
+            promise = promise.delete_key(public_key);
+        }
+        promise.then(ext_self::on_token_account_created(
+            env::attached_deposit().into(),
+            &env::current_account_id(),
+            0,
+            2 * CALLBACK_GAS,
+        ))
+    }
+
+    /// Resolves `create_token_account`. The refund of a failed creation may arrive after this
+    /// callback, so the deposit is returned to the factory by `return_deposit` in a later
+    /// receipt.
+    #[private]
+    pub fn on_token_account_created(&mut self, deposit: U128) -> PromiseOrValue<bool> {
+        if is_promise_success() {
+            return PromiseOrValue::Value(true);
+        }
+        ext_self::return_deposit(deposit, &env::current_account_id(), 0, CALLBACK_GAS).into()
+    }
+
+    #[private]
+    pub fn return_deposit(&mut self, deposit: U128) -> bool {
+        Promise::new(self.factory_id.clone()).transfer(deposit.0);
+        false
+    }
+
+    pub fn get_factory_id(&self) -> AccountId {
+        self.factory_id.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{get_created_receipts, VMContextBuilder};
+    use near_sdk::{serde_json, testing_env, MockedBlockchain};
+    use std::convert::TryInto;
+
+    const DEPOSIT: u128 = 1_000_000_000_000_000_000_000_000;
+
+    /// Starts a callback of the namespace with the given promise result, on empty storage since
+    /// `testing_env!` carries over the storage of the previous test on the thread.
+    fn setup_callback(result: PromiseResult) -> TokenNamespace {
+        env::take_blockchain_interface();
+        let mut context = VMContextBuilder::new();
+        context
+            .current_account_id("proj.factory".to_string().try_into().unwrap())
+            .predecessor_account_id("proj.factory".to_string().try_into().unwrap());
+        testing_env!(
+            context.build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![result]
+        );
+        TokenNamespace {
+            factory_id: "factory".to_string(),
+        }
+    }
+
+    /// The receipts created so far, in their JSON form, since the fields of the mocked receipts
+    /// aren't public. They go through a string because `serde_json::Value` can't hold the u128
+    /// deposits.
+    fn created_receipts() -> Vec<serde_json::Value> {
+        get_created_receipts()
+            .iter()
+            .map(|receipt| serde_json::from_str(&serde_json::to_string(receipt).unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_created_token_account_resolves_to_true() {
+        let mut namespace = setup_callback(PromiseResult::Successful(vec![]));
+        assert!(matches!(
+            namespace.on_token_account_created(DEPOSIT.into()),
+            PromiseOrValue::Value(true)
+        ));
+        assert!(created_receipts().is_empty());
+    }
+
+    #[test]
+    fn test_failed_creation_returns_the_deposit_to_the_factory() {
+        let mut namespace = setup_callback(PromiseResult::Failed);
+        let result = namespace.on_token_account_created(DEPOSIT.into());
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        drop(result);
+        let receipts = created_receipts();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0]["receiver_id"], "proj.factory");
+        assert_eq!(
+            receipts[0]["actions"][0]["FunctionCall"]["method_name"],
+            "return_deposit"
+        );
+
+        let mut namespace = setup_callback(PromiseResult::Successful(vec![]));
+        assert!(!namespace.return_deposit(DEPOSIT.into()));
+        let receipts = created_receipts();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0]["receiver_id"], "factory");
+        // The JSON numbers can't hold a u128 exactly.
+        assert_eq!(
+            receipts[0]["actions"][0]["Transfer"]["deposit"].as_f64(),
+            Some(DEPOSIT as f64)
+        );
+    }
+}
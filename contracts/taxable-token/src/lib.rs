@@ -0,0 +1,304 @@
+use factory_types::{MAX_BPS, MAX_TRANSFER_FEE_BPS};
+use near_contract_standards::fungible_token::core::FungibleTokenCore;
+use near_contract_standards::fungible_token::metadata::{
+    FungibleTokenMetadata, FungibleTokenMetadataProvider,
+};
+use near_contract_standards::fungible_token::resolver::FungibleTokenResolver;
+use near_contract_standards::fungible_token::FungibleToken;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LazyOption;
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{
+    env, near_bindgen, AccountId, Balance, BorshStorageKey, PanicOnDefault, PromiseOrValue,
+};
+
+near_sdk::setup_alloc!();
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct Contract {
+    token: FungibleToken,
+    metadata: LazyOption<FungibleTokenMetadata>,
+    transfer_fee_bps: u16,
+    fee_sink_id: AccountId,
+}
+
+#[derive(BorshSerialize, BorshStorageKey)]
+enum StorageKey {
+    Token,
+    Metadata,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TransferFee {
+    pub transfer_fee_bps: u16,
+    pub fee_sink_id: AccountId,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Initializes the token like the default fungible token. Every transfer additionally sends
+    /// `transfer_fee_bps` basis points of the amount to `fee_sink_id`, except transfers from or
+    /// to the fee sink itself.
+    #[init]
+    pub fn new(
+        owner_id: ValidAccountId,
+        total_supply: U128,
+        metadata: FungibleTokenMetadata,
+        transfer_fee_bps: u16,
+        fee_sink_id: ValidAccountId,
+    ) -> Self {
+        // The factory may deploy the code ahead of the init, so no other account can initialize
+        // the token before the factory or the namespace creating it does.
+        let predecessor_id = env::predecessor_account_id();
+        let current_account_id = env::current_account_id();
+        assert!(
+            predecessor_id == current_account_id
+                || current_account_id
+                    .strip_suffix(&format!(".{}", predecessor_id))
+                    .map_or(false, |name| !name.contains('.')),
+            "Only the account or its parent can initialize the token"
+        );
+        metadata.assert_valid();
+        assert!(
+            transfer_fee_bps <= MAX_TRANSFER_FEE_BPS,
+            "Transfer fee can't exceed {} basis points",
+            MAX_TRANSFER_FEE_BPS
+        );
+        let mut this = Self {
+            token: FungibleToken::new(StorageKey::Token),
+            metadata: LazyOption::new(StorageKey::Metadata, Some(&metadata)),
+            transfer_fee_bps,
+            fee_sink_id: fee_sink_id.into(),
+        };
+        this.token.internal_register_account(owner_id.as_ref());
+        this.token
+            .internal_deposit(owner_id.as_ref(), total_supply.into());
+        if this.token.accounts.get(&this.fee_sink_id).is_none() {
+            let fee_sink_id = this.fee_sink_id.clone();
+            this.token.internal_register_account(&fee_sink_id);
+        }
+        this
+    }
+
+    pub fn get_transfer_fee(&self) -> TransferFee {
+        TransferFee {
+            transfer_fee_bps: self.transfer_fee_bps,
+            fee_sink_id: self.fee_sink_id.clone(),
+        }
+    }
+
+    /// Moves the fee of a transfer of `amount` from the sender to the fee sink and returns the
+    /// amount left for the receiver.
+    fn internal_take_fee(&mut self, receiver_id: &str, amount: Balance) -> Balance {
+        let sender_id = env::predecessor_account_id();
+        if sender_id == self.fee_sink_id || receiver_id == self.fee_sink_id {
+            return amount;
+        }
+        let fee = amount / Balance::from(MAX_BPS) * Balance::from(self.transfer_fee_bps)
+            + amount % Balance::from(MAX_BPS) * Balance::from(self.transfer_fee_bps)
+                / Balance::from(MAX_BPS);
+        if fee > 0 {
+            let fee_sink_id = self.fee_sink_id.clone();
+            self.token.internal_transfer(
+                &sender_id,
+                &fee_sink_id,
+                fee,
+                Some("transfer fee".to_string()),
+            );
+        }
+        amount - fee
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenCore for Contract {
+    #[payable]
+    fn ft_transfer(&mut self, receiver_id: ValidAccountId, amount: U128, memo: Option<String>) {
+        let amount = self.internal_take_fee(receiver_id.as_ref(), amount.into());
+        self.token.ft_transfer(receiver_id, amount.into(), memo)
+    }
+
+    /// The fee is taken upfront, so the receiver is called with the amount after the fee and a
+    /// refund only returns what the receiver didn't use of it.
+    #[payable]
+    fn ft_transfer_call(
+        &mut self,
+        receiver_id: ValidAccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        let amount = self.internal_take_fee(receiver_id.as_ref(), amount.into());
+        self.token
+            .ft_transfer_call(receiver_id, amount.into(), memo, msg)
+    }
+
+    fn ft_total_supply(&self) -> U128 {
+        self.token.ft_total_supply()
+    }
+
+    fn ft_balance_of(&self, account_id: ValidAccountId) -> U128 {
+        self.token.ft_balance_of(account_id)
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenResolver for Contract {
+    #[private]
+    fn ft_resolve_transfer(
+        &mut self,
+        sender_id: ValidAccountId,
+        receiver_id: ValidAccountId,
+        amount: U128,
+    ) -> U128 {
+        let sender_id: AccountId = sender_id.into();
+        let (used_amount, _) =
+            self.token
+                .internal_ft_resolve_transfer(&sender_id, receiver_id, amount);
+        used_amount.into()
+    }
+}
+
+near_contract_standards::impl_fungible_token_storage!(Contract, token);
+
+#[near_bindgen]
+impl FungibleTokenMetadataProvider for Contract {
+    fn ft_metadata(&self) -> FungibleTokenMetadata {
+        self.metadata.get().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_contract_standards::fungible_token::metadata::FT_METADATA_SPEC;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain};
+    use std::convert::TryInto;
+
+    const TOTAL_SUPPLY: Balance = 1_000_000;
+    /// 2.5% of every transfer.
+    const TRANSFER_FEE_BPS: u16 = 250;
+
+    /// Starts a call to the token from `predecessor_id`, on empty storage since `testing_env!`
+    /// carries over the storage of the previous test on the thread.
+    fn setup(predecessor_id: &str) {
+        env::take_blockchain_interface();
+        call(predecessor_id);
+    }
+
+    /// Continues with a call from `predecessor_id` on the same storage, with the yoctoNEAR the
+    /// transfers need attached.
+    fn call(predecessor_id: &str) {
+        let mut context = VMContextBuilder::new();
+        context
+            .current_account_id("token.factory".to_string().try_into().unwrap())
+            .predecessor_account_id(predecessor_id.to_string().try_into().unwrap())
+            .attached_deposit(1);
+        testing_env!(context.build());
+    }
+
+    fn metadata() -> FungibleTokenMetadata {
+        FungibleTokenMetadata {
+            spec: FT_METADATA_SPEC.to_string(),
+            name: "Token".to_string(),
+            symbol: "TKN".to_string(),
+            icon: None,
+            reference: None,
+            reference_hash: None,
+            decimals: 18,
+        }
+    }
+
+    /// Initializes the token with the supply owned by `accounts(1)` and the fees going to
+    /// `accounts(3)`, and registers `accounts(2)`.
+    fn setup_token(transfer_fee_bps: u16) -> Contract {
+        setup("factory");
+        let mut contract = Contract::new(
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            metadata(),
+            transfer_fee_bps,
+            accounts(3),
+        );
+        contract
+            .token
+            .internal_register_account(accounts(2).as_ref());
+        contract
+    }
+
+    #[test]
+    fn test_transfer_sends_the_fee_to_the_sink() {
+        let mut contract = setup_token(TRANSFER_FEE_BPS);
+        call(accounts(1).as_ref());
+        contract.ft_transfer(accounts(2), 10_000.into(), None);
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, TOTAL_SUPPLY - 10_000);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 9_750);
+        assert_eq!(contract.ft_balance_of(accounts(3)).0, 250);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+    }
+
+    #[test]
+    fn test_fee_rounds_down() {
+        let mut contract = setup_token(TRANSFER_FEE_BPS);
+        call(accounts(1).as_ref());
+        // 2.5% of 39 is 0.975.
+        contract.ft_transfer(accounts(2), 39.into(), None);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 39);
+        assert_eq!(contract.ft_balance_of(accounts(3)).0, 0);
+    }
+
+    #[test]
+    fn test_transfers_of_the_sink_are_free() {
+        let mut contract = setup_token(TRANSFER_FEE_BPS);
+        call(accounts(1).as_ref());
+        contract.ft_transfer(accounts(3), 10_000.into(), None);
+        assert_eq!(contract.ft_balance_of(accounts(3)).0, 10_000);
+
+        call(accounts(3).as_ref());
+        contract.ft_transfer(accounts(2), 4_000.into(), None);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 4_000);
+        assert_eq!(contract.ft_balance_of(accounts(3)).0, 6_000);
+    }
+
+    #[test]
+    fn test_new_registers_the_fee_sink() {
+        let contract = setup_token(TRANSFER_FEE_BPS);
+        assert!(contract.token.accounts.get(accounts(3).as_ref()).is_some());
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, TOTAL_SUPPLY);
+        let transfer_fee = contract.get_transfer_fee();
+        assert_eq!(transfer_fee.transfer_fee_bps, TRANSFER_FEE_BPS);
+        assert_eq!(transfer_fee.fee_sink_id, accounts(3).as_ref().clone());
+    }
+
+    #[test]
+    fn test_new_with_the_maximum_fee() {
+        let contract = setup_token(MAX_TRANSFER_FEE_BPS);
+        assert_eq!(
+            contract.get_transfer_fee().transfer_fee_bps,
+            MAX_TRANSFER_FEE_BPS
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Transfer fee can't exceed 1000 basis points")]
+    fn test_new_with_too_large_fee() {
+        setup_token(MAX_TRANSFER_FEE_BPS + 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the account or its parent can initialize the token")]
+    fn test_new_by_another_account() {
+        setup("attacker");
+        Contract::new(
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            metadata(),
+            TRANSFER_FEE_BPS,
+            accounts(3),
+        );
+    }
+}
@@ -0,0 +1,55 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::{env, near_bindgen, Gas, Promise};
+
+near_sdk::setup_alloc!();
+
+/// Gas the caller keeps for its own call, the rest goes to the factory.
+const CALLER_GAS: Gas = 10_000_000_000_000;
+
+/// Contract creating tokens through the factory like a DAO would, for the integration tests.
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct TestCaller {}
+
+#[near_bindgen]
+impl TestCaller {
+    /// Calls `create_token` of `factory_id` with the JSON `args` and the attached deposit. The
+    /// returned promise resolves to the result of the factory.
+    #[payable]
+    pub fn create_token(&mut self, factory_id: ValidAccountId, args: String) -> Promise {
+        Promise::new(factory_id.into()).function_call(
+            b"create_token".to_vec(),
+            args.into_bytes(),
+            env::attached_deposit(),
+            env::prepaid_gas() - env::used_gas() - CALLER_GAS,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    const DEPOSIT: u128 = 10_000_000_000_000_000_000_000_000;
+
+    #[test]
+    fn test_create_token_forwards_the_deposit_to_the_factory() {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(DEPOSIT)
+            .build());
+        drop(TestCaller::default().create_token(accounts(2), r#"{"args":{}}"#.to_string()));
+        let receipts = get_created_receipts();
+        assert_eq!(receipts.len(), 1);
+        // The fields of the mocked receipts aren't public, and u128 deposits don't fit into a
+        // `serde_json::Value`.
+        let receipt = near_sdk::serde_json::to_string(&receipts[0]).unwrap();
+        assert!(receipt.contains(&format!(r#""receiver_id":"{}""#, accounts(2))));
+        assert!(receipt.contains(r#""method_name":"create_token""#));
+        assert!(receipt.contains(&format!(r#""deposit":{}"#, DEPOSIT)));
+    }
+}
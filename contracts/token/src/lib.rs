@@ -1,11 +1,12 @@
+use factory_types::TokenInitArgs;
 use near_contract_standards::fungible_token::metadata::{
     FungibleTokenMetadata, FungibleTokenMetadataProvider,
 };
 use near_contract_standards::fungible_token::FungibleToken;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::collections::LazyOption;
-use near_sdk::json_types::{ValidAccountId, U128};
-use near_sdk::{env, BorshStorageKey, near_bindgen, AccountId, PanicOnDefault, PromiseOrValue};
+use near_sdk::json_types::{Base64VecU8, ValidAccountId, U128};
+use near_sdk::{env, BorshStorageKey, near_bindgen, AccountId, Balance, PanicOnDefault, PromiseOrValue};
 
 near_sdk::setup_alloc!();
 
@@ -14,6 +15,9 @@ near_sdk::setup_alloc!();
 pub struct Contract {
     token: FungibleToken,
     metadata: LazyOption<FungibleTokenMetadata>,
+    /// Factory that created the token, which may be another account than the parent if the
+    /// token was created by a namespace.
+    factory_id: AccountId,
 }
 
 #[derive(BorshSerialize, BorshStorageKey)]
@@ -25,22 +29,74 @@ enum StorageKey {
 #[near_bindgen]
 impl Contract {
     /// Initializes the contract with the given total supply owned by the given `owner_id` with
-    /// the given fungible token metadata.
+    /// the given fungible token metadata. If `treasury_id` is given, `treasury_split_bps` basis
+    /// points of the supply are minted to the treasury instead. The factory passes its account
+    /// as `factory_id`, otherwise the initializing account counts as the factory.
     #[init]
     pub fn new(
         owner_id: ValidAccountId,
         total_supply: U128,
         metadata: FungibleTokenMetadata,
+        treasury_id: Option<ValidAccountId>,
+        treasury_split_bps: Option<u16>,
+        factory_id: Option<ValidAccountId>,
     ) -> Self {
-        metadata.assert_valid();
+        // The factory may deploy the code ahead of the init, so no other account can initialize
+        // the token before the factory or the namespace creating it does.
+        let predecessor_id = env::predecessor_account_id();
+        let current_account_id = env::current_account_id();
+        assert!(
+            predecessor_id == current_account_id
+                || current_account_id
+                    .strip_suffix(&format!(".{}", predecessor_id))
+                    .map_or(false, |name| !name.contains('.')),
+            "Only the account or its parent can initialize the token"
+        );
+        let args = TokenInitArgs {
+            owner_id,
+            total_supply,
+            metadata,
+            treasury_id,
+            treasury_split_bps,
+        };
+        args.metadata.assert_valid();
         let mut this = Self {
             token: FungibleToken::new(StorageKey::Token),
-            metadata: LazyOption::new(StorageKey::Metadata, Some(&metadata)),
+            metadata: LazyOption::new(StorageKey::Metadata, Some(&args.metadata)),
+            factory_id: factory_id.map_or(predecessor_id, Into::into),
         };
+        let total_supply: Balance = args.total_supply.into();
+        let treasury_supply = args.treasury_supply();
+        let owner_id = args.owner_id;
         this.token.internal_register_account(owner_id.as_ref());
-        this.token.internal_deposit(owner_id.as_ref(), total_supply.into());
+        this.token.internal_deposit(owner_id.as_ref(), total_supply - treasury_supply);
+        if let Some(treasury_id) = args.treasury_id {
+            if this.token.accounts.get(treasury_id.as_ref()).is_none() {
+                this.token.internal_register_account(treasury_id.as_ref());
+            }
+            this.token.internal_deposit(treasury_id.as_ref(), treasury_supply);
+        }
         this
     }
+
+    /// Replaces the reference of the metadata. Only the factory that created the token can
+    /// call it, after the creator's refresh passed the factory's timelock.
+    pub fn update_reference(
+        &mut self,
+        reference: Option<String>,
+        reference_hash: Option<Base64VecU8>,
+    ) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.factory_id,
+            "Only the factory can update the reference"
+        );
+        let mut metadata = self.metadata.get().unwrap();
+        metadata.reference = reference;
+        metadata.reference_hash = reference_hash;
+        metadata.assert_valid();
+        self.metadata.set(&metadata);
+    }
 }
 
 near_contract_standards::impl_fungible_token_core!(Contract, token);
@@ -52,3 +108,157 @@ impl FungibleTokenMetadataProvider for Contract {
         self.metadata.get().unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_contract_standards::fungible_token::core::FungibleTokenCore;
+    use near_contract_standards::fungible_token::metadata::FT_METADATA_SPEC;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain};
+    use std::convert::TryInto;
+
+    const TOTAL_SUPPLY: Balance = 1_000_000;
+
+    /// Starts a call to the token from `predecessor_id`, on empty storage since `testing_env!`
+    /// carries over the storage of the previous test on the thread.
+    fn setup(predecessor_id: &str) {
+        env::take_blockchain_interface();
+        call("token.factory", predecessor_id);
+    }
+
+    /// Continues with a call to `account_id` from `predecessor_id` on the same storage.
+    fn call(account_id: &str, predecessor_id: &str) {
+        let mut context = VMContextBuilder::new();
+        context
+            .current_account_id(account_id.to_string().try_into().unwrap())
+            .predecessor_account_id(predecessor_id.to_string().try_into().unwrap())
+            .attached_deposit(0);
+        testing_env!(context.build());
+    }
+
+    fn metadata() -> FungibleTokenMetadata {
+        FungibleTokenMetadata {
+            spec: FT_METADATA_SPEC.to_string(),
+            name: "Token".to_string(),
+            symbol: "TKN".to_string(),
+            icon: None,
+            reference: None,
+            reference_hash: None,
+            decimals: 18,
+        }
+    }
+
+    #[test]
+    fn test_new_mints_the_treasury_split() {
+        setup("factory");
+        let contract = Contract::new(
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            metadata(),
+            Some(accounts(2)),
+            Some(2_500),
+            None,
+        );
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, 750_000);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 250_000);
+    }
+
+    #[test]
+    fn test_new_without_treasury() {
+        setup("factory");
+        let contract = Contract::new(
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            metadata(),
+            None,
+            None,
+            None,
+        );
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, TOTAL_SUPPLY);
+    }
+
+    #[test]
+    #[should_panic(expected = "Treasury split")]
+    fn test_new_with_incomplete_treasury_split() {
+        setup("factory");
+        Contract::new(
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            metadata(),
+            Some(accounts(2)),
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the account or its parent can initialize the token")]
+    fn test_new_by_another_account() {
+        setup("attacker");
+        Contract::new(
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            metadata(),
+            None,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the account or its parent can initialize the token")]
+    fn test_new_by_an_ancestor_other_than_the_parent() {
+        env::take_blockchain_interface();
+        call("token.project.factory", "factory");
+        Contract::new(
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            metadata(),
+            None,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    fn test_factory_updates_the_reference_of_a_token_of_its_namespace() {
+        // The namespace creating the token isn't under the factory.
+        env::take_blockchain_interface();
+        call("token.namespace", "namespace");
+        let mut contract = Contract::new(
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            metadata(),
+            None,
+            None,
+            Some("factory".to_string().try_into().unwrap()),
+        );
+        call("token.namespace", "factory");
+        contract.update_reference(
+            Some("https://example.com/token.json".to_string()),
+            Some(vec![0; 32].into()),
+        );
+        assert_eq!(
+            contract.ft_metadata().reference.as_deref(),
+            Some("https://example.com/token.json")
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the factory can update the reference")]
+    fn test_namespace_cant_update_the_reference() {
+        env::take_blockchain_interface();
+        call("token.namespace", "namespace");
+        let mut contract = Contract::new(
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            metadata(),
+            None,
+            None,
+            Some("factory".to_string().try_into().unwrap()),
+        );
+        contract.update_reference(None, None);
+    }
+}
@@ -159,12 +159,11 @@ class App extends React.Component {
 
   async computeRequiredDeposit(args) {
     args = args || this.constructArgs();
-    return Big(
-      await this._contract.get_required_deposit({
-        args,
-        account_id: this._accountId,
-      })
-    );
+    const deposit = await this._contract.get_required_deposit({
+      args,
+      account_id: this._accountId,
+    });
+    return Big(deposit.total);
   }
 
   async _initNear() {
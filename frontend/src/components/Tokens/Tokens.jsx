@@ -338,7 +338,7 @@ class Tokens extends React.Component {
     const limit = 5;
     for (let i = tokens.length; i < numTokens; i += limit) {
       const newTokens = await contract.get_tokens({ from_index: i, limit });
-      tokens.push(...newTokens);
+      tokens.push(...newTokens.map((token) => token.record));
       ls.set(this.props.lsKeyCachedTokens, tokens);
       this.updateTokens();
     }
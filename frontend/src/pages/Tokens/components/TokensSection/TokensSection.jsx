@@ -334,7 +334,7 @@ class TokensSection extends React.Component {
     const limit = 5;
     for (let i = tokens.length; i < numTokens; i += limit) {
       const newTokens = await contract.get_tokens({ from_index: i, limit });
-      tokens.push(...newTokens);
+      tokens.push(...newTokens.map((token) => token.record));
       ls.set(this.props.lsKeyCachedTokens, tokens);
       this.updateTokens();
     }